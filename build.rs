@@ -12,39 +12,288 @@ fn main() {
 
     let mut map_builder = ::phf_codegen::Map::new();
     let mut set_builder = ::phf_codegen::Set::new();
+    let mut reverse_map_builder = ::phf_codegen::Map::new();
     let mut check_set = std::collections::HashSet::new();
 
     let infile = Path::new("stationpedia.txt");
     let contents = fs::read_to_string(infile).unwrap();
 
     for line in contents.lines() {
-        let mut it = line.splitn(2, ' ');
-        let hash = it.next().unwrap();
-        let name = it.next().unwrap();
+        let (hash, name) = line.split_once(' ').unwrap();
         map_builder.entry(hash, &format!("\"{}\"", name));
 
         if !check_set.contains(name) {
             set_builder.entry(name);
+            reverse_map_builder.entry(name, &format!("{hash}i64"));
             check_set.insert(name);
         }
     }
 
+    let mut enum_builder = ::phf_codegen::Set::new();
+    let mut enum_lookup_builder = ::phf_codegen::Map::new();
+    let mut enum_docs_builder = ::phf_codegen::Map::new();
+
+    let infile = Path::new("enums.txt");
+    let contents = fs::read_to_string(infile).unwrap();
+
+    for line in contents.lines() {
+        let mut it = line.splitn(3, ' ');
+        let name = it.next().unwrap();
+        let value = it.next().unwrap();
+        let docs = it.next().unwrap_or("");
+
+        enum_builder.entry(name);
+        enum_lookup_builder.entry(name, &format!("{value}i64"));
+        enum_docs_builder.entry(name, &format!("{docs:?}"));
+    }
+
+    let mut constants_builder = ::phf_codegen::Map::new();
+    let mut constants_docs_builder = ::phf_codegen::Map::new();
+
+    let infile = Path::new("constants.txt");
+    let contents = fs::read_to_string(infile).unwrap();
+
+    for line in contents.lines() {
+        let mut it = line.splitn(3, ' ');
+        let name = it.next().unwrap();
+        let value = it.next().unwrap().parse::<f64>().unwrap();
+        let docs = it.next().unwrap_or("");
+
+        let value = if value.is_nan() {
+            "f64::NAN".to_string()
+        } else if value == f64::INFINITY {
+            "f64::INFINITY".to_string()
+        } else if value == f64::NEG_INFINITY {
+            "f64::NEG_INFINITY".to_string()
+        } else {
+            format!("{value:?}f64")
+        };
+
+        constants_builder.entry(name, &value);
+        constants_docs_builder.entry(name, &format!("{docs:?}"));
+    }
+
+    let mut examples_builder = ::phf_codegen::Map::new();
+
+    let infile = Path::new("instruction_examples.txt");
+    let contents = fs::read_to_string(infile).unwrap();
+
+    for line in contents
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        let (name, example) = line.split_once(' ').unwrap();
+        examples_builder.entry(name, &format!("{example:?}"));
+    }
+
+    let mut device_logic_types_builder = ::phf_codegen::Map::new();
+
+    let infile = Path::new("devicelogictypes.txt");
+    let contents = fs::read_to_string(infile).unwrap();
+
+    for line in contents
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        let mut it = line.split_whitespace();
+        let hash = it.next().unwrap();
+        let logic_types = it.map(|x| format!("{x:?}")).collect::<Vec<_>>();
+
+        device_logic_types_builder.entry(hash, &format!("&[{}]", logic_types.join(", ")));
+    }
+
+    let mut rulesets = fs::read_dir("rulesets")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    rulesets.sort();
+
+    let mut ruleset_builder = ::phf_codegen::Map::new();
+    let mut ruleset_sets = Vec::new();
+    let mut ruleset_signature_builder = ::phf_codegen::Map::new();
+    let mut ruleset_signature_maps = Vec::new();
+
+    for path in &rulesets {
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let const_name = format!("RULESET_{}", name.to_uppercase().replace('-', "_"));
+
+        let mut instruction_builder = ::phf_codegen::Set::new();
+        let contents = fs::read_to_string(path.join("instructions.txt")).unwrap();
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            instruction_builder.entry(line.to_string());
+        }
+
+        // Signatures that differ from the built-in table, in the format of a data directory
+        let mut signature_builder = ::phf_codegen::Map::new();
+        let infile = path.join("instructions_sig.txt");
+        let contents = fs::read_to_string(&infile).unwrap_or_default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut it = line.split_whitespace();
+            let instruction = it.next().unwrap();
+            let parameters = it
+                .map(|parameter| {
+                    let inner = parameter
+                        .strip_prefix('(')
+                        .and_then(|x| x.strip_suffix(')'))
+                        .unwrap_or(parameter);
+                    let types = inner
+                        .split('|')
+                        .map(|x| {
+                            let data_type = data_type(x).unwrap_or_else(|| {
+                                panic!(
+                                    "{}:{}: Unknown parameter type `{x}`",
+                                    infile.display(),
+                                    i + 1
+                                )
+                            });
+                            format!("DataType::{data_type}")
+                        })
+                        .collect::<Vec<_>>();
+                    format!("Union(&[{}])", types.join(", "))
+                })
+                .collect::<Vec<_>>();
+            signature_builder.entry(
+                instruction.to_string(),
+                &format!("InstructionSignature(&[{}])", parameters.join(", ")),
+            );
+        }
+
+        ruleset_builder.entry(name.clone(), &format!("&{const_name}"));
+        ruleset_signature_builder.entry(name, &format!("&{const_name}_SIGNATURES"));
+        ruleset_sets.push((const_name.clone(), instruction_builder));
+        ruleset_signature_maps.push((const_name, signature_builder));
+    }
+
     let output_file = File::create(dest_path).unwrap();
     let mut writer = BufWriter::new(&output_file);
 
-    write!(
+    writeln!(
         &mut writer,
-        "pub(crate) const HASH_NAME_LOOKUP: phf::Map<&'static str, &'static str> = {};\n",
+        "pub(crate) const HASH_NAME_LOOKUP: phf::Map<&'static str, &'static str> = {};",
         map_builder.build()
     )
     .unwrap();
 
-    write!(
+    writeln!(
         &mut writer,
-        "pub(crate) const HASH_NAMES: phf::Set<&'static str> = {};\n",
+        "pub(crate) const HASH_NAMES: phf::Set<&'static str> = {};",
         set_builder.build()
     )
     .unwrap();
 
+    writeln!(
+        &mut writer,
+        "pub(crate) const HASH_LOOKUP: phf::Map<&'static str, i64> = {};",
+        reverse_map_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const ENUMS: phf::Set<&'static str> = {};",
+        enum_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const ENUM_LOOKUP: phf::Map<&'static str, i64> = {};",
+        enum_lookup_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const ENUM_DOCS: phf::Map<&'static str, &'static str> = {};",
+        enum_docs_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "#[allow(clippy::approx_constant)]\npub(crate) const CONSTANTS: phf::Map<&'static str, f64> = {};",
+        constants_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const CONSTANTS_DOCS: phf::Map<&'static str, &'static str> = {};",
+        constants_docs_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const INSTRUCTION_EXAMPLES: phf::Map<&'static str, &'static str> = {};",
+        examples_builder.build()
+    )
+    .unwrap();
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const DEVICE_LOGIC_TYPES: phf::Map<&'static str, &[&'static str]> = {};",
+        device_logic_types_builder.build()
+    )
+    .unwrap();
+
+    for (const_name, instruction_builder) in &ruleset_sets {
+        writeln!(
+            &mut writer,
+            "const {const_name}: phf::Set<&'static str> = {};",
+            instruction_builder.build()
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const RULESETS: phf::Map<&'static str, &phf::Set<&'static str>> = {};",
+        ruleset_builder.build()
+    )
+    .unwrap();
+
+    for (const_name, signature_builder) in &ruleset_signature_maps {
+        writeln!(
+            &mut writer,
+            "const {const_name}_SIGNATURES: phf::Map<&'static str, InstructionSignature> = {};",
+            signature_builder.build()
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        &mut writer,
+        "pub(crate) const RULESET_SIGNATURES: phf::Map<&'static str, &phf::Map<&'static str, InstructionSignature>> = {};",
+        ruleset_signature_builder.build()
+    )
+    .unwrap();
+
     println!("cargo:rerun-if-changed=stationpedia.txt");
+    println!("cargo:rerun-if-changed=enums.txt");
+    println!("cargo:rerun-if-changed=constants.txt");
+    println!("cargo:rerun-if-changed=devicelogictypes.txt");
+    println!("cargo:rerun-if-changed=instruction_examples.txt");
+    println!("cargo:rerun-if-changed=rulesets");
+}
+
+/// The `DataType` variant of a parameter type as written in signatures, e.g. `r?`.
+fn data_type(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "num" => "Number",
+        "r?" => "Register",
+        "d?" => "Device",
+        "type" => "LogicType",
+        "slotType" => "SlotLogicType",
+        "name" => "Name",
+        "batchMode" => "BatchMode",
+        "reagentMode" => "ReagentMode",
+        "slotIndex" => "SlotIndex",
+        _ => return None,
+    })
 }
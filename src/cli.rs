@@ -3,8 +3,29 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(version)]
 pub(crate) struct Cli {
+    /// Talk over stdin and stdout, even if a host or port is given. This is the default
+    #[arg(long, conflicts_with_all = ["listen", "socket"])]
+    pub stdio: bool,
     #[arg(long)]
     pub listen: bool,
     pub host: Option<String>,
     pub port: Option<u16>,
+    /// Listen on a Unix domain socket at the given path instead
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["listen", "host"])]
+    pub socket: Option<std::path::PathBuf>,
+    /// Instruction set to check against
+    #[arg(long)]
+    pub ruleset: Option<String>,
+    /// Directory with custom instruction and logic type data
+    #[arg(long, value_name = "PATH")]
+    pub data_dir: Option<std::path::PathBuf>,
+    /// Check a file for problems and exit
+    #[arg(long, value_name = "FILE")]
+    pub check: Option<std::path::PathBuf>,
+    /// Format a file, printing the result to stdout, and exit
+    #[arg(long, value_name = "FILE", conflicts_with = "check")]
+    pub format: Option<std::path::PathBuf>,
+    /// Write the result of --format back to the file instead
+    #[arg(long, requires = "format")]
+    pub write: bool,
 }
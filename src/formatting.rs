@@ -0,0 +1,431 @@
+use std::{collections::HashMap, fmt::Display};
+
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+use tree_sitter::{Node, QueryCursor, Tree};
+
+use crate::queries;
+
+/// The tokens making up a single line, split into code and trailing comment.
+struct LineTokens {
+    /// Operation and operands, or the label
+    code: Vec<String>,
+    comment: Option<String>,
+    is_instruction: bool,
+}
+
+impl LineTokens {
+    /// Splits a line into its tokens, lowercasing the instruction.
+    ///
+    /// Returns `None` for lines containing syntax errors, which should be left alone. The missing
+    /// newline at the end of a document without a final newline does not count as one.
+    fn parse(line_node: Node, content: &[u8]) -> Option<Self> {
+        let mut cursor = line_node.walk();
+        let children = line_node.children(&mut cursor).collect::<Vec<_>>();
+        let unterminated = children
+            .last()
+            .is_some_and(|x| x.kind() == "newline" && x.byte_range().is_empty());
+        if line_node.has_error()
+            && !(unterminated && children.iter().rev().skip(1).all(|x| !x.has_error()))
+        {
+            return None;
+        }
+
+        let mut ret = LineTokens {
+            code: Vec::new(),
+            comment: None,
+            is_instruction: false,
+        };
+
+        let mut cursor = line_node.walk();
+        for child in line_node.named_children(&mut cursor) {
+            match child.kind() {
+                "instruction" => {
+                    ret.is_instruction = true;
+                    let mut instruction_cursor = child.walk();
+                    if let Some(operation) = child.child_by_field_name("operation") {
+                        ret.code
+                            .push(operation.utf8_text(content).unwrap().to_lowercase());
+                    }
+                    for operand in child.children_by_field_name("operand", &mut instruction_cursor)
+                    {
+                        ret.code
+                            .push(operand.utf8_text(content).unwrap().to_string());
+                    }
+                }
+                "label" => {
+                    ret.code
+                        .push(child.utf8_text(content).unwrap().trim_end().to_string());
+                }
+                "comment" => {
+                    ret.comment = Some(child.utf8_text(content).unwrap().trim_end().to_string());
+                }
+                _ => {}
+            }
+        }
+
+        Some(ret)
+    }
+
+    /// Like [`Display`], but pads every code token that is followed by another one to the width of
+    /// its column.
+    fn to_string_aligned(&self, widths: &[usize]) -> String {
+        let mut ret = String::new();
+        for (i, token) in self.code.iter().enumerate() {
+            if i + 1 < self.code.len() {
+                let width = widths.get(i).copied().unwrap_or(0);
+                ret.push_str(&format!("{token:width$} "));
+            } else {
+                ret.push_str(token);
+            }
+        }
+        if let Some(comment) = &self.comment {
+            if !ret.is_empty() {
+                ret.push(' ');
+            }
+            ret.push_str(comment);
+        }
+        ret
+    }
+}
+
+impl Display for LineTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_string_aligned(&[]).fmt(f)
+    }
+}
+
+/// Maps each row to the `line` node starting on it.
+fn line_nodes_by_row<'a>(tree: &'a Tree, content: &[u8]) -> HashMap<usize, Node<'a>> {
+    let mut ret = HashMap::new();
+    let mut cursor = QueryCursor::new();
+    let query = &*queries::LINES;
+    for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+        let node = capture.captures[0].node;
+        ret.insert(node.start_position().row, node);
+    }
+    ret
+}
+
+/// Pairs every row of the document with its tokens, if the row consists of exactly one line
+/// without syntax errors.
+fn tokenize<'a>(tree: &Tree, content: &'a [u8]) -> Vec<(&'a str, Option<LineTokens>)> {
+    let line_nodes = line_nodes_by_row(tree, content);
+    let text = std::str::from_utf8(content).unwrap();
+
+    text.lines()
+        .enumerate()
+        .map(|(row, raw)| {
+            let tokens = line_nodes
+                .get(&row)
+                .filter(|x| raw[..x.start_position().column].trim().is_empty())
+                .and_then(|x| LineTokens::parse(*x, content));
+            (raw, tokens)
+        })
+        .collect()
+}
+
+/// Formats the document, returning one edit for every line that changed.
+///
+/// Every line is re-emitted with a single space between its tokens. If `align_operands` is set,
+/// operands of instructions are additionally aligned into columns. Lines containing syntax errors
+/// and lines consisting only of a comment are left untouched.
+pub(crate) fn format_document(tree: &Tree, content: &[u8], align_operands: bool) -> Vec<TextEdit> {
+    let lines = tokenize(tree, content);
+
+    let mut widths = Vec::new();
+    if align_operands {
+        for tokens in lines.iter().filter_map(|x| x.1.as_ref()) {
+            if !tokens.is_instruction {
+                continue;
+            }
+            // The last token is not padded, so it does not widen its column
+            let padded = tokens.code.len().saturating_sub(1);
+            for (i, token) in tokens.code[..padded].iter().enumerate() {
+                if widths.len() <= i {
+                    widths.push(0);
+                }
+                widths[i] = widths[i].max(token.chars().count());
+            }
+        }
+    }
+
+    let mut ret = Vec::new();
+    for (row, (raw, tokens)) in lines.iter().enumerate() {
+        let Some(tokens) = tokens else {
+            continue;
+        };
+        if tokens.code.is_empty() && tokens.comment.is_some() {
+            continue;
+        }
+
+        let formatted = if tokens.is_instruction {
+            tokens.to_string_aligned(&widths)
+        } else {
+            tokens.to_string()
+        };
+
+        if formatted != *raw {
+            ret.push(TextEdit::new(
+                Range::new(
+                    Position::new(row as u32, 0),
+                    Position::new(row as u32, raw.len() as u32),
+                ),
+                formatted,
+            ));
+        }
+    }
+    ret
+}
+
+/// Formats the document like [`format_document`], returning the resulting text.
+///
+/// Line endings are preserved.
+pub(crate) fn format_text(tree: &Tree, content: &[u8], align_operands: bool) -> String {
+    let mut edits = format_document(tree, content, align_operands)
+        .into_iter()
+        .map(|x| (x.range.start.line as usize, x.new_text))
+        .collect::<HashMap<_, _>>();
+    let text = std::str::from_utf8(content).unwrap();
+
+    let mut ret = String::with_capacity(text.len());
+    for (row, line) in text.split_inclusive('\n').enumerate() {
+        let Some(formatted) = edits.remove(&row) else {
+            ret.push_str(line);
+            continue;
+        };
+        let raw = line.strip_suffix('\n').unwrap_or(line);
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        ret.push_str(&formatted);
+        ret.push_str(&line[raw.len()..]);
+    }
+    ret
+}
+
+/// Computes the canonical form of a program:
+///
+/// - every line is re-emitted with a single space between its tokens and the instruction
+///   lowercased, lines with syntax errors are kept as-is
+/// - all `define` lines are moved to the top, sorted by name, unless the program depends on line
+///   numbers
+/// - trailing blank lines are removed and the program ends in a single newline
+pub(crate) fn canonicalize(tree: &Tree, content: &[u8]) -> String {
+    let lines = tokenize(tree, content);
+    let keep_lines = depends_on_line_numbers(&lines);
+
+    let mut defines = Vec::new();
+    let mut rest = Vec::new();
+
+    for (raw, tokens) in lines {
+        let Some(tokens) = tokens else {
+            rest.push(raw.trim_end().to_string());
+            continue;
+        };
+
+        if tokens.is_instruction
+            && tokens.code[0] == "define"
+            && tokens.code.len() > 1
+            && !keep_lines
+        {
+            defines.push((tokens.code[1].clone(), tokens.to_string()));
+        } else {
+            rest.push(tokens.to_string());
+        }
+    }
+
+    defines.sort_by(|x, y| x.0.cmp(&y.0));
+    while rest.last().is_some_and(String::is_empty) {
+        rest.pop();
+    }
+
+    let mut ret = String::new();
+    for line in defines.into_iter().map(|x| x.1).chain(rest) {
+        ret.push_str(&line);
+        ret.push('\n');
+    }
+    ret
+}
+
+/// Computes a compact form of a program for pasting into the game:
+///
+/// - comments are removed and every line is re-emitted with a single space between its tokens
+/// - blank lines are removed, unless the program jumps to line numbers or uses relative jumps, in
+///   which case they are kept empty so every instruction stays on its line
+/// - lines containing syntax errors are kept as they are, apart from surrounding whitespace
+/// - trailing blank lines are removed and there is no final newline
+pub(crate) fn minify(tree: &Tree, content: &[u8]) -> String {
+    let lines = tokenize(tree, content);
+    let keep_lines = depends_on_line_numbers(&lines);
+
+    let mut ret = Vec::new();
+    for (raw, tokens) in lines {
+        let line = match tokens {
+            Some(tokens) => LineTokens {
+                comment: None,
+                ..tokens
+            }
+            .to_string(),
+            None => raw.trim().to_string(),
+        };
+        if !line.is_empty() || keep_lines {
+            ret.push(line);
+        }
+    }
+    while ret.last().is_some_and(String::is_empty) {
+        ret.pop();
+    }
+    ret.join("\n")
+}
+
+/// Reorganizes the layout of a program without changing any line's contents:
+///
+/// - all `define` and `alias` lines are moved to the top, in their original order, followed by a
+///   blank line
+/// - every label is preceded by exactly one blank line, placed before any comment lines directly
+///   above it, unless it follows another label or starts the program
+/// - trailing blank lines are removed and the program ends in a single newline
+///
+/// Returns `None` if the program jumps to line numbers or uses relative jumps, as moving lines
+/// would change where those jumps end up.
+pub(crate) fn organize(tree: &Tree, content: &[u8]) -> Option<String> {
+    let lines = tokenize(tree, content);
+    if depends_on_line_numbers(&lines) {
+        return None;
+    }
+
+    let mut definitions = Vec::new();
+    let mut rest: Vec<(&str, bool)> = Vec::new();
+    for (raw, tokens) in &lines {
+        let raw = raw.trim_end();
+        let Some(tokens) = tokens else {
+            rest.push((raw, false));
+            continue;
+        };
+        let is_label = !tokens.is_instruction && !tokens.code.is_empty();
+        if tokens.is_instruction && matches!(tokens.code[0].as_str(), "define" | "alias") {
+            definitions.push(raw);
+        } else if is_label {
+            // Blank lines before the label and its comments are replaced by a single one
+            let mut comments = Vec::new();
+            while rest
+                .last()
+                .is_some_and(|x| x.0.trim_start().starts_with('#'))
+            {
+                comments.push(rest.pop().unwrap());
+            }
+            while rest.last().is_some_and(|x| x.0.trim().is_empty()) {
+                rest.pop();
+            }
+            let follows_label = rest.last().is_some_and(|x| x.1) && comments.is_empty();
+            if !rest.is_empty() && !follows_label {
+                rest.push(("", false));
+            }
+            rest.extend(comments.into_iter().rev());
+            rest.push((raw, true));
+        } else {
+            rest.push((raw, false));
+        }
+    }
+
+    while rest.first().is_some_and(|x| x.0.trim().is_empty()) {
+        rest.remove(0);
+    }
+    while rest.last().is_some_and(|x| x.0.trim().is_empty()) {
+        rest.pop();
+    }
+
+    let mut ret = String::new();
+    for line in &definitions {
+        ret.push_str(line);
+        ret.push('\n');
+    }
+    if !definitions.is_empty() && !rest.is_empty() {
+        ret.push('\n');
+    }
+    for (line, _) in rest {
+        ret.push_str(line);
+        ret.push('\n');
+    }
+    Some(ret)
+}
+
+/// Whether the program jumps to line numbers, directly or through a define, or uses relative
+/// jumps, which both depend on every instruction staying on its line.
+fn depends_on_line_numbers(lines: &[(&str, Option<LineTokens>)]) -> bool {
+    let defines = lines
+        .iter()
+        .filter_map(|x| x.1.as_ref())
+        .filter(|x| x.is_instruction && x.code.len() > 1 && x.code[0] == "define")
+        .map(|x| x.code[1].as_str())
+        .collect::<Vec<_>>();
+    lines.iter().filter_map(|x| x.1.as_ref()).any(|tokens| {
+        let Some(operation) = tokens.code.first().filter(|_| tokens.is_instruction) else {
+            return false;
+        };
+        if operation.starts_with("br") || operation == "jr" {
+            return true;
+        }
+        if operation != "j" && operation != "jal" && !operation.starts_with('b') {
+            return false;
+        }
+        let target = tokens.code.last().unwrap();
+        target.starts_with(|x: char| x.is_ascii_digit() || matches!(x, '-' | '$' | '%'))
+            || defines.contains(&target.as_str())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tree_sitter::Parser;
+
+    use super::*;
+
+    fn format(source: &str, align_operands: bool) -> String {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        format_text(&tree, source.as_bytes(), align_operands)
+    }
+
+    #[test]
+    fn normalizes_spacing() {
+        assert_eq!(
+            format("  MOVE   r0\t1   # set\nstart:  \n", false),
+            "move r0 1 # set\nstart:\n"
+        );
+    }
+
+    #[test]
+    fn aligns_operands() {
+        let source = "move r0 1\nadd r10 r0 100\nloop:\nyield\n";
+        assert_eq!(
+            format(source, true),
+            "move r0  1\nadd  r10 r0 100\nloop:\nyield\n"
+        );
+        assert_eq!(format(source, false), source);
+    }
+
+    #[test]
+    fn keeps_comment_lines() {
+        let source = "   #   indented   comment  \nmove  r0 1\n";
+        assert_eq!(
+            format(source, false),
+            "   #   indented   comment  \nmove r0 1\n"
+        );
+    }
+
+    #[test]
+    fn skips_syntax_errors() {
+        let source = "move r0,   1\nadd  r0 r0 1\n";
+        assert_eq!(format(source, false), "move r0,   1\nadd r0 r0 1\n");
+        assert_eq!(format("yield\nmove r0,   1", false), "yield\nmove r0,   1");
+    }
+
+    #[test]
+    fn keeps_line_endings() {
+        assert_eq!(
+            format("move  r0 1\r\nyield \r\n", false),
+            "move r0 1\r\nyield\r\n"
+        );
+        assert_eq!(format("yield\nmove  r0 1", false), "yield\nmove r0 1");
+    }
+}
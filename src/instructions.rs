@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, path::Path, sync::RwLock};
 
 use phf::{phf_map, phf_set};
 
@@ -12,6 +12,14 @@ pub(crate) enum DataType {
     Name,
     BatchMode,
     ReagentMode,
+    SlotIndex,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LogicTypeAccess {
+    Read,
+    Write,
+    ReadWrite,
 }
 
 #[derive(Debug)]
@@ -27,6 +35,7 @@ const LOGIC_TYPE: Union = Union(&[DataType::LogicType]);
 const SLOT_LOGIC_TYPE: Union = Union(&[DataType::SlotLogicType]);
 const BATCH_MODE: Union = Union(&[DataType::BatchMode, DataType::Number, DataType::Register]);
 const REAGENT_MODE: Union = Union(&[DataType::ReagentMode, DataType::Number, DataType::Register]);
+const SLOT_INDEX: Union = Union(&[DataType::SlotIndex, DataType::Number, DataType::Register]);
 
 pub(crate) const INSTRUCTIONS: phf::Map<&'static str, InstructionSignature> = phf_map! {
     "alias" => InstructionSignature(&[Union(&[DataType::Name]), Union(&[DataType::Register, DataType::Device])]),
@@ -41,7 +50,7 @@ pub(crate) const INSTRUCTIONS: phf::Map<&'static str, InstructionSignature> = ph
     "l" => InstructionSignature(&[REGISTER,DEVICE,LOGIC_TYPE]),
     "lb" => InstructionSignature(&[REGISTER,VALUE,LOGIC_TYPE,BATCH_MODE]),
     "lr" => InstructionSignature(&[REGISTER,DEVICE,REAGENT_MODE,VALUE]),
-    "ls" => InstructionSignature(&[REGISTER,DEVICE,VALUE,SLOT_LOGIC_TYPE]),
+    "ls" => InstructionSignature(&[REGISTER,DEVICE,SLOT_INDEX,SLOT_LOGIC_TYPE]),
     "s" => InstructionSignature(&[DEVICE,LOGIC_TYPE,VALUE]),
     "sb" => InstructionSignature(&[VALUE,LOGIC_TYPE,VALUE]),
     "bap" => InstructionSignature(&[VALUE,VALUE,VALUE,VALUE]),
@@ -151,20 +160,84 @@ pub(crate) const INSTRUCTIONS: phf::Map<&'static str, InstructionSignature> = ph
     "bnan" => InstructionSignature(&[VALUE, VALUE]),
     "brnan" => InstructionSignature(&[VALUE, VALUE]),
     "lbn" => InstructionSignature(&[REGISTER, VALUE, VALUE, LOGIC_TYPE, BATCH_MODE]),
-    "lbns" => InstructionSignature(&[REGISTER, VALUE, VALUE, VALUE, SLOT_LOGIC_TYPE, BATCH_MODE]),
-    "lbs" => InstructionSignature(&[REGISTER, VALUE, VALUE, SLOT_LOGIC_TYPE, BATCH_MODE]),
+    "lbns" => InstructionSignature(&[REGISTER, VALUE, VALUE, SLOT_INDEX, SLOT_LOGIC_TYPE, BATCH_MODE]),
+    "lbs" => InstructionSignature(&[REGISTER, VALUE, SLOT_INDEX, SLOT_LOGIC_TYPE, BATCH_MODE]),
     "not" => InstructionSignature(&[REGISTER, VALUE]),
     "sbn" => InstructionSignature(&[VALUE, VALUE, LOGIC_TYPE, REGISTER]),
-    "sbs" => InstructionSignature(&[VALUE, VALUE, SLOT_LOGIC_TYPE, REGISTER]),
+    "sbs" => InstructionSignature(&[VALUE, SLOT_INDEX, SLOT_LOGIC_TYPE, REGISTER]),
     "sla" => InstructionSignature(&[REGISTER, VALUE, VALUE]),
     "sll" => InstructionSignature(&[REGISTER, VALUE, VALUE]),
     "sra" => InstructionSignature(&[REGISTER, VALUE, VALUE]),
     "srl" => InstructionSignature(&[REGISTER, VALUE, VALUE]),
     "snan" => InstructionSignature(&[REGISTER, VALUE]),
     "snanz" => InstructionSignature(&[REGISTER, VALUE]),
-    "ss" => InstructionSignature(&[DEVICE, VALUE, SLOT_LOGIC_TYPE, REGISTER]),
+    "ss" => InstructionSignature(&[DEVICE, SLOT_INDEX, SLOT_LOGIC_TYPE, REGISTER]),
+};
+
+/// The forms of instructions accepting differently shaped operands, as shown in signature help.
+///
+/// [`INSTRUCTIONS`] holds the combined signature of these, which is used for type checking.
+pub(crate) const OVERLOADS: phf::Map<&'static str, &'static [InstructionSignature]> = phf_map! {
+    "alias" => &[
+        InstructionSignature(&[Union(&[DataType::Name]), REGISTER]),
+        InstructionSignature(&[Union(&[DataType::Name]), DEVICE]),
+    ],
+    "label" => &[
+        InstructionSignature(&[Union(&[DataType::Name]), REGISTER]),
+        InstructionSignature(&[Union(&[DataType::Name]), DEVICE]),
+    ],
 };
 
+/// All forms of an instruction in the given ruleset, or none if it does not exist.
+pub(crate) fn signatures(ruleset: &str, instruction: &str) -> &'static [InstructionSignature] {
+    if let Some(signature) = custom_data()
+        .and_then(|x| x.instructions.get(instruction))
+        .or_else(|| ruleset_signature(ruleset, instruction))
+    {
+        return std::slice::from_ref(signature);
+    }
+    if let Some(overloads) = OVERLOADS.get(instruction) {
+        return overloads;
+    }
+    signature(ruleset, instruction)
+        .map(std::slice::from_ref)
+        .unwrap_or(&[])
+}
+
+/// Instructions jumping to an absolute line, given as their last operand
+pub(crate) const BRANCH_INSTRUCTIONS: phf::Set<&'static str> = phf_set!(
+    "bdns", "bdnsal", "bdse", "bdseal", "bap", "bapal", "bapz", "bapzal", "beq", "beqal", "beqz",
+    "beqzal", "bge", "bgeal", "bgez", "bgezal", "bgt", "bgtal", "bgtz", "bgtzal", "ble", "bleal",
+    "blez", "blezal", "blt", "bltal", "bltz", "bltzal", "bna", "bnaal", "bnan", "bnaz", "bnazal",
+    "bne", "bneal", "bnez", "bnezal", "j", "jal"
+);
+
+/// Relative counterparts of absolute branch instructions
+pub(crate) const RELATIVE_BRANCHES: phf::Map<&'static str, &'static str> = phf_map! {
+    "bdns" => "brdns",
+    "bdse" => "brdse",
+    "bap" => "brap",
+    "bapz" => "brapz",
+    "beq" => "breq",
+    "beqz" => "breqz",
+    "bge" => "brge",
+    "bgez" => "brgez",
+    "bgt" => "brgt",
+    "bgtz" => "brgtz",
+    "ble" => "brle",
+    "blez" => "brlez",
+    "blt" => "brlt",
+    "bltz" => "brltz",
+    "bna" => "brna",
+    "bnaz" => "brnaz",
+    "bne" => "brne",
+    "bnez" => "brnez",
+    "j" => "jr",
+};
+
+/// Instructions that still work, but should no longer be used
+pub(crate) const DEPRECATED_INSTRUCTIONS: phf::Set<&'static str> = phf_set!("label");
+
 pub(crate) const LOGIC_TYPES: phf::Set<&'static str> = phf_set! {
     "Power",
     "Open",
@@ -331,6 +404,135 @@ pub(crate) const LOGIC_TYPES: phf::Set<&'static str> = phf_set! {
     "WattsReachingContact",
 };
 
+/// Logic types that can only be read or only be written. All others can be both read and written,
+/// which includes any that are writable on at least one device, such as the vent pressure settings.
+pub(crate) const LOGIC_TYPE_ACCESS: phf::Map<&'static str, LogicTypeAccess> = phf_map! {
+    "Power" => LogicTypeAccess::Read,
+    "Error" => LogicTypeAccess::Read,
+    "Pressure" => LogicTypeAccess::Read,
+    "Temperature" => LogicTypeAccess::Read,
+    "Charge" => LogicTypeAccess::Read,
+    "Reagents" => LogicTypeAccess::Read,
+    "RatioOxygen" => LogicTypeAccess::Read,
+    "RatioCarbonDioxide" => LogicTypeAccess::Read,
+    "RatioNitrogen" => LogicTypeAccess::Read,
+    "RatioPollutant" => LogicTypeAccess::Read,
+    "RatioVolatiles" => LogicTypeAccess::Read,
+    "RatioWater" => LogicTypeAccess::Read,
+    "RatioNitrousOxide" => LogicTypeAccess::Read,
+    "SolarAngle" => LogicTypeAccess::Read,
+    "Maximum" => LogicTypeAccess::Read,
+    "Ratio" => LogicTypeAccess::Read,
+    "PowerPotential" => LogicTypeAccess::Read,
+    "PowerActual" => LogicTypeAccess::Read,
+    "Quantity" => LogicTypeAccess::Read,
+    "ImportQuantity" => LogicTypeAccess::Read,
+    "ExportQuantity" => LogicTypeAccess::Read,
+    "RequiredPower" => LogicTypeAccess::Read,
+    "PowerRequired" => LogicTypeAccess::Read,
+    "Idle" => LogicTypeAccess::Read,
+    "PlantHealth1" => LogicTypeAccess::Read,
+    "PlantHealth2" => LogicTypeAccess::Read,
+    "PlantHealth3" => LogicTypeAccess::Read,
+    "PlantHealth4" => LogicTypeAccess::Read,
+    "PlantGrowth1" => LogicTypeAccess::Read,
+    "PlantGrowth2" => LogicTypeAccess::Read,
+    "PlantGrowth3" => LogicTypeAccess::Read,
+    "PlantGrowth4" => LogicTypeAccess::Read,
+    "PlantEfficiency1" => LogicTypeAccess::Read,
+    "PlantEfficiency2" => LogicTypeAccess::Read,
+    "PlantEfficiency3" => LogicTypeAccess::Read,
+    "PlantEfficiency4" => LogicTypeAccess::Read,
+    "PlantHash1" => LogicTypeAccess::Read,
+    "PlantHash2" => LogicTypeAccess::Read,
+    "PlantHash3" => LogicTypeAccess::Read,
+    "PlantHash4" => LogicTypeAccess::Read,
+    "CompletionRatio" => LogicTypeAccess::Read,
+    "ExportCount" => LogicTypeAccess::Read,
+    "ImportCount" => LogicTypeAccess::Read,
+    "PowerGeneration" => LogicTypeAccess::Read,
+    "TotalMoles" => LogicTypeAccess::Read,
+    "TemperatureExternal" => LogicTypeAccess::Read,
+    "PositionX" => LogicTypeAccess::Read,
+    "PositionY" => LogicTypeAccess::Read,
+    "PositionZ" => LogicTypeAccess::Read,
+    "VelocityMagnitude" => LogicTypeAccess::Read,
+    "VelocityRelativeX" => LogicTypeAccess::Read,
+    "VelocityRelativeY" => LogicTypeAccess::Read,
+    "VelocityRelativeZ" => LogicTypeAccess::Read,
+    "PrefabHash" => LogicTypeAccess::Read,
+    "SignalStrength" => LogicTypeAccess::Read,
+    "SignalID" => LogicTypeAccess::Read,
+    "MineablesInVicinity" => LogicTypeAccess::Read,
+    "MineablesInQueue" => LogicTypeAccess::Read,
+    "NextWeatherEventTime" => LogicTypeAccess::Read,
+    "Combustion" => LogicTypeAccess::Read,
+    "ReturnFuelCost" => LogicTypeAccess::Read,
+    "CollectableGoods" => LogicTypeAccess::Read,
+    "Time" => LogicTypeAccess::Read,
+    "EnvironmentEfficiency" => LogicTypeAccess::Read,
+    "WorkingGasEfficiency" => LogicTypeAccess::Read,
+    "PressureInput" => LogicTypeAccess::Read,
+    "TemperatureInput" => LogicTypeAccess::Read,
+    "RatioOxygenInput" => LogicTypeAccess::Read,
+    "RatioCarbonDioxideInput" => LogicTypeAccess::Read,
+    "RatioNitrogenInput" => LogicTypeAccess::Read,
+    "RatioPollutantInput" => LogicTypeAccess::Read,
+    "RatioVolatilesInput" => LogicTypeAccess::Read,
+    "RatioWaterInput" => LogicTypeAccess::Read,
+    "RatioNitrousOxideInput" => LogicTypeAccess::Read,
+    "TotalMolesInput" => LogicTypeAccess::Read,
+    "CombustionInput" => LogicTypeAccess::Read,
+    "PressureInput2" => LogicTypeAccess::Read,
+    "TemperatureInput2" => LogicTypeAccess::Read,
+    "RatioOxygenInput2" => LogicTypeAccess::Read,
+    "RatioCarbonDioxideInput2" => LogicTypeAccess::Read,
+    "RatioNitrogenInput2" => LogicTypeAccess::Read,
+    "RatioPollutantInput2" => LogicTypeAccess::Read,
+    "RatioVolatilesInput2" => LogicTypeAccess::Read,
+    "RatioWaterInput2" => LogicTypeAccess::Read,
+    "RatioNitrousOxideInput2" => LogicTypeAccess::Read,
+    "TotalMolesInput2" => LogicTypeAccess::Read,
+    "CombustionInput2" => LogicTypeAccess::Read,
+    "PressureOutput" => LogicTypeAccess::Read,
+    "TemperatureOutput" => LogicTypeAccess::Read,
+    "RatioOxygenOutput" => LogicTypeAccess::Read,
+    "RatioCarbonDioxideOutput" => LogicTypeAccess::Read,
+    "RatioNitrogenOutput" => LogicTypeAccess::Read,
+    "RatioPollutantOutput" => LogicTypeAccess::Read,
+    "RatioVolatilesOutput" => LogicTypeAccess::Read,
+    "RatioWaterOutput" => LogicTypeAccess::Read,
+    "RatioNitrousOxideOutput" => LogicTypeAccess::Read,
+    "TotalMolesOutput" => LogicTypeAccess::Read,
+    "CombustionOutput" => LogicTypeAccess::Read,
+    "PressureOutput2" => LogicTypeAccess::Read,
+    "TemperatureOutput2" => LogicTypeAccess::Read,
+    "RatioOxygenOutput2" => LogicTypeAccess::Read,
+    "RatioCarbonDioxideOutput2" => LogicTypeAccess::Read,
+    "RatioNitrogenOutput2" => LogicTypeAccess::Read,
+    "RatioPollutantOutput2" => LogicTypeAccess::Read,
+    "RatioVolatilesOutput2" => LogicTypeAccess::Read,
+    "RatioWaterOutput2" => LogicTypeAccess::Read,
+    "RatioNitrousOxideOutput2" => LogicTypeAccess::Read,
+    "TotalMolesOutput2" => LogicTypeAccess::Read,
+    "CombustionOutput2" => LogicTypeAccess::Read,
+    "OperationalTemperatureEfficiency" => LogicTypeAccess::Read,
+    "TemperatureDifferentialEfficiency" => LogicTypeAccess::Read,
+    "PressureEfficiency" => LogicTypeAccess::Read,
+    "Rpm" => LogicTypeAccess::Read,
+    "Stress" => LogicTypeAccess::Read,
+    "InterrogationProgress" => LogicTypeAccess::Read,
+    "SizeX" => LogicTypeAccess::Read,
+    "SizeY" => LogicTypeAccess::Read,
+    "SizeZ" => LogicTypeAccess::Read,
+    "MinimumWattsToContact" => LogicTypeAccess::Read,
+    "WattsReachingContact" => LogicTypeAccess::Read,
+    "ClearMemory" => LogicTypeAccess::Write,
+    "ForceWrite" => LogicTypeAccess::Write,
+    "Harvest" => LogicTypeAccess::Write,
+    "Plant" => LogicTypeAccess::Write,
+};
+
 pub(crate) const SLOT_LOGIC_TYPES: phf::Set<&'static str> = phf_set! {
     "Occupied",
     "OccupantHash",
@@ -352,6 +554,126 @@ pub(crate) const SLOT_LOGIC_TYPES: phf::Set<&'static str> = phf_set! {
     "Seeding",
 };
 
+/// Logic types whose values are not necessarily whole numbers, such as measurements and ratios.
+/// Loading any other logic type gives an integer, e.g. a state, mode, count or hash.
+pub(crate) const FRACTIONAL_LOGIC_TYPES: phf::Set<&'static str> = phf_set! {
+    "Pressure",
+    "Temperature",
+    "PressureExternal",
+    "PressureInternal",
+    "Charge",
+    "Setting",
+    "RatioOxygen",
+    "RatioCarbonDioxide",
+    "RatioNitrogen",
+    "RatioPollutant",
+    "RatioVolatiles",
+    "RatioWater",
+    "Horizontal",
+    "Vertical",
+    "SolarAngle",
+    "Maximum",
+    "Ratio",
+    "PowerPotential",
+    "PowerActual",
+    "RequiredPower",
+    "HorizontalRatio",
+    "VerticalRatio",
+    "PowerRequired",
+    "ElevatorSpeed",
+    "CompletionRatio",
+    "PowerGeneration",
+    "TotalMoles",
+    "Volume",
+    "PressureSetting",
+    "TemperatureSetting",
+    "TemperatureExternal",
+    "PositionX",
+    "PositionY",
+    "PositionZ",
+    "VelocityMagnitude",
+    "VelocityRelativeX",
+    "VelocityRelativeY",
+    "VelocityRelativeZ",
+    "RatioNitrousOxide",
+    "SignalStrength",
+    "TargetX",
+    "TargetY",
+    "TargetZ",
+    "SettingInput",
+    "SettingOutput",
+    "NextWeatherEventTime",
+    "Fuel",
+    "ReturnFuelCost",
+    "Time",
+    "EnvironmentEfficiency",
+    "WorkingGasEfficiency",
+    "PressureInput",
+    "TemperatureInput",
+    "RatioOxygenInput",
+    "RatioCarbonDioxideInput",
+    "RatioNitrogenInput",
+    "RatioPollutantInput",
+    "RatioVolatilesInput",
+    "RatioWaterInput",
+    "RatioNitrousOxideInput",
+    "TotalMolesInput",
+    "PressureInput2",
+    "TemperatureInput2",
+    "RatioOxygenInput2",
+    "RatioCarbonDioxideInput2",
+    "RatioNitrogenInput2",
+    "RatioPollutantInput2",
+    "RatioVolatilesInput2",
+    "RatioWaterInput2",
+    "RatioNitrousOxideInput2",
+    "TotalMolesInput2",
+    "PressureOutput",
+    "TemperatureOutput",
+    "RatioOxygenOutput",
+    "RatioCarbonDioxideOutput",
+    "RatioNitrogenOutput",
+    "RatioPollutantOutput",
+    "RatioVolatilesOutput",
+    "RatioWaterOutput",
+    "RatioNitrousOxideOutput",
+    "TotalMolesOutput",
+    "PressureOutput2",
+    "TemperatureOutput2",
+    "RatioOxygenOutput2",
+    "RatioCarbonDioxideOutput2",
+    "RatioNitrogenOutput2",
+    "RatioPollutantOutput2",
+    "RatioVolatilesOutput2",
+    "RatioWaterOutput2",
+    "RatioNitrousOxideOutput2",
+    "TotalMolesOutput2",
+    "OperationalTemperatureEfficiency",
+    "TemperatureDifferentialEfficiency",
+    "PressureEfficiency",
+    "CombustionLimiter",
+    "Throttle",
+    "Rpm",
+    "Stress",
+    "InterrogationProgress",
+    "MinimumWattsToContact",
+    "WattsReachingContact",
+};
+
+/// Slot logic types whose values are not necessarily whole numbers.
+pub(crate) const FRACTIONAL_SLOT_LOGIC_TYPES: phf::Set<&'static str> = phf_set! {
+    "Damage",
+    "Efficiency",
+    "Health",
+    "Growth",
+    "Pressure",
+    "Temperature",
+    "Charge",
+    "ChargeRatio",
+    "PressureWaste",
+    "PressureAir",
+};
+
 pub(crate) const BATCH_MODES: phf::Set<&'static str> = phf_set! {
     "Average",
     "Sum",
@@ -389,6 +711,7 @@ impl Display for DataType {
             DataType::Name => "name",
             DataType::BatchMode => "batchMode",
             DataType::ReagentMode => "reagentMode",
+            DataType::SlotIndex => "slotIndex",
         };
         write!(f, "{}", val)
     }
@@ -460,13 +783,20 @@ impl<'a> Union<'a> {
     }
 }
 
+pub(crate) fn logic_type_access(name: &str) -> LogicTypeAccess {
+    LOGIC_TYPE_ACCESS
+        .get(name)
+        .copied()
+        .unwrap_or(LogicTypeAccess::ReadWrite)
+}
+
 pub(crate) fn logictype_candidates(text: &str) -> Vec<DataType> {
     let mut ret = Vec::with_capacity(3);
 
-    if LOGIC_TYPES.contains(text) {
+    if is_logic_type(text) {
         ret.push(DataType::LogicType);
     }
-    if SLOT_LOGIC_TYPES.contains(text) {
+    if is_slot_logic_type(text) {
         ret.push(DataType::SlotLogicType);
     }
     if BATCH_MODES.contains(text) {
@@ -479,6 +809,297 @@ pub(crate) fn logictype_candidates(text: &str) -> Vec<DataType> {
     ret
 }
 
+/// Index of the operand holding the prefab hash of a batch instruction.
+pub(crate) fn prefab_hash_parameter(instruction: &str) -> Option<usize> {
+    match instruction {
+        "lb" | "lbn" | "lbs" | "lbns" => Some(1),
+        "sb" | "sbn" | "sbs" => Some(0),
+        _ => None,
+    }
+}
+
+/// Instructions and logic types loaded from a data directory, taking precedence over the built-in
+/// tables.
+#[derive(Default)]
+struct CustomData {
+    instructions: HashMap<String, InstructionSignature>,
+    instruction_docs: HashMap<String, String>,
+    logic_types: HashMap<String, String>,
+    slot_logic_types: HashMap<String, String>,
+    device_logic_types: HashMap<i32, Vec<String>>,
+}
+
+/// Loaded custom data. Replacing it leaks the previous data, which only happens when the data
+/// directory is changed.
+static CUSTOM_DATA: RwLock<Option<&'static CustomData>> = RwLock::new(None);
+
+fn custom_data() -> Option<&'static CustomData> {
+    *CUSTOM_DATA.read().unwrap()
+}
+
+/// Reads the lines of a data file as pairs of the first word and the rest of the line, skipping
+/// blank lines and `#` comments. Missing files have no lines.
+fn read_data_file(path: &Path) -> Result<Vec<(usize, String, String)>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format!("{}: {err}", path.display())),
+    };
+    Ok(contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            (i, name.to_string(), rest.trim().to_string())
+        })
+        .collect())
+}
+
+/// Parses a parameter written the way signatures are displayed, e.g. `r?` or `(r?|num)`.
+fn parse_parameter(text: &str) -> Option<Union<'static>> {
+    const TYPES: &[DataType] = &[
+        DataType::Number,
+        DataType::Register,
+        DataType::Device,
+        DataType::LogicType,
+        DataType::SlotLogicType,
+        DataType::Name,
+        DataType::BatchMode,
+        DataType::ReagentMode,
+        DataType::SlotIndex,
+    ];
+
+    let inner = text
+        .strip_prefix('(')
+        .and_then(|x| x.strip_suffix(')'))
+        .unwrap_or(text);
+    let types = inner
+        .split('|')
+        .map(|name| TYPES.iter().find(|x| x.to_string() == name).copied())
+        .collect::<Option<Vec<_>>>()?;
+    Some(Union(Vec::leak(types)))
+}
+
+/// Loads instructions and logic types from `dir`, replacing any previously loaded data.
+///
+/// - `instructions_sig.txt`: an instruction name followed by its parameters, as in `frob r? (r?|num)`
+/// - `instructions_desc.txt`: an instruction name followed by its description
+/// - `logictypes.txt` and `slotlogictypes.txt`: a logic type followed by its description
+/// - `devicelogictypes.txt`: a prefab name or hash followed by the logic types it supports
+pub(crate) fn load_data_dir(dir: &Path) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+
+    let mut data = CustomData::default();
+    let path = dir.join("instructions_sig.txt");
+    for (line, name, parameters) in read_data_file(&path)? {
+        let parameters = parameters
+            .split_whitespace()
+            .map(|x| {
+                parse_parameter(x).ok_or_else(|| {
+                    format!("{}:{line}: Unknown parameter type `{x}`", path.display())
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        data.instructions
+            .insert(name, InstructionSignature(Vec::leak(parameters)));
+    }
+    for (_, name, docs) in read_data_file(&dir.join("instructions_desc.txt"))? {
+        data.instruction_docs.insert(name, docs);
+    }
+    for (_, name, docs) in read_data_file(&dir.join("logictypes.txt"))? {
+        data.logic_types.insert(name, docs);
+    }
+    for (_, name, docs) in read_data_file(&dir.join("slotlogictypes.txt"))? {
+        data.slot_logic_types.insert(name, docs);
+    }
+    for (_, name, logic_types) in read_data_file(&dir.join("devicelogictypes.txt"))? {
+        let prefab_hash = name.parse().unwrap_or_else(|_| hash(&name));
+        let logic_types = logic_types.split_whitespace().map(str::to_string).collect();
+        data.device_logic_types.insert(prefab_hash, logic_types);
+    }
+
+    *CUSTOM_DATA.write().unwrap() = Some(Box::leak(Box::new(data)));
+    Ok(())
+}
+
+/// Goes back to only the built-in data.
+#[cfg(test)]
+pub(crate) fn unload_data_dir() {
+    *CUSTOM_DATA.write().unwrap() = None;
+}
+
+/// Signature of an instruction in the given ruleset, preferring loaded custom data over the
+/// signatures of the ruleset and those over the built-in table.
+pub(crate) fn signature(ruleset: &str, name: &str) -> Option<&'static InstructionSignature> {
+    custom_data()
+        .and_then(|x| x.instructions.get(name))
+        .or_else(|| ruleset_signature(ruleset, name))
+        .or_else(|| INSTRUCTIONS.get(name))
+}
+
+/// Signature of an instruction from the `instructions_sig.txt` of a ruleset, for instructions that
+/// took different operands in that version of the game.
+fn ruleset_signature(ruleset: &str, name: &str) -> Option<&'static InstructionSignature> {
+    RULESET_SIGNATURES.get(ruleset).and_then(|x| x.get(name))
+}
+
+pub(crate) fn instruction_docs(name: &str) -> Option<&'static str> {
+    custom_data()
+        .and_then(|x| x.instruction_docs.get(name))
+        .map(String::as_str)
+        .or_else(|| INSTRUCTION_DOCS.get(name).copied())
+}
+
+/// Names of all known instructions, built-in and custom.
+pub(crate) fn instruction_names() -> Vec<&'static str> {
+    let mut names = INSTRUCTIONS.keys().copied().collect::<Vec<_>>();
+    if let Some(data) = custom_data() {
+        names.extend(
+            data.instructions
+                .keys()
+                .map(String::as_str)
+                .filter(|x| !INSTRUCTIONS.contains_key(x)),
+        );
+    }
+    names
+}
+
+/// Whether the instruction is defined by custom data.
+pub(crate) fn is_custom_instruction(name: &str) -> bool {
+    custom_data().is_some_and(|x| x.instructions.contains_key(name))
+}
+
+pub(crate) fn is_logic_type(name: &str) -> bool {
+    LOGIC_TYPES.contains(name) || custom_data().is_some_and(|x| x.logic_types.contains_key(name))
+}
+
+pub(crate) fn is_slot_logic_type(name: &str) -> bool {
+    SLOT_LOGIC_TYPES.contains(name)
+        || custom_data().is_some_and(|x| x.slot_logic_types.contains_key(name))
+}
+
+pub(crate) fn logic_type_docs(name: &str) -> Option<&'static str> {
+    custom_data()
+        .and_then(|x| x.logic_types.get(name))
+        .map(String::as_str)
+        .or_else(|| LOGIC_TYPE_DOCS.get(name).copied())
+}
+
+pub(crate) fn slot_type_docs(name: &str) -> Option<&'static str> {
+    custom_data()
+        .and_then(|x| x.slot_logic_types.get(name))
+        .map(String::as_str)
+        .or_else(|| SLOT_TYPE_DOCS.get(name).copied())
+}
+
+/// Logic types of the given kind with their documentation, built-in and custom.
+pub(crate) fn logic_types(typ: DataType) -> Vec<(&'static str, &'static str)> {
+    let (builtin, custom) = match typ {
+        DataType::LogicType => (&LOGIC_TYPE_DOCS, custom_data().map(|x| &x.logic_types)),
+        DataType::SlotLogicType => (&SLOT_TYPE_DOCS, custom_data().map(|x| &x.slot_logic_types)),
+        _ => return Vec::new(),
+    };
+    let mut ret = builtin
+        .entries()
+        .filter(|(name, _)| !custom.is_some_and(|x| x.contains_key(**name)))
+        .map(|(name, docs)| (*name, *docs))
+        .collect::<Vec<_>>();
+    if let Some(custom) = custom {
+        ret.extend(
+            custom
+                .iter()
+                .map(|(name, docs)| (name.as_str(), docs.as_str())),
+        );
+    }
+    ret
+}
+
+/// Logic types supported by the prefab with the given hash, if known.
+pub(crate) fn device_logic_types(prefab_hash: i32) -> Option<Vec<&'static str>> {
+    if let Some(logic_types) = custom_data().and_then(|x| x.device_logic_types.get(&prefab_hash)) {
+        return Some(logic_types.iter().map(String::as_str).collect());
+    }
+    DEVICE_LOGIC_TYPES
+        .get(&prefab_hash.to_string())
+        .map(|x| x.to_vec())
+}
+
+/// Ruleset used when none has been selected.
+pub(crate) const DEFAULT_RULESET: &str = "current";
+
+/// Whether `instruction` is available in the given ruleset. Unknown rulesets allow everything, as
+/// do custom instructions.
+///
+/// Rulesets list instruction names and, see [`signature`], the signatures that differ from the
+/// built-in table. Logic types are the same for all of them.
+pub(crate) fn in_ruleset(ruleset: &str, instruction: &str) -> bool {
+    is_custom_instruction(instruction)
+        || RULESETS
+            .get(ruleset)
+            .is_none_or(|instructions| instructions.contains(instruction))
+}
+
+/// Finds the supported instruction closest to `text`, if any is close enough to be a likely typo.
+pub(crate) fn closest_instruction(text: &str, ruleset: &str) -> Option<&'static str> {
+    let max_distance = (text.len() / 3).max(1);
+
+    instruction_names()
+        .into_iter()
+        .filter(|instruction| in_ruleset(ruleset, instruction))
+        .map(|instruction| (instruction, edit_distance(text, instruction)))
+        .filter(|(_, distance)| *distance <= max_distance)
+        .min_by(|(x, x_dist), (y, y_dist)| x_dist.cmp(y_dist).then(x.cmp(y)))
+        .map(|(instruction, _)| instruction)
+}
+
+/// Computes the value of `HASH("name")`, the CRC-32 of the name reinterpreted as signed.
+pub(crate) fn hash(name: &str) -> i32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in name.bytes() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc as i32
+}
+
+/// Optimal string alignment distance, counting adjacent transpositions as a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
 // Taken directly from the game's rocketstation_Data/StreamingAssets/Language/english.xml
 // with slight changes
 pub(crate) const INSTRUCTION_DOCS: phf::Map<&'static str, &'static str> = phf_map! {
@@ -601,6 +1222,21 @@ pub(crate) const INSTRUCTION_DOCS: phf::Map<&'static str, &'static str> = phf_ma
     "acos" => "Returns the angle (radians) whos cosine is the specified value",
     "atan" => "Returns the angle (radians) whos tan is the specified value",
     "atan2" => "Returns the angle (radians) whose tangent is the quotient of two specified values: a (y) and b (x)",
+    "bnan" => "Branch to line b if a is not a number (NaN)",
+    "brnan" => "Relative branch to line b if a is not a number (NaN)",
+    "lbn" => "Loads var from all output network devices with provided type and name hashes using the provide batch mode. Average (0), Sum (1), Minimum (2), Maximum (3). Can use either the word, or the number.",
+    "lbns" => "Loads var from slot on all output network devices with provided type and name hashes using the provide batch mode. Average (0), Sum (1), Minimum (2), Maximum (3). Can use either the word, or the number.",
+    "lbs" => "Loads var from slot on all output network devices with provided type hash using the provide batch mode. Average (0), Sum (1), Minimum (2), Maximum (3). Can use either the word, or the number.",
+    "not" => "Performs a bitwise logical NOT operation flipping each bit of the input value, resulting in a binary complement.",
+    "sbn" => "Stores register value to var on all output network devices with provided type hash and name.",
+    "sbs" => "Stores register value to slot var on all output network devices with provided type hash in the provided slot.",
+    "sla" => "Performs a bitwise arithmetic left shift operation on the binary representation of a value. It shifts the bits to the left and fills the vacated rightmost bits with a copy of the sign bit.",
+    "sll" => "Performs a bitwise logical left shift operation on the binary representation of a value. It shifts the bits to the left and fills the vacated rightmost bits with zeros.",
+    "sra" => "Performs a bitwise arithmetic right shift operation on the binary representation of a value. It shifts the bits to the right and fills the vacated leftmost bits with a copy of the sign bit.",
+    "srl" => "Performs a bitwise logical right shift operation on the binary representation of a value. It shifts the bits to the right and fills the vacated leftmost bits with zeros.",
+    "snan" => "Register = 1 if a is NaN, otherwise 0",
+    "snanz" => "Register = 0 if a is NaN, otherwise a",
+    "ss" => "Stores register value to device stored in a slot var on device.",
 };
 
 pub(crate) const LOGIC_TYPE_DOCS: phf::Map<&'static str, &'static str> = phf_map! {
@@ -813,6 +1449,26 @@ pub(crate) const BATCH_MODE_DOCS: phf::Map<&'static str, &'static str> = phf_map
     "Maximum" => "Highest of all read values",
 };
 
+pub(crate) const REAGENT_MODE_DOCS: phf::Map<&'static str, &'static str> = phf_map! {
+    "Contents" => "Reagents currently held by the device",
+    "Required" => "Reagents still needed by the device's current recipe",
+    "Recipe" => "Reagents needed by the device's current recipe in total",
+};
+
+/// Slot indices offered for completion, with the slot they usually refer to.
+pub(crate) const SLOT_INDICES: &[(u8, Option<&str>)] = &[
+    (0, Some("import / first slot")),
+    (1, Some("export / second slot")),
+    (2, None),
+    (3, None),
+    (4, None),
+    (5, None),
+    (6, None),
+    (7, None),
+    (8, None),
+    (9, None),
+];
+
 include!(concat!(env!("OUT_DIR"), "/stationpedia.rs"));
 
 #[cfg(test)]
@@ -831,6 +1487,138 @@ mod test {
         }
     }
 
+    #[test]
+    fn matching_overloads() {
+        for (instruction, overloads) in OVERLOADS.entries() {
+            println!("Do the overloads of {instruction} fit its signature?");
+            let signature = INSTRUCTIONS.get(instruction).unwrap();
+            for overload in overloads.iter() {
+                assert_eq!(overload.0.len(), signature.0.len());
+                for (parameter, combined) in overload.0.iter().zip(signature.0) {
+                    assert!(parameter.0.iter().all(|x| combined.match_type(*x)));
+                }
+            }
+        }
+        assert_eq!(signatures(DEFAULT_RULESET, "alias").len(), 2);
+        assert_eq!(signatures(DEFAULT_RULESET, "move").len(), 1);
+        assert!(signatures(DEFAULT_RULESET, "foo").is_empty());
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(hash("ItemPumpkin"), 1277828144);
+        assert_eq!(
+            HASH_NAME_LOOKUP.get(&hash("ItemPumpkin").to_string()),
+            Some(&"Pumpkin")
+        );
+        assert_eq!(hash("StructurePump"), -676640025);
+        assert_eq!(hash(""), 0);
+    }
+
+    #[test]
+    fn instruction_examples() {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        for (name, example) in INSTRUCTION_EXAMPLES.entries() {
+            assert!(
+                INSTRUCTIONS.contains_key(name),
+                "Unknown instruction {name}"
+            );
+            assert_eq!(example.split(' ').next(), Some(*name));
+            let tree = parser.parse(format!("{example}\n"), None).unwrap();
+            assert!(!tree.root_node().has_error(), "Syntax error in {example}");
+        }
+    }
+
+    #[test]
+    fn prefab_hashes() {
+        for (prefab, name) in [
+            ("StructureVolumePump", "Volume Pump"),
+            ("ItemIronIngot", "Ingot (Iron)"),
+            ("StructureSolarPanel", "Solar Panel"),
+            ("StructureBattery", "Station Battery"),
+            ("StructureGasSensor", "Gas Sensor"),
+            ("StructureCircuitHousing", "IC Housing"),
+            ("ItemIntegratedCircuit10", "Integrated Circuit (IC10)"),
+            ("StructureDaylightSensor", "Daylight Sensor"),
+        ] {
+            println!("Does {prefab} hash to {name}?");
+            assert_eq!(HASH_NAME_LOOKUP.get(&hash(prefab).to_string()), Some(&name));
+        }
+    }
+
+    #[test]
+    fn deprecated_instructions() {
+        for (instruction, docs) in INSTRUCTION_DOCS.entries() {
+            println!("Is {instruction} deprecated?");
+            assert_eq!(
+                DEPRECATED_INSTRUCTIONS.contains(instruction),
+                docs.starts_with("DEPRECATED")
+            );
+        }
+    }
+
+    #[test]
+    fn matching_enums() {
+        for name in ENUMS.iter() {
+            println!("Is {name} in ENUM_LOOKUP and ENUM_DOCS?");
+            assert!(ENUM_LOOKUP.contains_key(name));
+            assert!(ENUM_DOCS.contains_key(name));
+        }
+    }
+
+    #[test]
+    fn matching_constants() {
+        for name in CONSTANTS.keys() {
+            println!("Is {name} in CONSTANTS_DOCS?");
+            assert!(CONSTANTS_DOCS.contains_key(name));
+        }
+        for name in CONSTANTS_DOCS.keys() {
+            println!("Is {name} in CONSTANTS?");
+            assert!(CONSTANTS.contains_key(name));
+        }
+    }
+
+    #[test]
+    fn closest_instructions() {
+        assert_eq!(closest_instruction("mvoe", DEFAULT_RULESET), Some("move"));
+        assert_eq!(closest_instruction("yeild", DEFAULT_RULESET), Some("yield"));
+        assert_eq!(
+            closest_instruction("sleeep", DEFAULT_RULESET),
+            Some("sleep")
+        );
+        assert_eq!(closest_instruction("frobnicate", DEFAULT_RULESET), None);
+        assert_eq!(closest_instruction("sbnn", DEFAULT_RULESET), Some("sbn"));
+        assert_ne!(closest_instruction("sbnn", "legacy"), Some("sbn"));
+    }
+
+    #[test]
+    fn matching_rulesets() {
+        assert!(RULESETS.contains_key(DEFAULT_RULESET));
+        for (name, ruleset) in RULESETS.entries() {
+            for instruction in ruleset.iter() {
+                println!("Is {instruction} from ruleset {name} in INSTRUCTIONS?");
+                assert!(INSTRUCTIONS.contains_key(instruction));
+            }
+        }
+        for instruction in INSTRUCTIONS.keys() {
+            println!("Is {instruction} in the default ruleset?");
+            assert!(RULESETS[DEFAULT_RULESET].contains(instruction));
+        }
+        assert_eq!(RULESET_SIGNATURES.len(), RULESETS.len());
+        for (name, signatures) in RULESET_SIGNATURES.entries() {
+            for instruction in signatures.keys() {
+                println!("Is {instruction} with a signature from ruleset {name} in that ruleset?");
+                assert!(RULESETS[name].contains(instruction));
+            }
+        }
+        // Instructions without a signature of their own in a ruleset use the built-in one
+        assert_eq!(
+            signature("legacy", "add").unwrap().to_string(),
+            INSTRUCTIONS.get("add").unwrap().to_string()
+        );
+    }
+
     #[test]
     fn matching_logic_types() {
         for logictype in LOGIC_TYPES.iter() {
@@ -843,6 +1631,62 @@ mod test {
         }
     }
 
+    #[test]
+    fn logic_type_access() {
+        for logictype in LOGIC_TYPE_ACCESS.keys() {
+            println!("Is {logictype} in LOGIC_TYPES?");
+            assert!(LOGIC_TYPES.contains(logictype));
+        }
+    }
+
+    #[test]
+    fn logic_type_access_entries() {
+        let mut write_only = LOGIC_TYPE_ACCESS
+            .entries()
+            .filter(|x| *x.1 == LogicTypeAccess::Write)
+            .map(|x| *x.0)
+            .collect::<Vec<_>>();
+        write_only.sort();
+        assert_eq!(
+            write_only,
+            ["ClearMemory", "ForceWrite", "Harvest", "Plant"]
+        );
+
+        for writable in [
+            "PressureExternal",
+            "PressureInternal",
+            "ManualResearchRequiredPod",
+            "CurrentResearchPodType",
+            "Setting",
+            "On",
+        ] {
+            assert_eq!(
+                super::logic_type_access(writable),
+                LogicTypeAccess::ReadWrite
+            );
+        }
+        for read_only in [
+            "Pressure",
+            "Temperature",
+            "PrefabHash",
+            "TotalMoles",
+            "Power",
+        ] {
+            assert_eq!(super::logic_type_access(read_only), LogicTypeAccess::Read);
+        }
+
+        // Logic types documented as settings must be writable
+        for (name, docs) in LOGIC_TYPE_DOCS.entries() {
+            if docs.starts_with("Setting") || docs.starts_with("Sets") {
+                assert_ne!(
+                    super::logic_type_access(name),
+                    LogicTypeAccess::Read,
+                    "{name}"
+                );
+            }
+        }
+    }
+
     #[test]
     fn matching_slot_types() {
         for slottype in SLOT_LOGIC_TYPES.iter() {
@@ -1,46 +1,107 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Display, net::Ipv4Addr, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    net::Ipv4Addr,
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+    time::{Duration, SystemTime},
+};
 
 use phf::phf_set;
-use serde_json::Value;
+use serde_json::{json, Value};
 use tokio::{
     net::{TcpListener, TcpStream},
-    sync::RwLock,
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
 };
 use tower_lsp::{
     async_trait,
     jsonrpc::Result,
     lsp_types::{
-        CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
-        CodeActionProviderCapability, CompletionItem, CompletionItemKind,
-        CompletionItemLabelDetails, CompletionOptions, CompletionOptionsCompletionItem,
-        CompletionParams, CompletionResponse, CompletionTextEdit, Diagnostic,
-        DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeConfigurationParams,
-        DidChangeTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbolParams,
+        CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+        CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+        CallHierarchyServerCapability, ClientCapabilities, CodeAction, CodeActionKind,
+        CodeActionOrCommand, CodeActionParams, CodeActionProviderCapability, CompletionItem,
+        CompletionItemKind, CompletionItemLabelDetails, CompletionOptions,
+        CompletionOptionsCompletionItem, CompletionParams, CompletionResponse, CompletionTextEdit,
+        Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag,
+        DidChangeConfigurationParams, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+        DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentFormattingParams,
+        DocumentLink, DocumentLinkOptions, DocumentLinkParams, DocumentSymbolParams,
         DocumentSymbolResponse, Documentation, ExecuteCommandOptions, ExecuteCommandParams,
+        FoldingRange, FoldingRangeKind, FoldingRangeParams, FoldingRangeProviderCapability,
         GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
         HoverProviderCapability, InitializeParams, InitializeResult, InitializedParams, InlayHint,
-        InlayHintKind, InlayHintLabel, InlayHintParams, LanguageString, Location, MarkedString,
-        MessageType, NumberOrString, OneOf, ParameterInformation, ParameterLabel,
-        Position as LspPosition, PositionEncodingKind, Range as LspRange, SemanticToken,
-        SemanticTokenType, SemanticTokens, SemanticTokensFullOptions, SemanticTokensLegend,
-        SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult,
+        InlayHintKind, InlayHintLabel, InlayHintParams, InsertTextFormat, LanguageString, Location,
+        MarkedString, MessageType, NumberOrString, OneOf, ParameterInformation, ParameterLabel,
+        Position as LspPosition, PositionEncodingKind, PrepareRenameResponse, Range as LspRange,
+        ReferenceParams, RenameOptions, RenameParams, SaveOptions, SelectionRange,
+        SelectionRangeParams, SelectionRangeProviderCapability, SemanticToken,
+        SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensFullOptions,
+        SemanticTokensLegend, SemanticTokensOptions, SemanticTokensParams,
+        SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
         SemanticTokensServerCapabilities, ServerCapabilities, ServerInfo, SignatureHelp,
         SignatureHelpOptions, SignatureHelpParams, SignatureInformation, SymbolInformation,
-        SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
-        WorkDoneProgressOptions, WorkspaceEdit,
+        SymbolKind, TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind,
+        TextDocumentSyncOptions, TextDocumentSyncSaveOptions, TextEdit, Url,
+        WillSaveTextDocumentParams, WorkDoneProgressOptions, WorkspaceEdit, WorkspaceSymbolParams,
     },
     Client, LanguageServer, LspService, Server,
 };
 use tree_sitter::{Node, Parser, Query, QueryCursor, Tree};
 
 mod cli;
+mod formatting;
 mod instructions;
+mod queries;
+
+const LINT_ABSOLUTE_JUMP: &str = "L001";
+const LINT_NUMBER_BATCH_MODE: &str = "L002";
+const LINT_NUMBER_REAGENT_MODE: &str = "L003";
+const LINT_UNSUPPORTED_INSTRUCTION: &str = "L004";
+const LINT_FLOAT_EQUALITY: &str = "L005";
+const LINT_SELF_MOVE: &str = "L006";
+const LINT_INVALID_MATH: &str = "L007";
+const LINT_DUPLICATE_ALIAS_TARGET: &str = "L008";
+const LINT_UNUSED_DEFINITION: &str = "L009";
+const LINT_USE_BEFORE_DEFINITION: &str = "L010";
+const LINT_MISSING_YIELD: &str = "L011";
+const LINT_BUILTIN_NAME: &str = "L012";
+const LINT_MIXED_DEFINE_USE: &str = "L013";
+const LINT_INCLUDE_CONFLICT: &str = "L014";
+const LINT_SAME_VALUE_REDEFINITION: &str = "L015";
+const LINT_REDUNDANT_LABEL: &str = "L016";
+const LINT_CONSTANT_BRANCH: &str = "L017";
+const LINT_UNKNOWN_PREFAB: &str = "L018";
+const LINT_STACK_BALANCE: &str = "L019";
+const LINT_UNREACHABLE_CODE: &str = "L020";
+
+// Kinds of completion items, stored in their `data` to find their documentation when resolved
+const COMPLETION_INSTRUCTION: &str = "instruction";
+const COMPLETION_LOGIC_TYPE: &str = "logic_type";
+const COMPLETION_SLOT_LOGIC_TYPE: &str = "slot_logic_type";
+const COMPLETION_BATCH_MODE: &str = "batch_mode";
+const COMPLETION_REAGENT_MODE: &str = "reagent_mode";
+const COMPLETION_ENUM: &str = "enum";
+const COMPLETION_CONSTANT: &str = "constant";
+
+const ERROR_UNKNOWN_IDENTIFIER: &str = "E001";
+const ERROR_INCLUDE: &str = "E002";
+const ERROR_NONEXISTENT_REGISTER: &str = "E003";
+
+/// Configuration file looked for in the workspace root
+const PROJECT_CONFIG_FILE: &str = ".ic10lsp.toml";
+
+/// Index of the highest device pin, not counting `db`
+const MAX_DEVICE: usize = 5;
+
+const SEMANTIC_MODIFIER_LEGEND: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DEPRECATED,
+    SemanticTokenModifier::STATIC,
+];
 
-const LINT_ABSOLUTE_JUMP: &'static str = "L001";
-const LINT_NUMBER_BATCH_MODE: &'static str = "L002";
-const LINT_NUMBER_REAGENT_MODE: &'static str = "L003";
-
-const SEMANTIC_SYMBOL_LEGEND: &'static [SemanticTokenType] = &[
+const SEMANTIC_SYMBOL_LEGEND: &[SemanticTokenType] = &[
     SemanticTokenType::KEYWORD,
     SemanticTokenType::COMMENT,
     SemanticTokenType::STRING,
@@ -48,23 +109,307 @@ const SEMANTIC_SYMBOL_LEGEND: &'static [SemanticTokenType] = &[
     SemanticTokenType::MACRO,
     SemanticTokenType::NUMBER,
     SemanticTokenType::VARIABLE,
+    SemanticTokenType::ENUM_MEMBER,
 ];
 struct DocumentData {
     url: Url,
     content: String,
     tree: Option<Tree>,
     parser: Parser,
+    /// Whether a newline was appended to `content` that the client does not know about.
+    appended_newline: bool,
+    /// Whether the client counts columns in UTF-16 code units instead of bytes.
+    utf16: bool,
+}
+
+impl DocumentData {
+    /// Applies an incremental change and reparses, reusing the unchanged parts of the old tree.
+    fn apply_change(&mut self, range: LspRange, text: &str) {
+        let range = self.to_byte_range(range);
+        let start_byte = self.byte_offset(range.start);
+        let old_end_byte = self.byte_offset(range.end).max(start_byte);
+        let new_end_byte = start_byte + text.len();
+
+        let start_position = self.point(start_byte);
+        let old_end_position = self.point(old_end_byte);
+        self.content.replace_range(start_byte..old_end_byte, text);
+        self.edit_tree(tree_sitter::InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+            start_position,
+            old_end_position,
+            new_end_position: self.point(new_end_byte),
+        });
+
+        // Keep exactly one trailing newline that the client does not know about, if any
+        let client_len = self.content.len() - usize::from(self.appended_newline);
+        if self.appended_newline && self.content[..client_len].ends_with('\n') {
+            let position = self.point(client_len);
+            self.content.pop();
+            self.appended_newline = false;
+            self.edit_tree(tree_sitter::InputEdit {
+                start_byte: client_len,
+                old_end_byte: client_len + 1,
+                new_end_byte: client_len,
+                start_position: position,
+                old_end_position: tree_sitter::Point::new(position.row + 1, 0),
+                new_end_position: position,
+            });
+        } else if !self.content.ends_with('\n') {
+            let position = self.point(client_len);
+            self.content.push('\n');
+            self.appended_newline = true;
+            self.edit_tree(tree_sitter::InputEdit {
+                start_byte: client_len,
+                old_end_byte: client_len,
+                new_end_byte: client_len + 1,
+                start_position: position,
+                old_end_position: position,
+                new_end_position: tree_sitter::Point::new(position.row + 1, 0),
+            });
+        }
+
+        self.tree = self.parser.parse(&self.content, self.tree.as_ref());
+    }
+
+    fn edit_tree(&mut self, edit: tree_sitter::InputEdit) {
+        if let Some(tree) = self.tree.as_mut() {
+            tree.edit(&edit);
+        }
+    }
+
+    /// Byte offset of an LSP position, clamped to the end of its line.
+    fn byte_offset(&self, position: LspPosition) -> usize {
+        let mut offset = 0;
+        for (row, line) in self.content.split_inclusive('\n').enumerate() {
+            if row == position.line as usize {
+                let line = line.strip_suffix('\n').unwrap_or(line);
+                let mut column = (position.character as usize).min(line.len());
+                while !line.is_char_boundary(column) {
+                    column -= 1;
+                }
+                return offset + column;
+            }
+            offset += line.len();
+        }
+        offset
+    }
+
+    fn line(&self, row: u32) -> Option<&str> {
+        self.content.split('\n').nth(row as usize)
+    }
+
+    /// Every identifier in the document with its text, in document order.
+    fn identifiers(&self) -> Vec<(&str, Node<'_>)> {
+        let Some(tree) = self.tree.as_ref() else {
+            return Vec::new();
+        };
+        let content = self.content.as_bytes();
+
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::IDENTIFIERS;
+        cursor
+            .captures(query, tree.root_node(), content)
+            .map(|(capture, _)| {
+                let node = capture.captures[0].node;
+                (node.utf8_text(content).unwrap(), node)
+            })
+            .collect()
+    }
+
+    /// Diagnostic codes suppressed on each line through a `# ic10lsp:ignore L001 L002` comment.
+    /// `None` means every diagnostic is suppressed, through `# ic10lsp:ignore-all`.
+    fn suppressions(&self) -> HashMap<u32, Option<Vec<&str>>> {
+        let Some(tree) = self.tree.as_ref() else {
+            return HashMap::new();
+        };
+        let content = self.content.as_bytes();
+
+        let mut ret = HashMap::new();
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::COMMENTS;
+        for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+            let node = capture.captures[0].node;
+            let Some(directive) = node
+                .utf8_text(content)
+                .unwrap()
+                .trim_start_matches('#')
+                .trim()
+                .strip_prefix("ic10lsp:")
+            else {
+                continue;
+            };
+            let line = node.start_position().row as u32;
+            if directive == "ignore-all" {
+                ret.insert(line, None);
+            } else if let Some(codes) = directive.strip_prefix("ignore ") {
+                let codes = codes.split([' ', ',']).filter(|x| !x.is_empty()).collect();
+                ret.insert(line, Some(codes));
+            }
+        }
+        ret
+    }
+
+    /// Batch and reagent modes given as literal numbers, in document order, along with the lint
+    /// reporting them and the name to replace them with, or why the number is not a valid mode.
+    fn literal_modes(
+        &self,
+    ) -> Vec<(
+        Node<'_>,
+        &'static str,
+        std::result::Result<&'static str, String>,
+    )> {
+        let Some(tree) = self.tree.as_ref() else {
+            return Vec::new();
+        };
+        let content = self.content.as_bytes();
+        let mut ret = Vec::new();
+
+        let mut cursor = QueryCursor::new();
+        for query_match in cursor.matches(&queries::LAST_NUMBER_OPERANDS, tree.root_node(), content)
+        {
+            let operation = query_match.captures[0].node.utf8_text(content).unwrap();
+            if !operation.starts_with("lb") {
+                continue;
+            }
+            let node = query_match.captures[1].node;
+            let replacement = match parse_number(node.utf8_text(content).unwrap()) {
+                Some(value) => mode_name(value, "batch mode", &instructions::BATCH_MODE_LOOKUP),
+                None => Err("Invalid batch mode".to_string()),
+            };
+            ret.push((node, LINT_NUMBER_BATCH_MODE, replacement));
+        }
+
+        let mut cursor = QueryCursor::new();
+        for (capture, _) in
+            cursor.captures(&queries::REAGENT_MODE_NUMBERS, tree.root_node(), content)
+        {
+            let node = capture.captures[0].node;
+            let replacement = match parse_number(node.utf8_text(content).unwrap()) {
+                Some(value) => mode_name(value, "reagent mode", &instructions::REAGENT_MODE_LOOKUP),
+                None => Err("Invalid reagent mode".to_string()),
+            };
+            ret.push((node, LINT_NUMBER_REAGENT_MODE, replacement));
+        }
+
+        ret
+    }
+
+    /// Finds the first character before `end` extending past the configured column limit, as
+    /// displayed by an editor: every character takes up one column, and tabs extend to the next
+    /// multiple of the tab width.
+    ///
+    /// Returns the byte position of that character, or `None` if the line fits.
+    fn column_overflow(
+        &self,
+        end: tree_sitter::Point,
+        config: &Configuration,
+    ) -> Option<LspPosition> {
+        let line = self.line(end.row as u32)?;
+        let tab_width = config.tab_width.max(1);
+
+        let mut width = 0;
+        for (offset, c) in line[..end.column.min(line.len())].char_indices() {
+            width = match c {
+                '\t' => (width / tab_width + 1) * tab_width,
+                _ => width + 1,
+            };
+            if width > config.max_columns {
+                return Some(LspPosition::new(end.row as u32, offset as u32));
+            }
+        }
+        None
+    }
+
+    /// Converts a position received from the client into one counting bytes.
+    fn to_byte_position(&self, position: LspPosition) -> LspPosition {
+        let Some(line) = self.line(position.line).filter(|_| self.utf16) else {
+            return position;
+        };
+        let mut units = 0;
+        for (column, c) in line.char_indices() {
+            if units >= position.character {
+                return LspPosition::new(position.line, column as u32);
+            }
+            units += c.len_utf16() as u32;
+        }
+        LspPosition::new(
+            position.line,
+            line.len() as u32 + position.character.saturating_sub(units),
+        )
+    }
+
+    fn to_byte_range(&self, range: LspRange) -> LspRange {
+        LspRange::new(
+            self.to_byte_position(range.start),
+            self.to_byte_position(range.end),
+        )
+    }
+
+    /// Converts a position counting bytes into the encoding used by the client.
+    fn to_client_position(&self, position: LspPosition) -> LspPosition {
+        let Some(line) = self.line(position.line).filter(|_| self.utf16) else {
+            return position;
+        };
+        let mut column = (position.character as usize).min(line.len());
+        while !line.is_char_boundary(column) {
+            column -= 1;
+        }
+        let units = line[..column].encode_utf16().count() + (position.character as usize - column);
+        LspPosition::new(position.line, units as u32)
+    }
+
+    fn to_client_range(&self, range: LspRange) -> LspRange {
+        LspRange::new(
+            self.to_client_position(range.start),
+            self.to_client_position(range.end),
+        )
+    }
+
+    /// Converts the ranges of diagnostics of this document into the encoding used by the client.
+    ///
+    /// Related locations in other files, such as included ones, already use the client's encoding,
+    /// as they were converted with the lines of their own file.
+    fn convert_diagnostics_for_client(&self, diagnostics: &mut [Diagnostic]) {
+        for diagnostic in diagnostics {
+            diagnostic.range = self.to_client_range(diagnostic.range);
+            for information in diagnostic.related_information.iter_mut().flatten() {
+                if information.location.uri == self.url {
+                    information.location.range = self.to_client_range(information.location.range);
+                }
+            }
+        }
+    }
+
+    /// The range of a node in the encoding used by the client.
+    fn client_range(&self, node: Node) -> LspRange {
+        self.to_client_range(Range::from(node.range()).into())
+    }
+
+    fn point(&self, byte_offset: usize) -> tree_sitter::Point {
+        let before = &self.content[..byte_offset];
+        let row = before.matches('\n').count();
+        let column = before.len() - before.rfind('\n').map_or(0, |x| x + 1);
+        tree_sitter::Point::new(row, column)
+    }
 }
 
 #[derive(Debug)]
 struct DefinitionData<T> {
     range: Range,
     value: T,
+    /// The included file this was defined in, or `None` for the document itself.
+    source: Option<Url>,
 }
 
 impl<T> DefinitionData<T> {
     fn new(range: Range, value: T) -> Self {
-        DefinitionData { range, value }
+        DefinitionData {
+            range,
+            value,
+            source: None,
+        }
     }
 }
 
@@ -120,7 +465,7 @@ impl HasType for DefinitionData<String> {
     }
 }
 
-impl HasType for DefinitionData<u8> {
+impl HasType for DefinitionData<u32> {
     fn get_type(&self) -> instructions::DataType {
         instructions::DataType::Number
     }
@@ -139,7 +484,7 @@ where
 struct TypeData {
     defines: HashMap<String, DefinitionData<String>>,
     aliases: HashMap<String, DefinitionData<AliasValue>>,
-    labels: HashMap<String, DefinitionData<u8>>,
+    labels: HashMap<String, DefinitionData<u32>>,
 }
 
 impl TypeData {
@@ -155,6 +500,173 @@ impl TypeData {
         }
         None
     }
+
+    /// The value of a define or alias in a form that can be compared between files. Labels have
+    /// none, as their lines are only meaningful within their own file.
+    fn comparable_value(&self, name: &str) -> Option<String> {
+        if let Some(definition_data) = self.defines.get(name) {
+            let value = &definition_data.value;
+            return Some(constant_value(value).map_or_else(|| value.clone(), |x| x.to_string()));
+        }
+        if let Some(definition_data) = self.aliases.get(name) {
+            return Some(format!("alias {}", definition_data.value));
+        }
+        None
+    }
+
+    /// Names defined both here and in `other`, with whether both definitions have the same value.
+    fn conflicts<'a>(&self, other: &'a TypeData) -> Vec<(&'a str, bool)> {
+        let mut ret = other
+            .defines
+            .keys()
+            .chain(other.aliases.keys())
+            .chain(other.labels.keys())
+            .filter(|name| self.get_range(name).is_some())
+            .map(|name| {
+                let value = self.comparable_value(name);
+                (
+                    name.as_str(),
+                    value.is_some() && value == other.comparable_value(name),
+                )
+            })
+            .collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    /// The included file a name is defined in, or `None` if it is defined in the document itself.
+    fn get_source(&self, name: &str) -> Option<&Url> {
+        if let Some(definition_data) = self.defines.get(name) {
+            return definition_data.source.as_ref();
+        }
+        if let Some(definition_data) = self.aliases.get(name) {
+            return definition_data.source.as_ref();
+        }
+        if let Some(definition_data) = self.labels.get(name) {
+            return definition_data.source.as_ref();
+        }
+        None
+    }
+
+    /// Converts the ranges of definitions made in the document itself into the encoding used by the
+    /// client, for when they are shown as part of another file including it.
+    fn convert_ranges_for_client(&mut self, document: &DocumentData) {
+        let definitions = self
+            .defines
+            .values_mut()
+            .map(|x| (&mut x.range, &x.source))
+            .chain(self.aliases.values_mut().map(|x| (&mut x.range, &x.source)))
+            .chain(self.labels.values_mut().map(|x| (&mut x.range, &x.source)));
+        for (range, _) in definitions.filter(|x| x.1.is_none()) {
+            range.0 = document.to_client_range(range.0);
+        }
+    }
+
+    /// All definitions made in the document itself with their range and symbol kind, matching the
+    /// kinds of document symbols.
+    fn symbols(&self) -> impl Iterator<Item = (&String, &Range, SymbolKind)> {
+        let defines = self
+            .defines
+            .iter()
+            .filter(|x| x.1.source.is_none())
+            .map(|(name, x)| (name, &x.range, SymbolKind::NUMBER));
+        let aliases = self
+            .aliases
+            .iter()
+            .filter(|x| x.1.source.is_none())
+            .map(|(name, x)| (name, &x.range, SymbolKind::VARIABLE));
+        let labels = self
+            .labels
+            .iter()
+            .filter(|x| x.1.source.is_none())
+            .map(|(name, x)| (name, &x.range, SymbolKind::FUNCTION));
+        defines.chain(aliases).chain(labels)
+    }
+
+    /// Adds the definitions of an included file, unless the name is already defined.
+    fn include(&mut self, other: TypeData, url: &Url) {
+        for (name, mut definition) in other.defines {
+            if self.get_range(&name).is_none() {
+                definition.source.get_or_insert_with(|| url.clone());
+                self.defines.insert(name, definition);
+            }
+        }
+        for (name, mut definition) in other.aliases {
+            if self.get_range(&name).is_none() {
+                definition.source.get_or_insert_with(|| url.clone());
+                self.aliases.insert(name, definition);
+            }
+        }
+        for (name, mut definition) in other.labels {
+            if self.get_range(&name).is_none() {
+                definition.source.get_or_insert_with(|| url.clone());
+                self.labels.insert(name, definition);
+            }
+        }
+    }
+
+    /// The type of an operand, resolving identifiers through defines, aliases and labels.
+    fn operand_type(&self, operand: Node, content: &[u8]) -> Option<instructions::DataType> {
+        use instructions::DataType;
+
+        let node = operand.named_child(0)?;
+        match node.kind() {
+            "register" => Some(DataType::Register),
+            "device_spec" => Some(DataType::Device),
+            "number" => Some(DataType::Number),
+            "identifier" => {
+                let name = node.utf8_text(content).unwrap();
+                if let Some(definition_data) = self.aliases.get(name) {
+                    Some(definition_data.get_type())
+                } else if self.defines.contains_key(name)
+                    || self.labels.contains_key(name)
+                    || instructions::ENUMS.contains(name)
+                {
+                    Some(DataType::Number)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The value of a literal number or constant, either directly or through a define.
+    fn number_value(&self, node: Node, content: &[u8]) -> Option<f64> {
+        let text = node.utf8_text(content).unwrap();
+        match node.kind() {
+            "number" => parse_number(text),
+            "identifier" => match instructions::ENUM_LOOKUP.get(text) {
+                Some(value) => Some(*value as f64),
+                None => parse_number(&self.defines.get(text)?.value),
+            },
+            _ => None,
+        }
+    }
+
+    /// Resolves an operand naming a register, either directly or through an alias.
+    ///
+    /// `sp` and `ra` are normalized to `r16` and `r17`.
+    fn register_name(&self, node: Node, content: &[u8]) -> Option<String> {
+        let text = node.utf8_text(content).unwrap();
+        let register = match node.kind() {
+            "register" => text,
+            "identifier" => match self.aliases.get(text) {
+                Some(DefinitionData {
+                    value: AliasValue::Register(register),
+                    ..
+                }) => register.as_str(),
+                _ if parse_register(text).is_some_and(|(is_device, _)| !is_device) => text,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(match register {
+            "sp" => "r16".to_string(),
+            "ra" => "r17".to_string(),
+            _ => register.to_string(),
+        })
+    }
 }
 
 struct FileData {
@@ -166,8 +678,34 @@ struct FileData {
 struct Configuration {
     max_lines: usize,
     max_columns: usize,
+    max_registers: usize,
+    tab_width: usize,
     warn_overline_comment: bool,
     warn_overcolumn_comment: bool,
+    warn_float_equality: bool,
+    warn_stack_balance: bool,
+    warn_duplicate_alias_target: bool,
+    warn_missing_yield: bool,
+    align_operands: bool,
+    instruction_count: bool,
+    count_definitions: bool,
+    count_labels: bool,
+    hash_hints: bool,
+    parameter_hints: bool,
+    value_hints: bool,
+    label_hints: bool,
+    save_trim_whitespace: bool,
+    save_named_modes: bool,
+    /// Characters that open completion and signature help. Only read during `initialize`.
+    trigger_characters: Vec<String>,
+    overline_instruction_severity: DiagnosticSeverity,
+    overcolumn_instruction_severity: DiagnosticSeverity,
+    /// Per-lint severity overrides keyed by lint code. `None` disables the lint.
+    lints: HashMap<String, Option<DiagnosticSeverity>>,
+    ruleset: String,
+    /// Directory custom instruction and logic type data was loaded from.
+    data_dir: Option<std::path::PathBuf>,
+    diagnostic_debounce_ms: u64,
 }
 
 impl Default for Configuration {
@@ -175,66 +713,342 @@ impl Default for Configuration {
         Self {
             max_lines: 128,
             max_columns: 52,
+            max_registers: 18,
+            tab_width: 4,
             warn_overline_comment: true,
             warn_overcolumn_comment: false,
+            warn_float_equality: false,
+            warn_stack_balance: false,
+            warn_duplicate_alias_target: false,
+            warn_missing_yield: false,
+            align_operands: false,
+            instruction_count: false,
+            count_definitions: true,
+            count_labels: true,
+            hash_hints: true,
+            parameter_hints: false,
+            value_hints: false,
+            label_hints: true,
+            save_trim_whitespace: false,
+            save_named_modes: false,
+            trigger_characters: vec![" ".to_string()],
+            overline_instruction_severity: DiagnosticSeverity::ERROR,
+            overcolumn_instruction_severity: DiagnosticSeverity::ERROR,
+            lints: HashMap::new(),
+            ruleset: instructions::DEFAULT_RULESET.to_string(),
+            data_dir: None,
+            diagnostic_debounce_ms: 150,
+        }
+    }
+}
+
+impl Configuration {
+    /// Applies the settings in `value`, keeping the current value of anything not mentioned.
+    /// Returns messages about settings that could not be applied.
+    fn update(&mut self, value: &Value) -> Vec<String> {
+        let mut messages = Vec::new();
+
+        if let Some(warnings) = value.get("warnings").and_then(Value::as_object) {
+            self.warn_overline_comment = warnings
+                .get("overline_comment")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_overline_comment);
+
+            self.warn_overcolumn_comment = warnings
+                .get("overcolumn_comment")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_overcolumn_comment);
+
+            self.warn_float_equality = warnings
+                .get("float_equality")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_float_equality);
+
+            self.warn_stack_balance = warnings
+                .get("stack_balance")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_stack_balance);
+
+            self.warn_duplicate_alias_target = warnings
+                .get("duplicate_alias_target")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_duplicate_alias_target);
+
+            self.warn_missing_yield = warnings
+                .get("missing_yield")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.warn_missing_yield);
+        }
+
+        if let Some(formatting) = value.get("formatting").and_then(Value::as_object) {
+            self.align_operands = formatting
+                .get("align_operands")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.align_operands);
+        }
+
+        if let Some(instruction_count) = value.get("instruction_count").and_then(Value::as_object) {
+            self.instruction_count = instruction_count
+                .get("enabled")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.instruction_count);
+
+            self.count_definitions = instruction_count
+                .get("definitions")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.count_definitions);
+
+            self.count_labels = instruction_count
+                .get("labels")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.count_labels);
+        }
+
+        if let Some(inlay_hints) = value.get("inlay_hints").and_then(Value::as_object) {
+            self.hash_hints = inlay_hints
+                .get("hashes")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.hash_hints);
+
+            self.parameter_hints = inlay_hints
+                .get("parameters")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.parameter_hints);
+
+            self.value_hints = inlay_hints
+                .get("values")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.value_hints);
+
+            self.label_hints = inlay_hints
+                .get("labels")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.label_hints);
+        }
+
+        if let Some(save_actions) = value.get("save_actions").and_then(Value::as_object) {
+            self.save_trim_whitespace = save_actions
+                .get("trim_trailing_whitespace")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.save_trim_whitespace);
+
+            self.save_named_modes = save_actions
+                .get("named_modes")
+                .and_then(Value::as_bool)
+                .unwrap_or(self.save_named_modes);
+        }
+
+        if let Some(completion) = value.get("completion").and_then(Value::as_object) {
+            if let Some(characters) = completion
+                .get("trigger_characters")
+                .and_then(Value::as_array)
+            {
+                self.trigger_characters = characters
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect();
+            }
+        }
+
+        if let Some(severity) = value.get("severity").and_then(Value::as_object) {
+            self.overline_instruction_severity = severity
+                .get("overline_instruction")
+                .and_then(Value::as_str)
+                .and_then(parse_severity)
+                .unwrap_or(self.overline_instruction_severity);
+
+            self.overcolumn_instruction_severity = severity
+                .get("overcolumn_instruction")
+                .and_then(Value::as_str)
+                .and_then(parse_severity)
+                .unwrap_or(self.overcolumn_instruction_severity);
+        }
+
+        if let Some(lints) = value.get("lints").and_then(Value::as_object) {
+            self.lints = lints
+                .iter()
+                .filter_map(|(code, severity)| {
+                    let severity = severity.as_str()?;
+                    if severity.eq_ignore_ascii_case("off") {
+                        Some((code.to_uppercase(), None))
+                    } else {
+                        Some((code.to_uppercase(), Some(parse_severity(severity)?)))
+                    }
+                })
+                .collect();
+        }
+
+        if let Some(ruleset) = value.get("ruleset").and_then(Value::as_str) {
+            if instructions::RULESETS.contains_key(ruleset) {
+                self.ruleset = ruleset.to_string();
+            } else {
+                messages.push(format!("Unknown ruleset '{ruleset}'"));
+            }
+        }
+
+        if let Some(data_dir) = value.get("data_dir").and_then(Value::as_str) {
+            let data_dir = std::path::PathBuf::from(data_dir);
+            if self.data_dir.as_ref() != Some(&data_dir) {
+                match instructions::load_data_dir(&data_dir) {
+                    Ok(()) => self.data_dir = Some(data_dir),
+                    Err(err) => messages.push(format!("Failed to load custom data: {err}")),
+                }
+            }
         }
+
+        self.max_lines = value
+            .get("max_lines")
+            .and_then(Value::as_u64)
+            .map(|x| x as usize)
+            .unwrap_or(self.max_lines);
+
+        self.max_columns = value
+            .get("max_columns")
+            .and_then(Value::as_u64)
+            .map(|x| x as usize)
+            .unwrap_or(self.max_columns);
+
+        self.tab_width = value
+            .get("tab_width")
+            .and_then(Value::as_u64)
+            .map(|x| x as usize)
+            .unwrap_or(self.tab_width);
+
+        self.max_registers = value
+            .get("max_registers")
+            .and_then(Value::as_u64)
+            .map(|x| x as usize)
+            .unwrap_or(self.max_registers);
+
+        self.diagnostic_debounce_ms = value
+            .get("diagnostic_debounce_ms")
+            .and_then(Value::as_u64)
+            .unwrap_or(self.diagnostic_debounce_ms);
+
+        messages
+    }
+
+    /// Severity to report the given lint with, or `None` if it has been turned off.
+    fn lint_severity(&self, code: &str, default: DiagnosticSeverity) -> Option<DiagnosticSeverity> {
+        self.lints.get(code).copied().unwrap_or(Some(default))
     }
 }
 
+#[derive(Clone)]
 struct Backend {
     client: Client,
     files: Arc<RwLock<HashMap<Url, FileData>>>,
     config: Arc<RwLock<Configuration>>,
+    client_capabilities: Arc<RwLock<ClientCapabilities>>,
+    /// Diagnostics runs waiting for their debounce delay to pass, keyed by document.
+    pending_diagnostics: Arc<Mutex<HashMap<Url, JoinHandle<()>>>>,
 }
 
 #[async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        let mut utf8_supported = false;
-        if let Some(encodings) = params
-            .capabilities
-            .general
-            .and_then(|x| x.position_encodings)
-        {
-            for encoding in encodings {
-                if encoding == PositionEncodingKind::UTF8 {
-                    utf8_supported = true;
+        *self.client_capabilities.write().await = params.capabilities.clone();
+
+        #[allow(deprecated)]
+        let root = params
+            .root_uri
+            .as_ref()
+            .or_else(|| params.workspace_folders.as_ref()?.first().map(|x| &x.uri))
+            .and_then(|x| x.to_file_path().ok());
+        match root.as_deref().and_then(read_project_config) {
+            Some(Ok(value)) => {
+                let messages = self.config.write().await.update(&value);
+                for message in messages {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("{PROJECT_CONFIG_FILE}: {message}"),
+                        )
+                        .await;
+                }
+
+                let uris = self.files.read().await.keys().cloned().collect::<Vec<_>>();
+                for uri in uris {
+                    self.run_diagnostics(&uri).await;
                 }
             }
-            if !utf8_supported {
+            Some(Err(err)) => {
                 self.client
-                    .show_message(
-                        MessageType::WARNING,
-                        "Client does not support UTF-8. Non-ASCII characters will cause problems.",
-                    )
+                    .show_message(MessageType::ERROR, format!("{PROJECT_CONFIG_FILE}: {err}"))
+                    .await;
+            }
+            None => {}
+        }
+        if let Some(options) = &params.initialization_options {
+            let messages = self.config.write().await.update(options);
+            for message in messages {
+                self.client
+                    .show_message(MessageType::WARNING, message)
                     .await;
             }
         }
+        let trigger_characters = self.config.read().await.trigger_characters.clone();
+
+        let utf8_supported = supports_utf8(&params.capabilities);
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        will_save_wait_until: Some(true),
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                        ..Default::default()
+                    },
                 )),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["version".to_string()],
+                    commands: vec![
+                        "version".to_string(),
+                        "canonicalize".to_string(),
+                        "minify".to_string(),
+                        "stats".to_string(),
+                        "symbols".to_string(),
+                        "organize".to_string(),
+                    ],
                     work_done_progress_options: WorkDoneProgressOptions {
                         work_done_progress: None,
                     },
                 }),
                 inlay_hint_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                }),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                })),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
                 code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
                 signature_help_provider: Some(SignatureHelpOptions {
-                    trigger_characters: Some(vec![" ".to_string()]),
+                    trigger_characters: Some(trigger_characters.clone()),
                     retrigger_characters: None,
                     work_done_progress_options: WorkDoneProgressOptions::default(),
                 }),
                 position_encoding: utf8_supported.then_some(PositionEncodingKind::UTF8),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec![" ".to_string()]),
+                    resolve_provider: Some(true),
+                    trigger_characters: Some(trigger_characters),
                     completion_item: Some(CompletionOptionsCompletionItem {
                         label_details_support: Some(true),
                     }),
@@ -243,12 +1057,12 @@ impl LanguageServer for Backend {
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensOptions(
                         SemanticTokensOptions {
-                            range: Some(false),
+                            range: Some(true),
                             full: Some(SemanticTokensFullOptions::Bool(true)),
                             legend: {
                                 SemanticTokensLegend {
                                     token_types: SEMANTIC_SYMBOL_LEGEND.into(),
-                                    token_modifiers: vec![],
+                                    token_modifiers: SEMANTIC_MODIFIER_LEGEND.into(),
                                 }
                             },
                             ..Default::default()
@@ -259,7 +1073,7 @@ impl LanguageServer for Backend {
             },
             server_info: Some(ServerInfo {
                 name: "ic10lsp".to_string(),
-                version: Some("1.0.0".to_string()),
+                version: Some(format!("1.0.0+{}", self.config.read().await.ruleset)),
             }),
         })
     }
@@ -267,13 +1081,82 @@ impl LanguageServer for Backend {
     async fn initialized(&self, _params: InitializedParams) {}
 
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
-        if params.command == "version" {
-            self.client
-                .show_message(
-                    MessageType::INFO,
-                    concat!("IC10LSP Version: ", env!("CARGO_PKG_VERSION")),
-                )
-                .await;
+        match params.command.as_str() {
+            "version" => {
+                self.client
+                    .show_message(
+                        MessageType::INFO,
+                        concat!("IC10LSP Version: ", env!("CARGO_PKG_VERSION")),
+                    )
+                    .await;
+            }
+            "canonicalize" | "minify" | "stats" | "symbols" | "organize" => {
+                let Some(uri) = params
+                    .arguments
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(|x| Url::parse(x).ok())
+                else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "Expected a document URI",
+                    ));
+                };
+
+                let files = self.files.read().await;
+                let Some(file_data) = files.get(&uri) else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "Unknown document",
+                    ));
+                };
+                let document = &file_data.document_data;
+
+                if params.command == "stats" {
+                    let config = self.config.read().await;
+                    return Ok(Some(file_data.stats(&config)));
+                }
+                if params.command == "symbols" {
+                    return Ok(Some(file_data.symbols()));
+                }
+
+                let Some(ref tree) = document.tree else {
+                    return Err(tower_lsp::jsonrpc::Error::internal_error());
+                };
+
+                if params.command == "organize" {
+                    let Some(mut text) = formatting::organize(tree, document.content.as_bytes())
+                    else {
+                        return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                            "Cannot organize a program that jumps to line numbers",
+                        ));
+                    };
+                    if text == document.content {
+                        return Ok(Some(json!([])));
+                    }
+
+                    // Replace the whole document, as the client sees it
+                    let client_len =
+                        document.content.len() - usize::from(document.appended_newline);
+                    let client_content = &document.content[..client_len];
+                    if document.appended_newline {
+                        text.pop();
+                    }
+                    let end = LspPosition::new(
+                        client_content.matches('\n').count() as u32,
+                        client_content.rsplit('\n').next().unwrap().len() as u32,
+                    );
+                    let range = LspRange::new(LspPosition::new(0, 0), end);
+                    let edit = TextEdit::new(document.to_client_range(range), text);
+                    return Ok(Some(json!([edit])));
+                }
+
+                let text = if params.command == "minify" {
+                    formatting::minify(tree, document.content.as_bytes())
+                } else {
+                    formatting::canonicalize(tree, document.content.as_bytes())
+                };
+                return Ok(Some(Value::String(text)));
+            }
+            _ => {}
         }
         Ok(None)
     }
@@ -290,54 +1173,131 @@ impl LanguageServer for Backend {
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         for change in params.content_changes {
-            // Should only ever be one, because we are getting full updates
-            self.update_content(params.text_document.uri.clone(), change.text)
-                .await;
+            let Some(range) = change.range else {
+                self.update_content(params.text_document.uri.clone(), change.text)
+                    .await;
+                continue;
+            };
+
+            let mut files = self.files.write().await;
+            match files.get_mut(&params.text_document.uri) {
+                Some(file_data) => file_data.document_data.apply_change(range, &change.text),
+                None => {
+                    drop(files);
+                    self.update_content(params.text_document.uri.clone(), change.text)
+                        .await;
+                }
+            }
         }
-        self.run_diagnostics(&params.text_document.uri).await;
+        self.schedule_diagnostics(params.text_document.uri).await;
     }
 
-    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        // Clients with minimal sync may only send the text on save
+        if let Some(text) = params.text {
+            self.update_content(params.text_document.uri.clone(), text)
+                .await;
+        }
+        if let Some(handle) = self
+            .pending_diagnostics
+            .lock()
+            .await
+            .remove(&params.text_document.uri)
         {
-            let mut config = self.config.write().await;
-            let value = params.settings;
-
-            if let Some(warnings) = value.get("warnings").and_then(Value::as_object) {
-                config.warn_overline_comment = warnings
-                    .get("overline_comment")
-                    .and_then(Value::as_bool)
-                    .unwrap_or(config.warn_overline_comment);
-
-                config.warn_overcolumn_comment = warnings
-                    .get("overcolumn_comment")
-                    .and_then(Value::as_bool)
-                    .unwrap_or(config.warn_overcolumn_comment);
-            }
-
-            config.max_lines = value
-                .get("max_lines")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_lines);
-
-            config.max_columns = value
-                .get("max_columns")
-                .and_then(Value::as_u64)
-                .map(|x| x as usize)
-                .unwrap_or(config.max_columns);
+            handle.abort();
         }
+        self.run_diagnostics(&params.text_document.uri).await;
+    }
 
-        let uris = {
-            let files = self.files.read().await;
-            files.keys().map(Clone::clone).collect::<Vec<_>>()
-        };
-        for uri in uris {
-            self.run_diagnostics(&uri).await;
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if let Some(handle) = self.pending_diagnostics.lock().await.remove(&uri) {
+            handle.abort();
         }
+        self.files.write().await.remove(&uri);
+        self.client.publish_diagnostics(uri, Vec::new(), None).await;
     }
 
-    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
-        let mut ret = Vec::new();
+    async fn will_save_wait_until(
+        &self,
+        params: WillSaveTextDocumentParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let (trim_whitespace, named_modes) = {
+            let config = self.config.read().await;
+            (config.save_trim_whitespace, config.save_named_modes)
+        };
+
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        let document = &file_data.document_data;
+
+        // Only edits that leave the program doing exactly the same
+        let mut edits = Vec::new();
+        if trim_whitespace {
+            for (row, line) in document.content.split('\n').enumerate() {
+                // Keep the line ending of CRLF files
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                let trimmed = line.trim_end_matches([' ', '\t']);
+                if trimmed.len() != line.len() {
+                    let range = LspRange::new(
+                        LspPosition::new(row as u32, trimmed.len() as u32),
+                        LspPosition::new(row as u32, line.len() as u32),
+                    );
+                    edits.push(TextEdit::new(
+                        document.to_client_range(range),
+                        String::new(),
+                    ));
+                }
+            }
+        }
+        if named_modes {
+            for (node, _, replacement) in document.literal_modes() {
+                if let Ok(replacement) = replacement {
+                    edits.push(TextEdit::new(
+                        document.client_range(node),
+                        replacement.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok((!edits.is_empty()).then_some(edits))
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        {
+            let mut config = self.config.write().await;
+            for message in config.update(&params.settings) {
+                self.client
+                    .show_message(MessageType::WARNING, message)
+                    .await;
+            }
+        }
+
+        let uris = {
+            let files = self.files.read().await;
+            files.keys().map(Clone::clone).collect::<Vec<_>>()
+        };
+        for uri in uris {
+            self.run_diagnostics(&uri).await;
+        }
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let mut ret = Vec::new();
+
+        let (hash_hints, parameter_hints, value_hints, label_hints, ruleset) = {
+            let config = self.config.read().await;
+            (
+                config.hash_hints,
+                config.parameter_hints,
+                config.value_hints,
+                config.label_hints,
+                config.ruleset.clone(),
+            )
+        };
 
         let files = self.files.read().await;
         let uri = params.text_document.uri;
@@ -351,50 +1311,207 @@ impl LanguageServer for Backend {
             return Err(tower_lsp::jsonrpc::Error::internal_error());
         };
 
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(tree_sitter_ic10::language(), "(number)@x").unwrap();
-
-        for (capture, _) in cursor.captures(&query, tree.root_node(), document.content.as_bytes()) {
-            let node = capture.captures[0].node;
+        if parameter_hints {
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::INSTRUCTIONS;
+            let mut tree_cursor = tree.walk();
 
-            let range = Range::from(node.range());
-            if !range.contains(node.range().start_point.into())
-                || !range.contains(node.range().end_point.into())
+            cursor.set_point_range(
+                tree_sitter::Point::new(params.range.start.line as usize, 0)
+                    ..tree_sitter::Point::new(params.range.end.line as usize + 1, 0),
+            );
+            for (capture, _) in
+                cursor.captures(query, tree.root_node(), document.content.as_bytes())
             {
-                continue;
+                let node = capture.captures[0].node;
+                let Some(operation) = node
+                    .child_by_field_name("operation")
+                    .map(|x| x.utf8_text(document.content.as_bytes()).unwrap())
+                else {
+                    continue;
+                };
+                let Some(signature) = instructions::signature(&ruleset, operation) else {
+                    continue;
+                };
+
+                for (operand, parameter) in node
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .zip(signature.0.iter())
+                {
+                    ret.push(InlayHint {
+                        position: Position::from(operand.start_position()).into(),
+                        label: InlayHintLabel::String(format!("{parameter}:")),
+                        kind: Some(InlayHintKind::PARAMETER),
+                        text_edits: None,
+                        tooltip: None,
+                        padding_left: None,
+                        padding_right: Some(true),
+                        data: None,
+                    });
+                }
             }
+        }
 
-            let text = node.utf8_text(document.content.as_bytes()).unwrap();
-            if let Some(item_name) = instructions::HASH_NAME_LOOKUP.get(text) {
-                let Some(line_node) = node.find_parent("line") else {
+        if value_hints {
+            let type_data = &file_data.type_data;
+            let content = document.content.as_bytes();
+
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::IDENTIFIERS;
+
+            cursor.set_point_range(
+                tree_sitter::Point::new(params.range.start.line as usize, 0)
+                    ..tree_sitter::Point::new(params.range.end.line as usize + 1, 0),
+            );
+            for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+                let node = capture.captures[0].node;
+                if node.parent().map(|x| x.kind()) != Some("operand") {
+                    continue;
+                }
+
+                // Skip the name in the definition itself
+                let operation = node
+                    .find_parent("instruction")
+                    .and_then(|x| x.child_by_field_name("operation"))
+                    .map(|x| x.utf8_text(content).unwrap());
+                if matches!(operation, Some("define" | "alias" | "label")) {
+                    continue;
+                }
+
+                let name = node.utf8_text(content).unwrap();
+                let value = if let Some(definition) = type_data.defines.get(name) {
+                    definition.value.clone()
+                } else if let Some(definition) = type_data.aliases.get(name) {
+                    definition.value.to_string()
+                } else {
                     continue;
                 };
 
-                let endpos = if let Some(newline) =
-                    line_node.query("(newline)@x", document.content.as_bytes())
-                {
-                    Position::from(newline.range().start_point)
-                } else if let Some(instruction) =
-                    line_node.query("(instruction)@x", document.content.as_bytes())
+                ret.push(InlayHint {
+                    position: Position::from(node.end_position()).into(),
+                    label: InlayHintLabel::String(format!("= {value}")),
+                    kind: None,
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: Some(true),
+                    padding_right: None,
+                    data: None,
+                });
+            }
+        }
+
+        if label_hints {
+            let labels = &file_data.type_data.labels;
+            let content = document.content.as_bytes();
+
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::INSTRUCTIONS;
+            let mut tree_cursor = tree.walk();
+
+            cursor.set_point_range(
+                tree_sitter::Point::new(params.range.start.line as usize, 0)
+                    ..tree_sitter::Point::new(params.range.end.line as usize + 1, 0),
+            );
+            for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+                let node = capture.captures[0].node;
+                let Some(operation) = node.child_by_field_name("operation") else {
+                    continue;
+                };
+                if !instructions::BRANCH_INSTRUCTIONS
+                    .contains(operation.utf8_text(content).unwrap())
                 {
-                    Position::from(instruction.range().end_point)
-                } else {
-                    Position::from(node.range().end_point)
+                    continue;
+                }
+                let Some(target) = node
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .last()
+                    .and_then(|x| x.named_child(0))
+                    .filter(|x| x.kind() == "identifier")
+                else {
+                    continue;
+                };
+                let Some(label) = labels
+                    .get(target.utf8_text(content).unwrap())
+                    .filter(|x| x.source.is_none())
+                else {
+                    continue;
                 };
 
                 ret.push(InlayHint {
-                    position: endpos.into(),
-                    label: InlayHintLabel::String(item_name.to_string()),
-                    kind: Some(InlayHintKind::TYPE),
+                    position: Position::from(target.end_position()).into(),
+                    label: InlayHintLabel::String(format!("→ line {}", label.value + 1)),
+                    kind: None,
                     text_edits: None,
                     tooltip: None,
-                    padding_left: None,
+                    padding_left: Some(true),
                     padding_right: None,
                     data: None,
                 });
             }
         }
 
+        if hash_hints {
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::NUMBERS;
+            let range = Range(document.to_byte_range(params.range));
+
+            for (capture, _) in
+                cursor.captures(query, tree.root_node(), document.content.as_bytes())
+            {
+                let node = capture.captures[0].node;
+
+                if !range.contains(node.range().start_point.into())
+                    || !range.contains(node.range().end_point.into())
+                {
+                    continue;
+                }
+
+                let text = node.utf8_text(document.content.as_bytes()).unwrap();
+                // HASH("...") shows the computed value, whether or not it is a known prefab
+                let label = match node.child(0).filter(|x| x.kind() == "hash_preproc") {
+                    Some(hash_node) => hash_node
+                        .child_by_field_name("string")
+                        .map(|x| x.utf8_text(document.content.as_bytes()).unwrap())
+                        .map(|x| instructions::hash(x).to_string()),
+                    None => instructions::HASH_NAME_LOOKUP
+                        .get(text)
+                        .map(|x| x.to_string()),
+                };
+                if let Some(label) = label {
+                    let Some(line_node) = node.find_parent("line") else {
+                        continue;
+                    };
+
+                    let endpos = if let Some(newline) =
+                        line_node.query(&queries::NEWLINES, document.content.as_bytes())
+                    {
+                        Position::from(newline.range().start_point)
+                    } else if let Some(instruction) =
+                        line_node.query(&queries::INSTRUCTIONS, document.content.as_bytes())
+                    {
+                        Position::from(instruction.range().end_point)
+                    } else {
+                        Position::from(node.range().end_point)
+                    };
+
+                    ret.push(InlayHint {
+                        position: endpos.into(),
+                        label: InlayHintLabel::String(label),
+                        kind: Some(InlayHintKind::TYPE),
+                        text_edits: None,
+                        tooltip: None,
+                        padding_left: None,
+                        padding_right: None,
+                        data: None,
+                    });
+                }
+            }
+        }
+
+        for hint in &mut ret {
+            hint.position = document.to_client_position(hint.position);
+        }
+
         Ok(Some(ret))
     }
 
@@ -402,91 +1519,37 @@ impl LanguageServer for Backend {
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
-        let mut ret = Vec::new();
         let files = self.files.read().await;
         let uri = params.text_document.uri;
         let Some(file_data) = files.get(&uri) else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
-        let document = &file_data.document_data;
-
-        let Some(ref tree) = document.tree else {
-            return Err(tower_lsp::jsonrpc::Error::internal_error());
-        };
-
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(
-            tree_sitter_ic10::language(),
-            "(comment) @comment
-             (instruction (operation)@keyword)
-             (logictype)@string
-             (device)@preproc
-             (register)@macro
-             (number)@float
-             (identifier)@variable",
-        )
-        .unwrap();
-
-        let mut previous_line = 0u32;
-        let mut previous_col = 0u32;
-
-        let comment_idx = query.capture_index_for_name("comment").unwrap();
-        let keyword_idx = query.capture_index_for_name("keyword").unwrap();
-        let string_idx = query.capture_index_for_name("string").unwrap();
-        let preproc_idx = query.capture_index_for_name("preproc").unwrap();
-        let macro_idx = query.capture_index_for_name("macro").unwrap();
-        let float_idx = query.capture_index_for_name("float").unwrap();
-        let variable_idx = query.capture_index_for_name("variable").unwrap();
-
-        for (capture, _) in cursor.captures(&query, tree.root_node(), document.content.as_bytes()) {
-            let node = capture.captures[0].node;
-            let idx = capture.captures[0].index;
-            let start = node.range().start_point;
-
-            let delta_line = start.row as u32 - previous_line;
-            let delta_start = if delta_line == 0 {
-                start.column as u32 - previous_col
-            } else {
-                start.column as u32
-            };
 
-            let tokentype = {
-                if idx == comment_idx {
-                    SemanticTokenType::COMMENT
-                } else if idx == keyword_idx {
-                    SemanticTokenType::KEYWORD
-                } else if idx == string_idx {
-                    SemanticTokenType::STRING
-                } else if idx == preproc_idx {
-                    SemanticTokenType::FUNCTION
-                } else if idx == macro_idx {
-                    SemanticTokenType::MACRO
-                } else if idx == float_idx {
-                    SemanticTokenType::NUMBER
-                } else if idx == variable_idx {
-                    SemanticTokenType::VARIABLE
-                } else {
-                    continue;
-                }
-            };
+        let max_registers = self.config.read().await.max_registers;
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: self.semantic_tokens(&file_data.document_data, None, max_registers)?,
+        })))
+    }
 
-            ret.push(SemanticToken {
-                delta_line,
-                delta_start,
-                length: node.range().end_point.column as u32 - start.column as u32,
-                token_type: SEMANTIC_SYMBOL_LEGEND
-                    .iter()
-                    .position(|x| *x == tokentype)
-                    .unwrap() as u32,
-                token_modifiers_bitset: 0,
-            });
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let files = self.files.read().await;
+        let uri = params.text_document.uri;
+        let Some(file_data) = files.get(&uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
 
-            previous_line = start.row as u32;
-            previous_col = start.column as u32;
-        }
-        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+        let max_registers = self.config.read().await.max_registers;
+        Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
             result_id: None,
-            data: ret,
+            data: self.semantic_tokens(
+                &file_data.document_data,
+                Some(params.range),
+                max_registers,
+            )?,
         })))
     }
 
@@ -509,20 +1572,13 @@ impl LanguageServer for Backend {
         };
 
         let mut cursor = QueryCursor::new();
-        let query = Query::new(
-            tree_sitter_ic10::language(),
-            "(instruction (operation \"define\") . (operand)@name)@define
-            (instruction (operation \"alias\") . (operand)@name)@alias
-            (instruction (operation \"label\") . (operand)@name)@alias
-            (label (identifier)@name)@label",
-        )
-        .unwrap();
+        let query = &*queries::SYMBOLS;
         let define_idx = query.capture_index_for_name("define").unwrap();
         let alias_idx = query.capture_index_for_name("alias").unwrap();
         let label_idx = query.capture_index_for_name("label").unwrap();
         let name_idx = query.capture_index_for_name("name").unwrap();
 
-        let matches = cursor.matches(&query, tree.root_node(), document.content.as_bytes());
+        let matches = cursor.matches(query, tree.root_node(), document.content.as_bytes());
 
         for matched in matches {
             let main_match = {
@@ -559,18 +1615,102 @@ impl LanguageServer for Backend {
                 kind,
                 tags: None,
                 deprecated: Some(matched.pattern_index == 2),
-                location: Location::new(uri.clone(), Range::from(name_node.range()).into()),
+                location: Location::new(uri.clone(), document.client_range(name_node)),
                 container_name: None,
             });
         }
+
+        // Enum members have no definition, so their first use stands in for it
+        let mut enum_members = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::IDENTIFIERS;
+        for (capture, _) in cursor.captures(query, tree.root_node(), document.content.as_bytes()) {
+            let node = capture.captures[0].node;
+            let name = node.utf8_text(document.content.as_bytes()).unwrap();
+            if !instructions::ENUMS.contains(name)
+                || file_data.type_data.get_range(name).is_some()
+                || enum_members.contains(&name)
+            {
+                continue;
+            }
+            enum_members.push(name);
+
+            #[allow(deprecated)]
+            ret.push(SymbolInformation {
+                name: name.to_string(),
+                kind: SymbolKind::ENUM_MEMBER,
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri.clone(), document.client_range(node)),
+                container_name: name.split_once('.').map(|x| x.0.to_string()),
+            });
+        }
+
         Ok(Some(DocumentSymbolResponse::Flat(ret)))
     }
 
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let mut ret = Vec::new();
+        let query = params.query.to_lowercase();
+
+        let files = self.files.read().await;
+        for file_data in files.values() {
+            let document = &file_data.document_data;
+
+            for (name, range, kind) in file_data.type_data.symbols() {
+                if !name.to_lowercase().contains(&query) {
+                    continue;
+                }
+
+                #[allow(deprecated)]
+                ret.push(SymbolInformation {
+                    name: name.clone(),
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location::new(
+                        document.url.clone(),
+                        document.to_client_range(range.0),
+                    ),
+                    container_name: None,
+                });
+            }
+        }
+        ret.sort_by(|x, y| {
+            (&x.name, x.location.uri.as_str()).cmp(&(&y.name, y.location.uri.as_str()))
+        });
+
+        Ok(Some(ret))
+    }
+
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        fn instruction_completions(prefix: &str, completions: &mut Vec<CompletionItem>) {
+        fn instruction_completions(
+            prefix: &str,
+            ruleset: &str,
+            snippets: bool,
+            completions: &mut Vec<CompletionItem>,
+        ) {
             let start_entries = completions.len();
-            for (instruction, signature) in instructions::INSTRUCTIONS.entries() {
-                if instruction.starts_with(prefix) {
+            for instruction in instructions::instruction_names() {
+                let signature = instructions::signature(ruleset, instruction).unwrap();
+                if instruction.starts_with(prefix) && instructions::in_ruleset(ruleset, instruction)
+                {
+                    let snippet = (snippets && !signature.0.is_empty()).then(|| {
+                        let mut snippet = instruction.to_string();
+                        for (i, parameter) in signature.0.iter().enumerate() {
+                            let placeholder = parameter
+                                .to_string()
+                                .replace('\\', "\\\\")
+                                .replace('$', "\\$")
+                                .replace('}', "\\}");
+                            snippet.push_str(&format!(" ${{{}:{placeholder}}}", i + 1));
+                        }
+                        snippet
+                    });
+
                     completions.push(CompletionItem {
                         label: instruction.to_string(),
                         label_details: Some(CompletionItemLabelDetails {
@@ -578,10 +1718,12 @@ impl LanguageServer for Backend {
                             description: None,
                         }),
                         kind: Some(CompletionItemKind::FUNCTION),
-                        documentation: instructions::INSTRUCTION_DOCS
-                            .get(instruction)
-                            .map(|x| Documentation::String(x.to_string())),
-                        deprecated: Some(*instruction == "label"),
+                        deprecated: Some(
+                            instructions::DEPRECATED_INSTRUCTIONS.contains(instruction),
+                        ),
+                        insert_text_format: snippet.as_ref().map(|_| InsertTextFormat::SNIPPET),
+                        insert_text: snippet,
+                        data: Some(json!({"kind": COMPLETION_INSTRUCTION, "name": instruction})),
                         ..Default::default()
                     });
                 }
@@ -590,27 +1732,94 @@ impl LanguageServer for Backend {
             completions[start_entries..length].sort_by(|x, y| x.label.cmp(&y.label));
         }
 
+        /// Prefab names starting with `text`, followed by names merely containing it anywhere,
+        /// ignoring case.
+        fn hash_name_matches(text: &str) -> [Vec<&'static str>; 2] {
+            let needle = text.to_lowercase();
+            let mut prefix_matches = Vec::new();
+            let mut substring_matches = Vec::new();
+            for hash_name in &instructions::HASH_NAMES {
+                if hash_name.starts_with(text) {
+                    prefix_matches.push(*hash_name);
+                } else if !needle.is_empty() && hash_name.to_lowercase().contains(&needle) {
+                    substring_matches.push(*hash_name);
+                }
+            }
+            prefix_matches.sort();
+            substring_matches.sort();
+            [prefix_matches, substring_matches]
+        }
+
+        /// `device_logic_types` narrows logic types down to those of the targeted prefab. Logic types
+        /// that `instruction` cannot read or write are left out.
         fn param_completions_static(
+            instruction: &str,
             prefix: &str,
             detail: &str,
             param_type: &instructions::Union,
+            device_logic_types: Option<&[&str]>,
             completions: &mut Vec<CompletionItem>,
         ) {
-            use instructions::DataType;
+            use instructions::{DataType, LogicTypeAccess};
 
             let start_entries = completions.len();
 
             for typ in param_type.0 {
-                let map = match typ {
-                    DataType::LogicType => instructions::LOGIC_TYPE_DOCS,
-                    DataType::SlotLogicType => instructions::SLOT_TYPE_DOCS,
-                    DataType::BatchMode => instructions::BATCH_MODE_DOCS,
+                let (kind, names) = match typ {
+                    DataType::LogicType | DataType::SlotLogicType => {
+                        let kind = if *typ == DataType::LogicType {
+                            COMPLETION_LOGIC_TYPE
+                        } else {
+                            COMPLETION_SLOT_LOGIC_TYPE
+                        };
+                        let names = instructions::logic_types(*typ)
+                            .into_iter()
+                            .map(|x| x.0)
+                            .filter(|x| {
+                                *typ != DataType::LogicType
+                                    || device_logic_types.is_none_or(|types| types.contains(x))
+                            })
+                            // Leave out logic types that cannot be used in this direction
+                            .filter(|x| {
+                                *typ != DataType::LogicType
+                                    || !matches!(
+                                        (instruction, instructions::logic_type_access(x)),
+                                        ("s" | "sb" | "sbn", LogicTypeAccess::Read)
+                                            | ("l" | "lb" | "lbn", LogicTypeAccess::Write)
+                                    )
+                            })
+                            .collect::<Vec<_>>();
+                        (kind, names)
+                    }
+                    DataType::BatchMode => (
+                        COMPLETION_BATCH_MODE,
+                        instructions::BATCH_MODE_DOCS.keys().copied().collect(),
+                    ),
+                    DataType::ReagentMode => (
+                        COMPLETION_REAGENT_MODE,
+                        instructions::REAGENT_MODE_DOCS.keys().copied().collect(),
+                    ),
+                    DataType::SlotIndex => {
+                        for (index, description) in instructions::SLOT_INDICES {
+                            let label = index.to_string();
+                            if label.starts_with(prefix) {
+                                completions.push(CompletionItem {
+                                    label,
+                                    label_details: Some(CompletionItemLabelDetails {
+                                        description: description.map(str::to_string),
+                                        detail: Some(" slot".to_string()),
+                                    }),
+                                    kind: Some(CompletionItemKind::VALUE),
+                                    ..Default::default()
+                                });
+                            }
+                        }
+                        continue;
+                    }
                     _ => continue,
                 };
 
-                for entry in map.entries() {
-                    let name = *entry.0;
-                    let docs = *entry.1;
+                for name in names {
                     if name.starts_with(prefix) {
                         completions.push(CompletionItem {
                             label: name.to_string(),
@@ -619,7 +1828,7 @@ impl LanguageServer for Backend {
                                 detail: Some(detail.to_string()),
                             }),
                             kind: Some(CompletionItemKind::CONSTANT),
-                            documentation: Some(Documentation::String(docs.to_string())),
+                            data: Some(json!({"kind": kind, "name": name})),
                             ..Default::default()
                         });
                     }
@@ -629,16 +1838,147 @@ impl LanguageServer for Backend {
             completions[start_entries..length].sort_by(|x, y| x.label.cmp(&y.label));
         }
 
-        fn param_completions_dynamic<T>(
+        /// `max_registers` counts `sp` and `ra`, which are `r16` and `r17`.
+        fn register_completions(
             prefix: &str,
-            map: &HashMap<String, DefinitionData<T>>,
-            detail: &str,
             param_type: &instructions::Union,
+            max_registers: usize,
             completions: &mut Vec<CompletionItem>,
-        ) where
-            DefinitionData<T>: HasType,
-            T: std::fmt::Display,
-        {
+        ) {
+            if !param_type.match_type(instructions::DataType::Register) {
+                return;
+            }
+
+            let registers = (0..max_registers)
+                .map(|i| {
+                    let description = match i {
+                        16 => Some("stack pointer (sp)"),
+                        17 => Some("return address (ra)"),
+                        _ => None,
+                    };
+                    (format!("r{i}"), description)
+                })
+                .chain([
+                    ("sp".to_string(), Some("stack pointer (r16)")),
+                    ("ra".to_string(), Some("return address (r17)")),
+                ]);
+
+            for (i, (name, description)) in registers.enumerate() {
+                if name.starts_with(prefix) {
+                    completions.push(CompletionItem {
+                        label: name,
+                        label_details: Some(CompletionItemLabelDetails {
+                            description: description.map(str::to_string),
+                            detail: Some(" register".to_string()),
+                        }),
+                        kind: Some(CompletionItemKind::VARIABLE),
+                        sort_text: Some(format!("r{i:02}")),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        fn device_completions(
+            prefix: &str,
+            param_type: &instructions::Union,
+            max_registers: usize,
+            completions: &mut Vec<CompletionItem>,
+        ) {
+            if !param_type.match_type(instructions::DataType::Device) {
+                return;
+            }
+
+            let devices = (0..6)
+                .map(|i| (format!("d{i}"), format!("device pin {i}")))
+                .chain([("db".to_string(), "IC housing".to_string())])
+                .chain(
+                    (0..max_registers).map(|i| (format!("dr{i}"), format!("device pin in r{i}"))),
+                );
+
+            for (i, (name, description)) in devices.enumerate() {
+                if name.starts_with(prefix) {
+                    completions.push(CompletionItem {
+                        label: name,
+                        label_details: Some(CompletionItemLabelDetails {
+                            description: Some(description),
+                            detail: Some(" device".to_string()),
+                        }),
+                        kind: Some(CompletionItemKind::VARIABLE),
+                        sort_text: Some(format!("d{i:02}")),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        fn constant_completions(
+            prefix: &str,
+            param_type: &instructions::Union,
+            completions: &mut Vec<CompletionItem>,
+        ) {
+            if !param_type.match_type(instructions::DataType::Number) {
+                return;
+            }
+
+            let start_entries = completions.len();
+            for name in instructions::CONSTANTS.keys() {
+                if name.starts_with(prefix) {
+                    completions.push(CompletionItem {
+                        label: name.to_string(),
+                        label_details: Some(CompletionItemLabelDetails {
+                            description: None,
+                            detail: Some(" constant".to_string()),
+                        }),
+                        kind: Some(CompletionItemKind::CONSTANT),
+                        data: Some(json!({"kind": COMPLETION_CONSTANT, "name": name})),
+                        ..Default::default()
+                    });
+                }
+            }
+            let length = completions.len();
+            completions[start_entries..length].sort_by(|x, y| x.label.cmp(&y.label));
+        }
+
+        fn enum_completions(
+            prefix: &str,
+            param_type: &instructions::Union,
+            completions: &mut Vec<CompletionItem>,
+        ) {
+            if !param_type.match_type(instructions::DataType::Number) {
+                return;
+            }
+
+            let start_entries = completions.len();
+            for name in instructions::ENUMS.iter() {
+                if name.starts_with(prefix) {
+                    let value = instructions::ENUM_LOOKUP[name];
+                    completions.push(CompletionItem {
+                        label: name.to_string(),
+                        label_details: Some(CompletionItemLabelDetails {
+                            description: Some(value.to_string()),
+                            detail: Some(" enum".to_string()),
+                        }),
+                        kind: Some(CompletionItemKind::ENUM_MEMBER),
+                        data: Some(json!({"kind": COMPLETION_ENUM, "name": name})),
+                        ..Default::default()
+                    });
+                }
+            }
+            let length = completions.len();
+            completions[start_entries..length].sort_by(|x, y| x.label.cmp(&y.label));
+        }
+
+        fn param_completions_dynamic<T>(
+            prefix: &str,
+            map: &HashMap<String, DefinitionData<T>>,
+            detail: &str,
+            param_type: &instructions::Union,
+            completions: &mut Vec<CompletionItem>,
+        ) where
+            DefinitionData<T>: HasType,
+            T: std::fmt::Display,
+        {
             let start_entries = completions.len();
             for (identifier, value_data) in map.iter() {
                 let value = &value_data.value;
@@ -661,12 +2001,21 @@ impl LanguageServer for Backend {
         let mut ret = Vec::new();
 
         let uri = params.text_document_position.text_document.uri;
-        let position = {
-            let pos = params.text_document_position.position;
-            Position::from(tower_lsp::lsp_types::Position::new(
-                pos.line,
-                pos.character.saturating_sub(1),
-            ))
+
+        let snippet_support = self
+            .client_capabilities
+            .read()
+            .await
+            .text_document
+            .as_ref()
+            .and_then(|x| x.completion.as_ref())
+            .and_then(|x| x.completion_item.as_ref())
+            .and_then(|x| x.snippet_support)
+            .unwrap_or(false);
+
+        let (ruleset, max_registers) = {
+            let config = self.config.read().await;
+            (config.ruleset.clone(), config.max_registers)
         };
 
         let files = self.files.read().await;
@@ -680,35 +2029,77 @@ impl LanguageServer for Backend {
             return Err(tower_lsp::jsonrpc::Error::internal_error());
         };
 
+        let position = {
+            let pos = document.to_byte_position(params.text_document_position.position);
+            Position::from(tower_lsp::lsp_types::Position::new(
+                pos.line,
+                pos.character.saturating_sub(1),
+            ))
+        };
+
+        // Byte column of the cursor, not necessarily on a character boundary
+        let cursor = position.0.character as usize + 1;
+
         let Some(node) = self.node_at_position(position, tree) else {
             return Ok(None);
         };
 
+        // Only insert operand placeholders if the instruction does not have any operands yet
+        let has_operands = |node: Node| {
+            node.find_parent("instruction")
+                .is_some_and(|x| x.child_by_field_name("operand").is_some())
+        };
+
         if let Some(node) = node.find_parent("operation") {
             let text = node.utf8_text(document.content.as_bytes()).unwrap();
-            let cursor_pos = position.0.character as usize - node.start_position().column;
-            let prefix = &text[..cursor_pos + 1];
+            let Some(prefix) = text_before(text, node.start_position().column, cursor) else {
+                return Ok(None);
+            };
 
-            instruction_completions(prefix, &mut ret);
+            instruction_completions(
+                prefix,
+                &ruleset,
+                snippet_support && !has_operands(node),
+                &mut ret,
+            );
         } else if let Some(node) = node.find_parent("invalid_instruction") {
             let text = node.utf8_text(document.content.as_bytes()).unwrap();
-            let cursor_pos = position.0.character as usize - node.start_position().column;
-            let prefix = &text[..cursor_pos + 1];
+            let Some(prefix) = text_before(text, node.start_position().column, cursor) else {
+                return Ok(None);
+            };
 
-            instruction_completions(prefix, &mut ret);
+            instruction_completions(
+                prefix,
+                &ruleset,
+                snippet_support && !has_operands(node),
+                &mut ret,
+            );
         } else if let Some(line_node) = node.find_parent("line") {
             let text = line_node.utf8_text(document.content.as_bytes()).unwrap();
-            let cursor_pos = position.0.character as usize - line_node.start_position().column;
-            let global_prefix = &text[..cursor_pos as usize + 1];
+            let Some(global_prefix) = text_before(text, line_node.start_position().column, cursor)
+            else {
+                return Ok(None);
+            };
 
             if global_prefix.chars().all(char::is_whitespace) {
-                instruction_completions("", &mut ret);
+                let has_instruction = line_node
+                    .query(&queries::INSTRUCTIONS, document.content.as_bytes())
+                    .is_some();
+                instruction_completions(
+                    "",
+                    &ruleset,
+                    snippet_support && !has_instruction,
+                    &mut ret,
+                );
             } else {
                 let Some(line_node) = node.find_parent("line") else {
                     return Ok(None);
                 };
 
-                let Some(instruction_node) = line_node.query("(instruction)@x", file_data.document_data.content.as_bytes()) else {
+                let Some(instruction_node) = line_node.query(
+                    &queries::INSTRUCTIONS,
+                    file_data.document_data.content.as_bytes(),
+                ) else {
                     return Ok(None);
                 };
 
@@ -720,24 +2111,24 @@ impl LanguageServer for Backend {
                     .utf8_text(file_data.document_data.content.as_bytes())
                     .unwrap();
 
-                let (current_param, operand_node) =
-                    get_current_parameter(instruction_node, position.0.character as usize);
+                let (current_param, operand_node) = get_current_parameter(instruction_node, cursor);
 
                 let operand_text = operand_node
                     .map(|node| node.utf8_text(document.content.as_bytes()).unwrap())
                     .unwrap_or("");
 
-                let prefix = {
-                    if let Some(operand_node) = operand_node {
-                        let cursor_pos = (position.0.character as usize)
-                            .saturating_sub(operand_node.start_position().column);
-                        &operand_text[..(cursor_pos + 1).min(operand_text.len())]
-                    } else {
-                        ""
+                let prefix = match operand_node {
+                    Some(operand_node) => {
+                        let start = operand_node.start_position().column;
+                        let Some(prefix) = text_before(operand_text, start, cursor) else {
+                            return Ok(None);
+                        };
+                        prefix
                     }
+                    None => "",
                 };
 
-                let Some(signature) = instructions::INSTRUCTIONS.get(text) else {
+                let Some(signature) = instructions::signature(&ruleset, text) else {
                     return Ok(None);
                 };
 
@@ -746,39 +2137,115 @@ impl LanguageServer for Backend {
                 };
 
                 if let Some(preproc_string_node) = instruction_node.query(
-                    "(preproc_string)@x",
+                    &queries::PREPROC_STRINGS,
                     file_data.document_data.content.as_bytes(),
                 ) {
                     let string_text = preproc_string_node
                         .utf8_text(file_data.document_data.content.as_bytes())
                         .unwrap();
 
-                    let start_entries = ret.len();
+                    let edit_range = {
+                        let mut edit_range = Range::from(preproc_string_node.range());
+                        edit_range.0.end.character -= 1;
+                        file_data.document_data.to_client_range(edit_range.into())
+                    };
 
-                    for hash_name in &instructions::HASH_NAMES {
-                        if hash_name.starts_with(string_text) {
+                    for (rank, matches) in hash_name_matches(string_text).iter().enumerate() {
+                        for hash_name in matches {
                             ret.push(CompletionItem {
                                 label: hash_name.to_string(),
+                                sort_text: Some(format!("{rank}{hash_name}")),
+                                filter_text: Some(string_text.to_string()),
                                 text_edit: Some(CompletionTextEdit::Edit(TextEdit {
-                                    range: {
-                                        let mut edit_range =
-                                            Range::from(preproc_string_node.range());
-                                        edit_range.0.end.character -= 1;
-                                        edit_range.into()
-                                    },
+                                    range: edit_range,
                                     new_text: hash_name.to_string(),
                                 })),
                                 ..Default::default()
                             });
                         }
                     }
-                    let length = ret.len();
-                    ret[start_entries..length].sort_by(|x, y| x.label.cmp(&y.label));
                 };
 
-                if !text.starts_with("br") && text.starts_with("b") || text == "j" || text == "jal"
+                // Prefab hash operand outside of a string: insert the hash itself, as the game
+                // hashes prefab names rather than the display names matched here. Only offered
+                // once something has been typed, as there are far too many prefabs.
+                let in_string = operand_node.is_some_and(|x| {
+                    x.query(&queries::PREPROC_STRINGS, document.content.as_bytes())
+                        .is_some()
+                });
+                if instructions::prefab_hash_parameter(text) == Some(current_param)
+                    && !in_string
+                    && !prefix.is_empty()
+                    && !prefix.contains('(')
+                {
+                    let edit_range = {
+                        let start = document.to_client_position(
+                            Position::from(operand_node.unwrap().start_position()).into(),
+                        );
+                        LspRange::new(start, params.text_document_position.position)
+                    };
+
+                    for (rank, matches) in hash_name_matches(prefix).iter().enumerate() {
+                        for hash_name in matches {
+                            let hash = instructions::HASH_LOOKUP[hash_name].to_string();
+                            ret.push(CompletionItem {
+                                label: hash_name.to_string(),
+                                label_details: Some(CompletionItemLabelDetails {
+                                    detail: None,
+                                    description: Some(hash.clone()),
+                                }),
+                                kind: Some(CompletionItemKind::VALUE),
+                                sort_text: Some(format!("{rank}{hash_name}")),
+                                filter_text: Some(prefix.to_string()),
+                                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                                    range: edit_range,
+                                    new_text: hash,
+                                })),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+
+                // Logic types of the prefab a batch instruction targets, when it can be resolved
+                let device_logic_types = instructions::prefab_hash_parameter(text)
+                    .filter(|&index| index < current_param)
+                    .and_then(|index| {
+                        let mut cursor = instruction_node.walk();
+                        let operand = instruction_node
+                            .children_by_field_name("operand", &mut cursor)
+                            .nth(index)?;
+                        let operand = operand.utf8_text(document.content.as_bytes()).unwrap();
+                        let value = match file_data.type_data.defines.get(operand) {
+                            Some(define) => constant_value(&define.value),
+                            None => constant_value(operand),
+                        }?;
+                        instructions::device_logic_types(value as i32)
+                    });
+                let device_logic_types = device_logic_types.as_deref();
+
+                if matches!(text, "alias" | "define" | "label") {
+                    // The name is a new one, and the value cannot refer to other definitions
+                    register_completions(prefix, param_type, max_registers, &mut ret);
+                    device_completions(prefix, param_type, max_registers, &mut ret);
+                    constant_completions(prefix, param_type, &mut ret);
+                    enum_completions(prefix, param_type, &mut ret);
+                } else if !text.starts_with("br") && text.starts_with("b")
+                    || text == "j"
+                    || text == "jal"
                 {
-                    param_completions_static(prefix, "", param_type, &mut ret);
+                    param_completions_static(
+                        text,
+                        prefix,
+                        "",
+                        param_type,
+                        device_logic_types,
+                        &mut ret,
+                    );
+                    register_completions(prefix, param_type, max_registers, &mut ret);
+                    device_completions(prefix, param_type, max_registers, &mut ret);
+                    constant_completions(prefix, param_type, &mut ret);
+                    enum_completions(prefix, param_type, &mut ret);
 
                     param_completions_dynamic(
                         prefix,
@@ -804,7 +2271,18 @@ impl LanguageServer for Backend {
                         &mut ret,
                     );
                 } else {
-                    param_completions_static(prefix, "", param_type, &mut ret);
+                    param_completions_static(
+                        text,
+                        prefix,
+                        "",
+                        param_type,
+                        device_logic_types,
+                        &mut ret,
+                    );
+                    register_completions(prefix, param_type, max_registers, &mut ret);
+                    device_completions(prefix, param_type, max_registers, &mut ret);
+                    constant_completions(prefix, param_type, &mut ret);
+                    enum_completions(prefix, param_type, &mut ret);
 
                     param_completions_dynamic(
                         prefix,
@@ -836,9 +2314,35 @@ impl LanguageServer for Backend {
         Ok(Some(CompletionResponse::Array(ret)))
     }
 
+    async fn completion_resolve(&self, mut item: CompletionItem) -> Result<CompletionItem> {
+        let Some(data) = item.data.as_ref() else {
+            return Ok(item);
+        };
+        let (Some(kind), Some(name)) = (
+            data.get("kind").and_then(Value::as_str),
+            data.get("name").and_then(Value::as_str),
+        ) else {
+            return Ok(item);
+        };
+
+        let docs = match kind {
+            COMPLETION_INSTRUCTION => instructions::instruction_docs(name),
+            COMPLETION_LOGIC_TYPE => instructions::logic_type_docs(name),
+            COMPLETION_SLOT_LOGIC_TYPE => instructions::slot_type_docs(name),
+            COMPLETION_BATCH_MODE => instructions::BATCH_MODE_DOCS.get(name).copied(),
+            COMPLETION_REAGENT_MODE => instructions::REAGENT_MODE_DOCS.get(name).copied(),
+            COMPLETION_ENUM => instructions::ENUM_DOCS.get(name).copied(),
+            COMPLETION_CONSTANT => instructions::CONSTANTS_DOCS.get(name).copied(),
+            _ => None,
+        };
+        item.documentation = docs.map(|x| Documentation::String(x.to_string()));
+
+        Ok(item)
+    }
+
     async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
         let uri = params.text_document_position_params.text_document.uri;
-        let position = Position::from(params.text_document_position_params.position);
+        let ruleset = self.config.read().await.ruleset.clone();
 
         let files = self.files.read().await;
         let Some(file_data) = files.get(&uri) else {
@@ -846,6 +2350,9 @@ impl LanguageServer for Backend {
         };
 
         let document = &file_data.document_data;
+        let position = Position::from(
+            document.to_byte_position(params.text_document_position_params.position),
+        );
 
         let Some(ref tree) = document.tree else {
             return Err(tower_lsp::jsonrpc::Error::internal_error());
@@ -859,7 +2366,9 @@ impl LanguageServer for Backend {
             return Ok(None);
         };
 
-        let Some(instruction_node) = line_node.query("(instruction)@x", document.content.as_bytes()) else {
+        let Some(instruction_node) =
+            line_node.query(&queries::INSTRUCTIONS, document.content.as_bytes())
+        else {
             return Ok(None);
         };
 
@@ -871,43 +2380,70 @@ impl LanguageServer for Backend {
             .utf8_text(document.content.as_bytes())
             .unwrap();
 
-        let (current_param, _) = get_current_parameter(
-            instruction_node,
-            position.0.character.saturating_sub(1) as usize,
-        );
+        let (current_param, _) =
+            get_current_parameter(instruction_node, position.0.character as usize);
 
-        let Some(signature) = instructions::INSTRUCTIONS.get(text) else {
+        let signatures = instructions::signatures(&ruleset, text);
+        if signatures.is_empty() {
             return Ok(None);
+        }
+
+        // Choose the first form that all operands typed so far fit into
+        let operand_types = {
+            let mut cursor = instruction_node.walk();
+            instruction_node
+                .children_by_field_name("operand", &mut cursor)
+                .take(current_param + 1)
+                .map(|x| {
+                    file_data
+                        .type_data
+                        .operand_type(x, document.content.as_bytes())
+                })
+                .collect::<Vec<_>>()
         };
+        let active_signature = signatures
+            .iter()
+            .position(|signature| {
+                signature
+                    .0
+                    .iter()
+                    .zip(&operand_types)
+                    .all(|(parameter, typ)| {
+                        parameter.match_type(instructions::DataType::Name)
+                            || typ.is_none_or(|typ| parameter.match_type(typ))
+                    })
+            })
+            .unwrap_or(0);
 
-        let mut label = text.to_string();
-        let mut parameters = Vec::new();
+        let signatures = signatures
+            .iter()
+            .map(|signature| {
+                let mut label = text.to_string();
+                let mut parameters = Vec::new();
 
-        for parameter in signature.0 {
-            let start = label.len() as u32 + 1;
-            label.push_str(&format!(" {parameter}"));
-            let end = label.len() as u32 + 0;
-            parameters.push([start, end]);
-        }
+                for parameter in signature.0 {
+                    let start = label.len() as u32 + 1;
+                    label.push_str(&format!(" {parameter}"));
+                    let end = label.len() as u32;
+                    parameters.push(ParameterInformation {
+                        label: ParameterLabel::LabelOffsets([start, end]),
+                        documentation: None,
+                    });
+                }
+
+                SignatureInformation {
+                    label,
+                    documentation: instructions::instruction_docs(text)
+                        .map(|x| Documentation::String(x.to_string())),
+                    parameters: Some(parameters),
+                    active_parameter: Some(current_param as u32),
+                }
+            })
+            .collect();
 
         Ok(Some(SignatureHelp {
-            signatures: vec![SignatureInformation {
-                label: label,
-                documentation: instructions::INSTRUCTION_DOCS
-                    .get(text)
-                    .map(|x| Documentation::String(x.to_string())),
-                parameters: Some(
-                    parameters
-                        .iter()
-                        .map(|offset| ParameterInformation {
-                            label: ParameterLabel::LabelOffsets(offset.to_owned()),
-                            documentation: None,
-                        })
-                        .collect(),
-                ),
-                active_parameter: Some(current_param as u32),
-            }],
-            active_signature: None,
+            signatures,
+            active_signature: Some(active_signature as u32),
             active_parameter: None,
         }))
     }
@@ -918,6 +2454,7 @@ impl LanguageServer for Backend {
     ) -> Result<Option<Vec<CodeActionOrCommand>>> {
         let mut ret = Vec::new();
 
+        let max_registers = self.config.read().await.max_registers;
         let files = self.files.read().await;
         let Some(file_data) = files.get(&params.text_document.uri) else {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
@@ -930,16 +2467,21 @@ impl LanguageServer for Backend {
             return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
 
-        let Some(node) = self.node_at_range(params.range.into(), tree) else {
+        let Some(node) = self.node_at_range(document.to_byte_range(params.range).into(), tree)
+        else {
             return Ok(None);
         };
 
         'diagnostics: for diagnostic in params.context.diagnostics {
-            let Some(line_node) = node.find_parent("line") else { continue 'diagnostics; };
+            let Some(line_node) = node.find_parent("line") else {
+                continue 'diagnostics;
+            };
 
-            let Some(NumberOrString::String(code)) = diagnostic.code.clone() else {continue;};
+            let Some(NumberOrString::String(code)) = diagnostic.code.clone() else {
+                continue;
+            };
             match code.as_str() {
-                LINT_NUMBER_BATCH_MODE => {
+                LINT_NUMBER_BATCH_MODE | LINT_NUMBER_REAGENT_MODE => {
                     let replacement = diagnostic.data.as_ref().unwrap().as_str().unwrap();
 
                     let edit = TextEdit::new(diagnostic.range, replacement.to_string());
@@ -957,46 +2499,19 @@ impl LanguageServer for Backend {
                     }));
                 }
                 LINT_ABSOLUTE_JUMP => {
-                    const REPLACEMENTS: phf::Map<&'static str, &'static str> = phf::phf_map! {
-                        "bdns" => "brdns",
-                        "bdse" => "brdse",
-                        "bap" => "brap",
-                        "bapz" => "brapz",
-                        "beq" => "breq",
-                        "beqz" => "breqz",
-                        "bge" => "brge",
-                        "bgez" => "brgez",
-                        "bgt" => "brgt",
-                        "bgtz" => "brgtz",
-                        "ble" => "brle",
-                        "blez" => "brlez",
-                        "blt" => "brlt",
-                        "bltz" => "brltz",
-                        "bna" => "brna",
-                        "bnaz" => "brnaz",
-                        "bne" => "brne",
-                        "bnez" => "brnez",
-                        "j" => "jr",
-                    };
-
-                    if let Some(node) =
-                        line_node.query("(instruction (operation)@x)", document.content.as_bytes())
+                    if let Some(instruction) =
+                        line_node.query(&queries::INSTRUCTIONS, document.content.as_bytes())
                     {
-                        let text = node.utf8_text(document.content.as_bytes()).unwrap();
-
-                        if let Some(replacement) = REPLACEMENTS.get(text) {
-                            let edit = TextEdit::new(
-                                Range::from(node.range()).into(),
-                                replacement.to_string(),
-                            );
-
+                        if let Some((replacement, edits)) =
+                            relative_jump_edits(instruction, document)
+                        {
                             ret.push(CodeActionOrCommand::CodeAction(CodeAction {
                                 title: format!("Replace with {replacement}"),
                                 kind: Some(CodeActionKind::QUICKFIX),
                                 diagnostics: Some(vec![diagnostic]),
                                 edit: Some(WorkspaceEdit::new(HashMap::from([(
                                     uri.clone(),
-                                    vec![edit],
+                                    edits,
                                 )]))),
                                 command: None,
                                 is_preferred: Some(true),
@@ -1008,10 +2523,162 @@ impl LanguageServer for Backend {
                         break;
                     }
                 }
+                ERROR_UNKNOWN_IDENTIFIER => {
+                    let Some(data) = diagnostic.data.as_ref() else {
+                        continue;
+                    };
+                    let Some(name) = data.get("name").and_then(Value::as_str) else {
+                        continue;
+                    };
+                    let accepts = |key: &str| data.get(key).and_then(Value::as_bool) == Some(true);
+
+                    let (used_registers, used_devices) =
+                        used_registers(tree, document.content.as_bytes());
+
+                    let mut definitions = Vec::new();
+                    if accepts("register") {
+                        // sp and ra are not free for other uses
+                        let free_register = (0..max_registers)
+                            .filter(|x| !matches!(x, 16 | 17))
+                            .find(|x| !used_registers.contains(x));
+                        if let Some(register) = free_register {
+                            definitions.push(format!("alias {name} r{register}"));
+                        }
+                    }
+                    if accepts("device") {
+                        if let Some(device) = (0..6).find(|x| !used_devices.contains(x)) {
+                            definitions.push(format!("alias {name} d{device}"));
+                        }
+                    }
+                    if accepts("number") {
+                        definitions.push(format!("define {name} 0"));
+                    }
+
+                    for (i, definition) in definitions.into_iter().enumerate() {
+                        let edit = TextEdit::new(
+                            LspRange::new(LspPosition::new(0, 0), LspPosition::new(0, 0)),
+                            format!("{definition}\n"),
+                        );
+
+                        ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                            title: format!("Add `{definition}`"),
+                            kind: Some(CodeActionKind::QUICKFIX),
+                            diagnostics: Some(vec![diagnostic.clone()]),
+                            edit: Some(WorkspaceEdit::new(HashMap::from([(
+                                uri.clone(),
+                                vec![edit],
+                            )]))),
+                            is_preferred: Some(i == 0),
+                            ..Default::default()
+                        }));
+                    }
+                }
+                LINT_UNSUPPORTED_INSTRUCTION => {
+                    if let Some(replacement) = diagnostic.data.as_ref().and_then(Value::as_str) {
+                        let edit = TextEdit::new(diagnostic.range, replacement.to_string());
+
+                        ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                            title: format!("Replace with {replacement}"),
+                            kind: Some(CodeActionKind::QUICKFIX),
+                            diagnostics: Some(vec![diagnostic.clone()]),
+                            edit: Some(WorkspaceEdit::new(HashMap::from([(
+                                uri.clone(),
+                                vec![edit],
+                            )]))),
+                            is_preferred: Some(true),
+                            ..Default::default()
+                        }));
+                    }
+
+                    let row = line_node.start_position().row as u32;
+                    let edit = TextEdit::new(
+                        LspRange::new(LspPosition::new(row, 0), LspPosition::new(row + 1, 0)),
+                        String::new(),
+                    );
+
+                    ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: "Remove line".to_string(),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic]),
+                        edit: Some(WorkspaceEdit::new(HashMap::from([(
+                            uri.clone(),
+                            vec![edit],
+                        )]))),
+                        ..Default::default()
+                    }));
+                }
+                LINT_UNUSED_DEFINITION => {
+                    let row = line_node.start_position().row as u32;
+                    let edit = TextEdit::new(
+                        LspRange::new(LspPosition::new(row, 0), LspPosition::new(row + 1, 0)),
+                        String::new(),
+                    );
+
+                    ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: "Remove unused definition".to_string(),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic]),
+                        edit: Some(WorkspaceEdit::new(HashMap::from([(
+                            uri.clone(),
+                            vec![edit],
+                        )]))),
+                        is_preferred: Some(false),
+                        ..Default::default()
+                    }));
+                }
                 _ => {}
             }
         }
 
+        // Only instructions with an absolute counterpart are rewritten, so relative jumps are left
+        // alone
+        {
+            let mut edits = Vec::new();
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::NUMBER_OPERANDS;
+            for (capture, _) in
+                cursor.captures(query, tree.root_node(), document.content.as_bytes())
+            {
+                if let Some((_, instruction_edits)) =
+                    relative_jump_edits(capture.captures[0].node, document)
+                {
+                    edits.extend(instruction_edits);
+                }
+            }
+            if !edits.is_empty() {
+                ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Convert all absolute jumps to relative".to_string(),
+                    kind: Some(CodeActionKind::SOURCE),
+                    edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+                    ..Default::default()
+                }));
+            }
+        }
+
+        if let Some(hash_node) = node.find_parent("hash_preproc") {
+            let string = hash_node
+                .child_by_field_name("string")
+                .map(|x| x.utf8_text(document.content.as_bytes()).unwrap())
+                .unwrap_or("");
+            let hash = instructions::hash(string);
+
+            if instructions::HASH_NAMES.contains(string)
+                || instructions::HASH_NAME_LOOKUP.contains_key(&hash.to_string())
+            {
+                let edit = TextEdit::new(document.client_range(hash_node), hash.to_string());
+
+                ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Replace with numeric hash".to_string(),
+                    kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                    edit: Some(WorkspaceEdit::new(HashMap::from([(
+                        uri.clone(),
+                        vec![edit],
+                    )]))),
+                    ..Default::default()
+                }));
+            }
+        }
+
         Ok(Some(ret))
     }
 
@@ -1020,23 +2687,40 @@ impl LanguageServer for Backend {
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
         let files = self.files.read().await;
-        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri) else {
-            return Err(tower_lsp::jsonrpc::Error::internal_error());
+        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri)
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
         let document = &file_data.document_data;
         let type_data = &file_data.type_data;
 
-        let position = params.text_document_position_params.position;
+        let position = document.to_byte_position(params.text_document_position_params.position);
 
         if let Some(tree) = document.tree.as_ref() {
-            if let Some(node) = self.node_at_position(position.into(), tree) {
+            if let Some(mut node) = self.node_at_position(position.into(), tree) {
+                // A cursor right after the identifier lands on whatever follows it
+                if node.kind() != "identifier" && node.kind() != "operand" && position.character > 0
+                {
+                    let before = LspPosition::new(position.line, position.character - 1);
+                    if let Some(previous) = self.node_at_position(before.into(), tree) {
+                        node = previous;
+                    }
+                }
+                // Jump targets are wrapped in an operand node
+                if node.kind() == "operand" {
+                    node = node.named_child(0).unwrap_or(node);
+                }
                 if node.kind() == "identifier" {
                     let name = node.utf8_text(document.content.as_bytes()).unwrap();
                     if let Some(range) = type_data.get_range(name) {
-                        return Ok(Some(GotoDefinitionResponse::Scalar(Location::new(
-                            document.url.clone(),
-                            range.0,
-                        ))));
+                        let location = match type_data.get_source(name) {
+                            Some(url) => Location::new(url.clone(), range.0),
+                            None => Location::new(
+                                document.url.clone(),
+                                document.to_client_range(range.0),
+                            ),
+                        };
+                        return Ok(Some(GotoDefinitionResponse::Scalar(location)));
                     }
                 }
             }
@@ -1044,962 +2728,6403 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 
-    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
         let files = self.files.read().await;
-        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri) else {
-            return Err(tower_lsp::jsonrpc::Error::internal_error());
+        let Some(file_data) = files.get(&params.text_document_position.text_document.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
         let document = &file_data.document_data;
         let type_data = &file_data.type_data;
 
-        let position = params.text_document_position_params.position;
+        let position = document.to_byte_position(params.text_document_position.position);
 
         let Some(tree) = document.tree.as_ref() else {
             return Ok(None);
         };
-        let root = tree.root_node();
-        let Some(node) = root.named_descendant_for_point_range(
-            tree_sitter::Point::new(position.line as usize, position.character as usize),
-            tree_sitter::Point::new(position.line as usize, position.character as usize + 1),
-        ) else {
+        let Some(node) = self.node_at_position(position.into(), tree) else {
             return Ok(None);
         };
+        if node.kind() != "identifier" {
+            return Ok(None);
+        }
 
         let name = node.utf8_text(document.content.as_bytes()).unwrap();
-        match node.kind() {
-            "identifier" => {
-                if let Some(definition_data) = type_data.defines.get(name) {
-                    return Ok(Some(Hover {
-                        contents: HoverContents::Array(vec![MarkedString::LanguageString(
-                            LanguageString {
-                                language: "ic10".to_string(),
-                                value: format!("define {} {}", name, definition_data.value),
-                            },
-                        )]),
-                        range: Some(Range::from(node.range()).into()),
-                    }));
-                }
-                if let Some(definition_data) = type_data.aliases.get(name) {
-                    return Ok(Some(Hover {
-                        contents: HoverContents::Array(vec![MarkedString::LanguageString(
-                            LanguageString {
-                                language: "ic10".to_string(),
-                                value: format!("alias {} {}", name, definition_data.value),
-                            },
-                        )]),
-                        range: Some(Range::from(node.range()).into()),
-                    }));
-                }
-                if let Some(definition_data) = type_data.labels.get(name) {
-                    return Ok(Some(Hover {
-                        contents: HoverContents::Scalar(MarkedString::String(format!(
-                            "Label on line {}",
-                            definition_data.value + 1
-                        ))),
-                        range: Some(Range::from(node.range()).into()),
-                    }));
-                }
+        let declaration = type_data
+            .get_range(name)
+            .filter(|_| type_data.get_source(name).is_none());
+
+        let mut ret = Vec::new();
+        for (_, node) in document.identifiers().into_iter().filter(|x| x.0 == name) {
+            let range = Range::from(node.range());
+            if !params.context.include_declaration
+                && declaration.as_ref().is_some_and(|x| x.0 == range.0)
+            {
+                continue;
             }
-            "operation" => {
-                let Some(signature) =  instructions::INSTRUCTIONS.get(name) else {
-                    return Ok(None);
-                };
-                let mut content = name.to_string();
-                for parameter in signature.0 {
-                    content.push_str(&format!(" {parameter}"));
-                }
-                return Ok(Some(Hover {
-                    contents: HoverContents::Array({
-                        let mut v = Vec::new();
-                        v.push(MarkedString::String(content));
-                        if let Some(doc) = instructions::INSTRUCTION_DOCS.get(name) {
-                            v.push(MarkedString::String(doc.to_string()));
-                        }
-                        v
-                    }),
-                    range: Some(Range::from(node.range()).into()),
-                }));
+            ret.push(Location::new(
+                document.url.clone(),
+                document.to_client_range(range.into()),
+            ));
+        }
+
+        Ok(Some(ret))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri)
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let document = &file_data.document_data;
+
+        let position = document.to_byte_position(params.text_document_position_params.position);
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let Some(mut node) = self.node_at_position(position.into(), tree) else {
+            return Ok(None);
+        };
+        if node.kind() == "operand" {
+            node = node.named_child(0).unwrap_or(node);
+        }
+        if node.kind() != "identifier" {
+            return Ok(None);
+        }
+
+        let name = node.utf8_text(document.content.as_bytes()).unwrap();
+        Ok(file_data.call_hierarchy_item(name).map(|x| vec![x]))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.item.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let regions = file_data.label_regions();
+
+        let mut ret: Vec<CallHierarchyIncomingCall> = Vec::new();
+        for (row, target, range) in file_data.label_jumps() {
+            if target != params.item.name {
+                continue;
             }
-            "logictype" => {
-                let Some(instruction_node) = node.find_parent("instruction") else {
-                    return Ok(None);
-                };
+            // Jumps before the first label are not part of any label
+            let Some(caller) = regions.iter().rev().find(|x| x.2.start.line <= row) else {
+                continue;
+            };
+            match ret.iter_mut().find(|x| x.from.name == *caller.0) {
+                Some(call) => call.from_ranges.push(range),
+                None => ret.push(CallHierarchyIncomingCall {
+                    from: file_data.call_hierarchy_item(caller.0).unwrap(),
+                    from_ranges: vec![range],
+                }),
+            }
+        }
 
-                let Some(operation_node) = instruction_node.child_by_field_name("operation") else {
-                    return Ok(None);
-                };
+        Ok(Some(ret))
+    }
 
-                let operation = operation_node
-                    .utf8_text(document.content.as_bytes())
-                    .unwrap();
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.item.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let regions = file_data.label_regions();
+        let Some(region) = regions.iter().find(|x| *x.0 == params.item.name) else {
+            return Ok(None);
+        };
 
-                let (current_param, _) =
-                    get_current_parameter(instruction_node, position.character as usize);
+        let mut ret: Vec<CallHierarchyOutgoingCall> = Vec::new();
+        for (row, target, range) in file_data.label_jumps() {
+            if row < region.2.start.line || row >= region.2.end.line {
+                continue;
+            }
+            match ret.iter_mut().find(|x| x.to.name == target) {
+                Some(call) => call.from_ranges.push(range),
+                None => ret.push(CallHierarchyOutgoingCall {
+                    to: file_data.call_hierarchy_item(&target).unwrap(),
+                    from_ranges: vec![range],
+                }),
+            }
+        }
 
-                let candidates = instructions::logictype_candidates(name);
+        Ok(Some(ret))
+    }
 
-                let types = if let Some(signature) = instructions::INSTRUCTIONS.get(operation) {
-                    if let Some(param_type) = signature.0.get(current_param) {
-                        param_type.intersection(&candidates)
-                    } else {
-                        candidates
-                    }
-                } else {
-                    candidates
-                };
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
 
-                let strings = types
-                    .iter()
-                    .map(|typ| {
-                        MarkedString::String(format!("# `{}` (`{}`)\n{}", name, typ, {
-                            use instructions::DataType;
-                            match typ {
-                                DataType::LogicType => instructions::LOGIC_TYPE_DOCS.get(name),
-                                DataType::SlotLogicType => instructions::SLOT_TYPE_DOCS.get(name),
-                                DataType::BatchMode => instructions::BATCH_MODE_DOCS.get(name),
-                                _ => None,
-                            }
-                            .unwrap_or(&"")
-                        }))
-                    })
-                    .collect();
+        let mut ret = Vec::new();
+        for position in params.positions {
+            let position = document.to_byte_position(position);
+            let Some(node) = self.node_at_position(position.into(), tree) else {
+                return Ok(None);
+            };
 
-                return Ok(Some(Hover {
-                    contents: HoverContents::Array(strings),
-                    range: Some(Range::from(node.range()).into()),
-                }));
+            // Token, operand, instruction, line and finally the whole file, skipping ancestors
+            // covering the same range as their child
+            let mut ranges: Vec<LspRange> = Vec::new();
+            let mut current = Some(node);
+            while let Some(node) = current {
+                let range = document.client_range(node);
+                if ranges.last() != Some(&range) {
+                    ranges.push(range);
+                }
+                current = node.parent();
             }
-            _ => {}
+
+            let mut selection_range = None;
+            for range in ranges.into_iter().rev() {
+                selection_range = Some(SelectionRange {
+                    range,
+                    parent: selection_range.map(Box::new),
+                });
+            }
+            ret.extend(selection_range);
         }
-        Ok(None)
+
+        Ok(Some(ret))
     }
-}
 
-impl Backend {
-    fn node_at_position<'a>(&'a self, position: Position, tree: &'a Tree) -> Option<Node> {
-        self.node_at_range(
-            tower_lsp::lsp_types::Range::new(position.into(), position.into()).into(),
-            tree,
-        )
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let mut ret = Vec::new();
+
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let document = &file_data.document_data;
+
+        let Some(ref tree) = document.tree else {
+            return Err(tower_lsp::jsonrpc::Error::internal_error());
+        };
+
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::HASH_STRINGS;
+
+        for (capture, _) in cursor.captures(query, tree.root_node(), document.content.as_bytes()) {
+            let node = capture.captures[0].node;
+            let name = node.utf8_text(document.content.as_bytes()).unwrap();
+
+            let hash = instructions::hash(name);
+            let tooltip = match instructions::HASH_NAME_LOOKUP.get(&hash.to_string()) {
+                Some(item_name) if *item_name != name => {
+                    format!("HASH(\"{name}\") = {hash} ({item_name})")
+                }
+                _ => format!("HASH(\"{name}\") = {hash}"),
+            };
+
+            ret.push(DocumentLink {
+                range: document.client_range(node),
+                target: None,
+                tooltip: Some(tooltip),
+                data: None,
+            });
+        }
+
+        Ok(Some(ret))
     }
 
-    fn node_at_range<'a>(&'a self, range: Range, tree: &'a Tree) -> Option<Node> {
-        let root = tree.root_node();
-        let start = Position::from(range.0.start);
-        let end = Position::from(range.0.end);
-        let node = root.named_descendant_for_point_range(start.into(), end.into());
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let document = &file_data.document_data;
+        let type_data = &file_data.type_data;
 
-        node
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let position = document.to_byte_position(params.position);
+        let Some(node) = self.node_at_position(position.into(), tree) else {
+            return Ok(None);
+        };
+        if node.kind() != "identifier" {
+            return Ok(None);
+        }
+
+        let name = node.utf8_text(document.content.as_bytes()).unwrap();
+        // Definitions from included files would have to be renamed there as well
+        if type_data.get_range(name).is_none() || type_data.get_source(name).is_some() {
+            return Ok(None);
+        }
+
+        Ok(Some(PrepareRenameResponse::Range(
+            document.client_range(node),
+        )))
     }
 
-    async fn update_content(&self, uri: Url, mut text: String) {
-        let mut files = self.files.write().await;
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let files = self.files.read().await;
+        let uri = &params.text_document_position.text_document.uri;
+        let Some(file_data) = files.get(uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let document = &file_data.document_data;
+        let type_data = &file_data.type_data;
 
-        if !text.ends_with("\n") {
-            text.push('\n');
+        let Some(tree) = document.tree.as_ref() else {
+            return Ok(None);
+        };
+        let position = document.to_byte_position(params.text_document_position.position);
+        let Some(node) = self.node_at_position(position.into(), tree) else {
+            return Ok(None);
+        };
+        if node.kind() != "identifier" {
+            return Ok(None);
         }
-        match files.entry(uri) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                let mut parser = Parser::new();
-                parser
-                    .set_language(tree_sitter_ic10::language())
-                    .expect("Could not set language");
-                let key = entry.key().clone();
-                entry.insert(FileData {
-                    document_data: DocumentData {
-                        url: key,
-                        tree: parser.parse(&text, None),
-                        content: text,
-                        parser,
-                    },
-                    type_data: TypeData::default(),
-                });
-            }
-            std::collections::hash_map::Entry::Occupied(mut entry) => {
-                let mut entry = entry.get_mut();
-                entry.document_data.tree = entry.document_data.parser.parse(&text, None); // TODO
-                entry.document_data.content = text;
+
+        let name = node.utf8_text(document.content.as_bytes()).unwrap();
+        // Definitions from included files would have to be renamed there as well
+        if type_data.get_range(name).is_none() || type_data.get_source(name).is_some() {
+            return Ok(None);
+        }
+        if !is_valid_identifier(&params.new_name) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "'{}' is not a valid identifier",
+                params.new_name
+            )));
+        }
+
+        let mut edits = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::IDENTIFIERS;
+        for (capture, _) in cursor.captures(query, tree.root_node(), document.content.as_bytes()) {
+            let node = capture.captures[0].node;
+            if node.utf8_text(document.content.as_bytes()).unwrap() != name {
+                continue;
             }
+            edits.push(TextEdit::new(
+                document.client_range(node),
+                params.new_name.clone(),
+            ));
         }
+
+        let mut changes = HashMap::new();
+        changes.insert(uri.clone(), edits);
+        Ok(Some(WorkspaceEdit::new(changes)))
     }
 
-    async fn update_definitions(&self, uri: &Url, diagnostics: &mut Vec<Diagnostic>) {
-        let mut files = self.files.write().await;
-        let Some(file_data) = files.get_mut(uri) else {
-            return;
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let align_operands = self.config.read().await.align_operands;
+
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
         let document = &file_data.document_data;
-        let type_data = &mut file_data.type_data;
 
-        if let Some(tree) = document.tree.as_ref() {
-            type_data.defines.clear();
-            type_data.aliases.clear();
-            type_data.labels.clear();
+        let Some(ref tree) = document.tree else {
+            return Err(tower_lsp::jsonrpc::Error::internal_error());
+        };
 
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction (operation \"define\"))@define
-                         (instruction (operation \"alias\"))@alias
-                         (instruction (operation \"label\"))@alias
-                         (label (identifier)@label)",
-            )
-            .unwrap();
+        let mut edits =
+            formatting::format_document(tree, document.content.as_bytes(), align_operands);
+        for edit in &mut edits {
+            edit.range = document.to_client_range(edit.range);
+        }
+        Ok(Some(edits))
+    }
 
-            let define_idx = query.capture_index_for_name("define").unwrap();
-            let alias_idx = query.capture_index_for_name("alias").unwrap();
-            let label_idx = query.capture_index_for_name("label").unwrap();
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let mut ret = Vec::new();
 
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
+        let files = self.files.read().await;
+        let Some(file_data) = files.get(&params.text_document.uri) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
+        };
+        let document = &file_data.document_data;
 
-            for (capture, _) in captures {
-                let capture_idx = capture.captures[0].index;
-                if capture_idx == define_idx || capture_idx == alias_idx {
-                    if let Some(name_node) = capture.captures[0].node.child_by_field_name("operand")
-                    {
-                        let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
-                        let previous_range = {
-                            if let Some(previous) = type_data.defines.get(name) {
-                                Some(previous.range.clone())
-                            } else if let Some(previous) = type_data.aliases.get(name) {
-                                Some(previous.range.clone())
-                            } else {
-                                None
-                            }
-                        };
-                        if let Some(previous_range) = previous_range {
-                            diagnostics.push(Diagnostic::new(
-                                Range::from(name_node.range()).into(),
-                                Some(DiagnosticSeverity::ERROR),
-                                None,
-                                None,
-                                "Duplicate definition".to_string(),
-                                Some(vec![DiagnosticRelatedInformation {
-                                    location: Location::new(
-                                        document.url.clone(),
-                                        previous_range.into(),
-                                    ),
-                                    message: "Previously defined here".to_string(),
-                                }]),
-                                None,
-                            ));
-                            continue;
-                        } else {
-                            let mut cursor = capture.captures[0].node.walk();
-                            let value_node = capture.captures[0]
-                                .node
-                                .children_by_field_name("operand", &mut cursor)
-                                .last();
+        let Some(ref tree) = document.tree else {
+            return Err(tower_lsp::jsonrpc::Error::internal_error());
+        };
 
-                            if let Some(value_node) = value_node {
-                                let value =
-                                    value_node.utf8_text(document.content.as_bytes()).unwrap();
-                                if capture.captures[0].index == define_idx {
-                                    if value_node
-                                        .child(0)
-                                        .map(|x| x.kind())
-                                        .map_or(false, |x| x != "number")
-                                    {
-                                        continue;
-                                    }
-                                    type_data.defines.insert(
-                                        name.to_owned(),
-                                        DefinitionData::new(
-                                            name_node.range().into(),
-                                            value.to_string(),
-                                        ),
-                                    );
-                                } else if capture.captures[0].index == alias_idx {
-                                    if value_node
-                                        .child(0)
-                                        .map(|x| x.kind())
-                                        .map_or(false, |x| x != "register" && x != "device_spec")
-                                    {
-                                        continue;
-                                    }
-                                    type_data.aliases.insert(
-                                        name.to_owned(),
-                                        DefinitionData::new(
-                                            name_node.range().into(),
-                                            value.to_owned().into(),
-                                        ),
-                                    );
-                                }
-                            }
-                        }
-                    }
-                } else if capture_idx == label_idx {
-                    let name_node = capture.captures[0].node;
-                    let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
-                    if let Some(previous) = type_data.get_range(name) {
-                        diagnostics.push(Diagnostic::new(
-                            Range::from(name_node.range()).into(),
-                            Some(DiagnosticSeverity::ERROR),
-                            None,
-                            None,
-                            "Duplicate definition".to_string(),
-                            Some(vec![DiagnosticRelatedInformation {
-                                location: Location::new(document.url.clone(), previous.into()),
-                                message: "Previously defined here".to_string(),
-                            }]),
-                            None,
-                        ));
-                        continue;
-                    }
-                    type_data.labels.insert(
-                        name.to_owned(),
-                        DefinitionData {
-                            range: name_node.range().into(),
-                            value: name_node.start_position().row as u8,
-                        },
-                    );
+        let last_line = document.content.lines().count().saturating_sub(1) as u32;
+
+        let mut label_lines = Vec::new();
+        let mut comment_lines = Vec::new();
+
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::LINES;
+        for (capture, _) in cursor.captures(query, tree.root_node(), document.content.as_bytes()) {
+            let node = capture.captures[0].node;
+            let row = node.start_position().row as u32;
+            match node.named_child(0).map(|x| x.kind()) {
+                Some("label") => label_lines.push(row),
+                Some("comment") => comment_lines.push(row),
+                _ => {}
+            }
+        }
+
+        // Label blocks
+        for (i, start) in label_lines.iter().enumerate() {
+            let end = label_lines.get(i + 1).map(|x| x - 1).unwrap_or(last_line);
+            if end > *start {
+                ret.push(FoldingRange {
+                    start_line: *start,
+                    end_line: end,
+                    kind: Some(FoldingRangeKind::Region),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Comment groups
+        let mut group_start = None;
+        for (i, row) in comment_lines.iter().enumerate() {
+            let start = *group_start.get_or_insert(*row);
+            if comment_lines.get(i + 1) != Some(&(row + 1)) {
+                if *row > start {
+                    ret.push(FoldingRange {
+                        start_line: start,
+                        end_line: *row,
+                        kind: Some(FoldingRangeKind::Comment),
+                        ..Default::default()
+                    });
                 }
-                //println!("{:#?}", capture);
+                group_start = None;
             }
-            // println!("{:#?}", type_data.defines);
-            // println!("{:#?}", type_data.aliases);
-            // println!("{:#?}", type_data.labels);
         }
+
+        Ok(Some(ret))
     }
 
-    async fn check_types(&self, uri: &Url, diagnostics: &mut Vec<Diagnostic>) {
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let ruleset = self.config.read().await.ruleset.clone();
         let files = self.files.read().await;
-        let Some(file_data) = files.get(uri) else {
-            return;
+        let Some(file_data) = files.get(&params.text_document_position_params.text_document.uri)
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_request());
         };
         let document = &file_data.document_data;
         let type_data = &file_data.type_data;
 
+        let position = document.to_byte_position(params.text_document_position_params.position);
+
         let Some(tree) = document.tree.as_ref() else {
-            return;
+            return Ok(None);
+        };
+        let root = tree.root_node();
+        let Some(node) = root.named_descendant_for_point_range(
+            tree_sitter::Point::new(position.line as usize, position.character as usize),
+            tree_sitter::Point::new(position.line as usize, position.character as usize + 1),
+        ) else {
+            return Ok(None);
         };
 
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(tree_sitter_ic10::language(), "(instruction)@a").unwrap();
+        if let Some(hash_node) = node.find_parent("hash_preproc") {
+            let string = hash_node
+                .child_by_field_name("string")
+                .map(|x| x.utf8_text(document.content.as_bytes()).unwrap())
+                .unwrap_or("");
+            let hash = instructions::hash(string);
+
+            let mut v = vec![MarkedString::LanguageString(LanguageString {
+                language: "ic10".to_string(),
+                value: format!("HASH(\"{string}\") = {hash}"),
+            })];
+            if let Some(item_name) = instructions::HASH_NAME_LOOKUP.get(&hash.to_string()) {
+                v.push(MarkedString::String(format!("Hash of `{item_name}`")));
+            }
+            if let Some(prefab_hash) = instructions::HASH_LOOKUP
+                .get(string)
+                .filter(|x| **x != hash as i64)
+            {
+                v.push(MarkedString::String(format!(
+                    "`{string}` is the name of the prefab with hash `{prefab_hash}`"
+                )));
+            }
+            return Ok(Some(Hover {
+                contents: HoverContents::Array(v),
+                range: Some(document.client_range(hash_node)),
+            }));
+        }
+
+        let name = node.utf8_text(document.content.as_bytes()).unwrap();
+        match node.kind() {
+            "identifier" => {
+                if let Some(value) = instructions::ENUM_LOOKUP.get(name) {
+                    return Ok(Some(Hover {
+                        contents: HoverContents::Array({
+                            let mut v = Vec::new();
+                            v.push(MarkedString::LanguageString(LanguageString {
+                                language: "ic10".to_string(),
+                                value: format!("{name} = {value}"),
+                            }));
+                            if let Some(doc) = instructions::ENUM_DOCS.get(name) {
+                                v.push(MarkedString::String(doc.to_string()));
+                            }
+                            v
+                        }),
+                        range: Some(document.client_range(node)),
+                    }));
+                }
+                if let Some(definition_data) = type_data.defines.get(name) {
+                    return Ok(Some(Hover {
+                        contents: HoverContents::Array(vec![MarkedString::LanguageString(
+                            LanguageString {
+                                language: "ic10".to_string(),
+                                value: format!("define {} {}", name, definition_data.value),
+                            },
+                        )]),
+                        range: Some(document.client_range(node)),
+                    }));
+                }
+                if let Some(definition_data) = type_data.aliases.get(name) {
+                    let kind = match definition_data.value {
+                        AliasValue::Register(_) => "Register",
+                        AliasValue::Device(_) => "Device",
+                    };
+                    return Ok(Some(Hover {
+                        contents: HoverContents::Array(vec![
+                            MarkedString::LanguageString(LanguageString {
+                                language: "ic10".to_string(),
+                                value: format!("alias {} {}", name, definition_data.value),
+                            }),
+                            MarkedString::String(format!("{kind} `{}`", definition_data.value)),
+                        ]),
+                        range: Some(document.client_range(node)),
+                    }));
+                }
+                if let Some(definition_data) = type_data.labels.get(name) {
+                    return Ok(Some(Hover {
+                        contents: HoverContents::Scalar(MarkedString::String(format!(
+                            "Label on line {}",
+                            definition_data.value + 1
+                        ))),
+                        range: Some(document.client_range(node)),
+                    }));
+                }
+            }
+            "number" => {
+                if let Some(value) = instructions::CONSTANTS.get(name) {
+                    return Ok(Some(Hover {
+                        contents: HoverContents::Array({
+                            let mut v = Vec::new();
+                            v.push(MarkedString::LanguageString(LanguageString {
+                                language: "ic10".to_string(),
+                                value: format!("{name} = {value}"),
+                            }));
+                            if let Some(doc) = instructions::CONSTANTS_DOCS.get(name) {
+                                v.push(MarkedString::String(doc.to_string()));
+                            }
+                            v
+                        }),
+                        range: Some(document.client_range(node)),
+                    }));
+                }
+                let Some(value) = parse_number(name) else {
+                    return Ok(None);
+                };
+                let mut v = vec![MarkedString::String(format!("Decimal: `{value}`"))];
+                if value.fract() == 0.0 && value.abs() < 2f64.powi(53) {
+                    let integer = value as i64;
+                    let sign = if integer < 0 { "-" } else { "" };
+                    let magnitude = integer.unsigned_abs();
+                    v.push(MarkedString::String(format!(
+                        "Hexadecimal: `{sign}0x{}`",
+                        group_digits(&format!("{magnitude:X}"), 4)
+                    )));
+                    v.push(MarkedString::String(format!(
+                        "Binary: `{sign}0b{}`",
+                        group_digits(&format!("{magnitude:b}"), 4)
+                    )));
+                    if let Some(item_name) =
+                        instructions::HASH_NAME_LOOKUP.get(&integer.to_string())
+                    {
+                        v.push(MarkedString::String(format!("Hash of `{item_name}`")));
+                    }
+                }
 
-        let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
+                // Batch and reagent modes given as numbers
+                if let Some((_, code, Ok(mode))) = document
+                    .literal_modes()
+                    .into_iter()
+                    .find(|x| x.0.id() == node.id())
+                {
+                    let (kind, docs) = if code == LINT_NUMBER_BATCH_MODE {
+                        ("Batch mode", &instructions::BATCH_MODE_DOCS)
+                    } else {
+                        ("Reagent mode", &instructions::REAGENT_MODE_DOCS)
+                    };
+                    let mut mode_docs = vec![MarkedString::String(format!("{kind} `{mode}`"))];
+                    if let Some(doc) = docs.get(mode) {
+                        mode_docs.push(MarkedString::String(doc.to_string()));
+                    }
+                    v.splice(0..0, mode_docs);
+                }
 
-        for (capture, _) in captures {
-            let capture = capture.captures[0].node;
+                return Ok(Some(Hover {
+                    contents: HoverContents::Array(v),
+                    range: Some(document.client_range(node)),
+                }));
+            }
+            "operation" => {
+                let Some(signature) = instructions::signature(&ruleset, name) else {
+                    return Ok(None);
+                };
+                let mut content = name.to_string();
+                for parameter in signature.0 {
+                    content.push_str(&format!(" {parameter}"));
+                }
+                return Ok(Some(Hover {
+                    contents: HoverContents::Array({
+                        let mut v = Vec::new();
+                        v.push(MarkedString::String(content));
+                        if let Some(doc) = instructions::instruction_docs(name) {
+                            v.push(MarkedString::String(doc.to_string()));
+                        }
+                        if let Some(example) = instructions::INSTRUCTION_EXAMPLES.get(name) {
+                            v.push(MarkedString::LanguageString(LanguageString {
+                                language: "ic10".to_string(),
+                                value: example.to_string(),
+                            }));
+                        }
+                        v
+                    }),
+                    range: Some(document.client_range(node)),
+                }));
+            }
+            "logictype" => {
+                let Some(instruction_node) = node.find_parent("instruction") else {
+                    return Ok(None);
+                };
+
+                let Some(operation_node) = instruction_node.child_by_field_name("operation") else {
+                    return Ok(None);
+                };
 
-            if let Some(operation_node) = capture.child_by_field_name("operation") {
                 let operation = operation_node
                     .utf8_text(document.content.as_bytes())
                     .unwrap();
-                let Some(signature) = instructions::INSTRUCTIONS.get(operation) else {
-                                if operation != "define" && operation != "alias" && operation != "label" {
-                                    diagnostics.push(Diagnostic::new(
-                                            Range::from(operation_node.range()).into(),
-                                            Some(DiagnosticSeverity::INFORMATION),
-                                            None,
-                                            None,
-                                            format!("Unsupported instruction"),
-                                            None,
-                                            None,
-                                            ));
-                                }
-                                continue;
-                            };
-
-                let mut argument_count = 0;
-                let mut tree_cursor = capture.walk();
-                let operands = capture.children_by_field_name("operand", &mut tree_cursor);
-                let mut parameters = signature.0.iter();
 
-                let mut first_superfluous_arg = None;
+                let (current_param, _) =
+                    get_current_parameter(instruction_node, position.character as usize);
 
-                for operand in operands {
-                    use instructions::DataType;
-                    argument_count = argument_count + 1;
-                    let Some(parameter) = parameters.next() else {
-                                        if first_superfluous_arg.is_none() {
-                                            first_superfluous_arg = Some(operand);
-                                        }
-                                        continue;
-                                    };
+                let candidates = instructions::logictype_candidates(name);
 
-                    let mut types = Vec::new();
-                    let typ = match operand.named_child(0).unwrap().kind() {
-                        "register" => instructions::Union(&[DataType::Register]),
-                        "device_spec" => instructions::Union(&[DataType::Device]),
-                        "number" => instructions::Union(&[DataType::Number]),
-                        "logictype" => {
-                            let ident = operand
-                                .named_child(0)
-                                .unwrap()
-                                .utf8_text(document.content.as_bytes())
-                                .unwrap();
+                let types = if let Some(signature) = instructions::signature(&ruleset, operation) {
+                    if let Some(param_type) = signature.0.get(current_param) {
+                        param_type.intersection(&candidates)
+                    } else {
+                        candidates
+                    }
+                } else {
+                    candidates
+                };
 
-                            if instructions::LOGIC_TYPES.contains(ident) {
-                                types.push(DataType::LogicType);
-                            }
-                            if instructions::SLOT_LOGIC_TYPES.contains(ident) {
-                                types.push(DataType::SlotLogicType);
+                let strings = types
+                    .iter()
+                    .map(|typ| {
+                        MarkedString::String(format!("# `{}` (`{}`)\n{}", name, typ, {
+                            use instructions::DataType;
+                            match typ {
+                                DataType::LogicType => instructions::logic_type_docs(name),
+                                DataType::SlotLogicType => instructions::slot_type_docs(name),
+                                DataType::BatchMode => {
+                                    instructions::BATCH_MODE_DOCS.get(name).copied()
+                                }
+                                DataType::ReagentMode => {
+                                    instructions::REAGENT_MODE_DOCS.get(name).copied()
+                                }
+                                _ => None,
                             }
-                            if instructions::BATCH_MODES.contains(ident) {
-                                types.push(DataType::BatchMode);
-                            }
-                            if instructions::REAGENT_MODES.contains(ident) {
-                                types.push(DataType::ReagentMode);
-                            }
-                            instructions::Union(types.as_slice())
-                        }
-                        "identifier" => {
-                            let ident = operand
-                                .named_child(0)
-                                .unwrap()
-                                .utf8_text(document.content.as_bytes())
-                                .unwrap();
-                            if parameter.match_type(DataType::Name) {
-                                instructions::Union(&[DataType::Name])
-                            } else if type_data.defines.contains_key(ident)
-                                || type_data.labels.contains_key(ident)
-                            {
-                                instructions::Union(&[DataType::Number])
-                            } else if let Some(type_data) = type_data.aliases.get(ident) {
-                                match type_data.value {
-                                    AliasValue::Device(_) => {
-                                        instructions::Union(&[DataType::Device])
-                                    }
-                                    AliasValue::Register(_) => {
-                                        instructions::Union(&[DataType::Register])
-                                    }
-                                }
-                            } else {
-                                diagnostics.push(Diagnostic::new(
-                                    Range::from(operand.range()).into(),
-                                    Some(DiagnosticSeverity::ERROR),
-                                    None,
-                                    None,
-                                    format!("Unknown identifier"),
-                                    None,
-                                    None,
-                                ));
-                                continue;
-                            }
-                        }
-                        _ => {
-                            // WTF
-                            continue;
-                        }
-                    };
+                            .unwrap_or("")
+                        }))
+                    })
+                    .collect();
 
-                    if !parameter.match_union(&typ) {
-                        diagnostics.push(Diagnostic::new(
-                            Range::from(operand.range()).into(),
-                            Some(DiagnosticSeverity::ERROR),
-                            None,
-                            None,
-                            format!("Type mismatch. Found {}, expected {}", typ, parameter),
-                            None,
-                            None,
-                        ));
+                return Ok(Some(Hover {
+                    contents: HoverContents::Array(strings),
+                    range: Some(document.client_range(node)),
+                }));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+impl Backend {
+    /// Computes the semantic tokens of a document, optionally restricted to a range.
+    ///
+    /// Tokens are always encoded relative to the start of the document, as the first token of a
+    /// range response is still relative to line 0, column 0.
+    fn semantic_tokens(
+        &self,
+        document: &DocumentData,
+        range: Option<LspRange>,
+        max_registers: usize,
+    ) -> Result<Vec<SemanticToken>> {
+        let mut ret = Vec::new();
+
+        let Some(ref tree) = document.tree else {
+            return Err(tower_lsp::jsonrpc::Error::internal_error());
+        };
+
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::SEMANTIC_TOKENS;
+
+        if let Some(range) = range {
+            let range = document.to_byte_range(range);
+            cursor.set_point_range(
+                Position::from(range.start).into()..Position::from(range.end).into(),
+            );
+        }
+
+        let comment_idx = query.capture_index_for_name("comment").unwrap();
+        let keyword_idx = query.capture_index_for_name("keyword").unwrap();
+        let string_idx = query.capture_index_for_name("string").unwrap();
+        let preproc_idx = query.capture_index_for_name("preproc").unwrap();
+        let macro_idx = query.capture_index_for_name("macro").unwrap();
+        let float_idx = query.capture_index_for_name("float").unwrap();
+        let variable_idx = query.capture_index_for_name("variable").unwrap();
+
+        let modifier_bit = |modifier: SemanticTokenModifier| {
+            1 << SEMANTIC_MODIFIER_LEGEND
+                .iter()
+                .position(|x| *x == modifier)
+                .unwrap()
+        };
+
+        // Start, length, type and modifiers of each token
+        let mut tokens = Vec::new();
+
+        for (capture, _) in cursor.captures(query, tree.root_node(), document.content.as_bytes()) {
+            let node = capture.captures[0].node;
+            let idx = capture.captures[0].index;
+            let start = node.range().start_point;
+            let length = node.range().end_point.column as u32 - start.column as u32;
+            let text = node.utf8_text(document.content.as_bytes()).unwrap();
+
+            let tokentype = {
+                if idx == comment_idx {
+                    SemanticTokenType::COMMENT
+                } else if idx == keyword_idx {
+                    SemanticTokenType::KEYWORD
+                } else if idx == string_idx {
+                    SemanticTokenType::STRING
+                } else if idx == preproc_idx {
+                    SemanticTokenType::FUNCTION
+                } else if idx == macro_idx {
+                    SemanticTokenType::MACRO
+                } else if idx == float_idx {
+                    if node.child(0).is_some_and(|x| x.kind() == "hash_preproc") {
+                        // The string itself is captured separately
+                        tokens.push((start, "HASH".len() as u32, SemanticTokenType::FUNCTION, 0));
+                        continue;
+                    } else if instructions::CONSTANTS.contains_key(text) {
+                        SemanticTokenType::ENUM_MEMBER
+                    } else {
+                        SemanticTokenType::NUMBER
                     }
-                }
-                if argument_count > signature.0.len() {
-                    let plural_str = if argument_count - signature.0.len() > 1 {
-                        "s"
+                } else if idx == variable_idx {
+                    if instructions::ENUMS.contains(text) {
+                        SemanticTokenType::ENUM_MEMBER
+                    } else if parse_register(text).is_some_and(|(_, index)| index < max_registers) {
+                        // Registers past r15 are not recognized by the grammar
+                        SemanticTokenType::MACRO
                     } else {
-                        ""
-                    };
-
-                    diagnostics.push(Diagnostic::new(
-                        tower_lsp::lsp_types::Range::new(
-                            Position::from(first_superfluous_arg.unwrap().start_position()).into(),
-                            Position::from(capture.end_position()).into(),
-                        ),
-                        Some(DiagnosticSeverity::ERROR),
-                        None,
-                        None,
-                        format!(
-                            "Superfluous argument{}. '{}' only requires {} arguments.",
-                            plural_str,
-                            operation,
-                            signature.0.len()
-                        ),
-                        None,
-                        None,
-                    ));
+                        SemanticTokenType::VARIABLE
+                    }
+                } else {
                     continue;
                 }
-                if argument_count != signature.0.len() {
-                    diagnostics.push(Diagnostic::new(
-                        Range::from(capture.range()).into(),
-                        Some(DiagnosticSeverity::ERROR),
-                        None,
-                        None,
-                        "Invalid number of arguments".to_string(),
-                        None,
-                        None,
-                    ));
+            };
+
+            if length == 0 {
+                continue;
+            }
+
+            let deprecated = if idx == keyword_idx {
+                instructions::DEPRECATED_INSTRUCTIONS.contains(text)
+            } else if node.kind() == "logictype" {
+                instructions::logic_type_docs(text).is_some_and(|x| x.starts_with("DEPRECATED"))
+            } else {
+                false
+            };
+            let mut modifiers = if deprecated {
+                modifier_bit(SemanticTokenModifier::DEPRECATED)
+            } else {
+                0
+            };
+            // The stack pointer and return address
+            if matches!(text, "sp" | "ra")
+                || matches!(text, "r16" | "r17") && tokentype == SemanticTokenType::MACRO
+            {
+                modifiers |= modifier_bit(SemanticTokenModifier::STATIC);
+            }
+
+            tokens.push((start, length, tokentype, modifiers));
+        }
+
+        // Captures of different patterns are not necessarily yielded in document order, but the
+        // deltas below need them to be
+        tokens.sort_by_key(|x| (x.0.row, x.0.column));
+
+        let mut previous_line = 0u32;
+        let mut previous_col = 0u32;
+
+        for (start, length, tokentype, modifiers) in tokens {
+            let end = document.to_client_position(LspPosition::new(
+                start.row as u32,
+                start.column as u32 + length,
+            ));
+            let start = document.to_client_position(Position::from(start).into());
+            let length = end.character - start.character;
+
+            let delta_line = start.line - previous_line;
+            let delta_start = if delta_line == 0 {
+                start.character - previous_col
+            } else {
+                start.character
+            };
+
+            ret.push(SemanticToken {
+                delta_line,
+                delta_start,
+                length,
+                token_type: SEMANTIC_SYMBOL_LEGEND
+                    .iter()
+                    .position(|x| *x == tokentype)
+                    .unwrap() as u32,
+                token_modifiers_bitset: modifiers,
+            });
+
+            previous_line = start.line;
+            previous_col = start.character;
+        }
+        Ok(ret)
+    }
+
+    fn node_at_position<'a>(&'a self, position: Position, tree: &'a Tree) -> Option<Node<'a>> {
+        self.node_at_range(
+            tower_lsp::lsp_types::Range::new(position.into(), position.into()).into(),
+            tree,
+        )
+    }
+
+    fn node_at_range<'a>(&'a self, range: Range, tree: &'a Tree) -> Option<Node<'a>> {
+        let root = tree.root_node();
+        let start = Position::from(range.0.start);
+        let end = Position::from(range.0.end);
+        let node = root.named_descendant_for_point_range(start.into(), end.into());
+
+        node
+    }
+
+    async fn update_content(&self, uri: Url, mut text: String) {
+        let utf16 = !supports_utf8(&*self.client_capabilities.read().await);
+        let mut files = self.files.write().await;
+
+        match files.entry(uri) {
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let key = entry.key().clone();
+                let mut file_data = FileData::new(key, text);
+                file_data.document_data.utf16 = utf16;
+                entry.insert(file_data);
+            }
+            std::collections::hash_map::Entry::Occupied(mut entry) => {
+                let appended_newline = !text.ends_with("\n");
+                if appended_newline {
+                    text.push('\n');
                 }
+                let entry = entry.get_mut();
+                entry.document_data.tree = entry.document_data.parser.parse(&text, None);
+                entry.document_data.content = text;
+                entry.document_data.appended_newline = appended_newline;
+            }
+        }
+    }
+
+    /// Runs diagnostics once no further changes have arrived for the configured debounce delay.
+    ///
+    /// Any run still pending for the same document is cancelled.
+    async fn schedule_diagnostics(&self, uri: Url) {
+        let delay = self.config.read().await.diagnostic_debounce_ms;
+        let mut pending = self.pending_diagnostics.lock().await;
+        if let Some(handle) = pending.remove(&uri) {
+            handle.abort();
+        }
+
+        if delay == 0 {
+            drop(pending);
+            self.run_diagnostics(&uri).await;
+            return;
+        }
+
+        let backend = self.clone();
+        let handle = tokio::spawn({
+            let uri = uri.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                backend.pending_diagnostics.lock().await.remove(&uri);
+                backend.run_diagnostics(&uri).await;
             }
+        });
+        pending.insert(uri, handle);
+    }
+
+    async fn run_diagnostics(&self, uri: &Url) {
+        let config = self.config.read().await.clone();
+        let diagnostics = {
+            let mut files = self.files.write().await;
+            let Some(file_data) = files.get_mut(uri) else {
+                return;
+            };
+            let mut diagnostics = file_data.diagnostics(&config);
+            file_data
+                .document_data
+                .convert_diagnostics_for_client(&mut diagnostics);
+            diagnostics
+        };
+
+        self.client
+            .publish_diagnostics(uri.to_owned(), diagnostics, None)
+            .await;
+
+        // Value hints depend on the definitions collected above
+        let refresh_support = self
+            .client_capabilities
+            .read()
+            .await
+            .workspace
+            .as_ref()
+            .and_then(|x| x.inlay_hint.as_ref())
+            .and_then(|x| x.refresh_support)
+            .unwrap_or(false);
+        if config.value_hints && refresh_support {
+            let _ = self.client.inlay_hint_refresh().await;
+        }
+    }
+}
+
+impl FileData {
+    fn new(url: Url, mut text: String) -> Self {
+        let appended_newline = !text.ends_with("\n");
+        if appended_newline {
+            text.push('\n');
         }
+        let mut parser = Parser::new();
+        parser
+            .set_language(tree_sitter_ic10::language())
+            .expect("Could not set language");
+        FileData {
+            document_data: DocumentData {
+                url,
+                tree: parser.parse(&text, None),
+                content: text,
+                parser,
+                appended_newline,
+                utf16: false,
+            },
+            type_data: TypeData::default(),
+        }
+    }
+
+    fn update_definitions(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        self.collect_definitions(diagnostics);
+        self.resolve_includes(diagnostics, &mut vec![self.document_data.url.clone()]);
+    }
+
+    /// Merges the definitions of files included with `# include "file"` comments.
+    ///
+    /// `visited` holds the chain of files currently being included, to detect cycles.
+    fn resolve_includes(&mut self, diagnostics: &mut Vec<Diagnostic>, visited: &mut Vec<Url>) {
+        let document = &self.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return;
+        };
+        let content = document.content.as_bytes();
+
+        let mut included = Vec::new();
+        let mut cursor = QueryCursor::new();
+        for (capture, _) in cursor.captures(&queries::COMMENTS, tree.root_node(), content) {
+            let node = capture.captures[0].node;
+            let Some(path) = parse_include(node.utf8_text(content).unwrap()) else {
+                continue;
+            };
+            let range: LspRange = Range::from(node.range()).into();
+
+            let Ok(url) = document.url.join(path) else {
+                diagnostics.push(Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String(ERROR_INCLUDE.to_string())),
+                    message: format!("Invalid include path \"{path}\""),
+                    ..Default::default()
+                });
+                continue;
+            };
+            if visited.contains(&url) {
+                diagnostics.push(Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String(ERROR_INCLUDE.to_string())),
+                    message: format!("Circular include of \"{path}\""),
+                    ..Default::default()
+                });
+                continue;
+            }
+            let text = match url.to_file_path() {
+                Ok(file_path) => read_included_file(&file_path).map_err(|e| e.to_string()),
+                Err(()) => Err("not a local file".to_string()),
+            };
+            let text = match text {
+                Ok(text) => text,
+                Err(e) => {
+                    diagnostics.push(Diagnostic {
+                        range,
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        code: Some(NumberOrString::String(ERROR_INCLUDE.to_string())),
+                        message: format!("Could not read included file \"{path}\": {e}"),
+                        ..Default::default()
+                    });
+                    continue;
+                }
+            };
+
+            // Problems in the included file are reported when it is opened itself
+            visited.push(url.clone());
+            let mut file_data = FileData::new(url.clone(), text.to_string());
+            file_data.document_data.utf16 = document.utf16;
+            file_data.collect_definitions(&mut Vec::new());
+            file_data.resolve_includes(&mut Vec::new(), visited);
+            file_data
+                .type_data
+                .convert_ranges_for_client(&file_data.document_data);
+            visited.pop();
+
+            included.push((url, range, file_data.type_data));
+        }
+
+        for (url, range, type_data) in included {
+            // Reported with the default severity, which is adjusted once the config is at hand
+            for (name, same_value) in self.type_data.conflicts(&type_data) {
+                let (code, message) = if same_value {
+                    (
+                        LINT_SAME_VALUE_REDEFINITION,
+                        format!(
+                            "'{name}' is defined again with the same value in an included file"
+                        ),
+                    )
+                } else {
+                    (
+                        LINT_INCLUDE_CONFLICT,
+                        format!("'{name}' is defined differently in an included file"),
+                    )
+                };
+                let other_location = Location::new(
+                    type_data.get_source(name).unwrap_or(&url).clone(),
+                    type_data.get_range(name).unwrap().into(),
+                );
+                // Definitions of the document itself take precedence, so report them there.
+                // Otherwise the earlier include does, and the later include is reported.
+                let (range, mut related_information) = match self.type_data.get_source(name) {
+                    None => (self.type_data.get_range(name).unwrap().into(), Vec::new()),
+                    Some(source) => (
+                        range,
+                        vec![DiagnosticRelatedInformation {
+                            location: Location::new(
+                                source.clone(),
+                                self.type_data.get_range(name).unwrap().into(),
+                            ),
+                            message: "Used definition".to_string(),
+                        }],
+                    ),
+                };
+                related_information.push(DiagnosticRelatedInformation {
+                    location: other_location,
+                    message: "Also defined here".to_string(),
+                });
+                diagnostics.push(Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(code.to_string())),
+                    message,
+                    related_information: Some(related_information),
+                    ..Default::default()
+                });
+            }
+            self.type_data.include(type_data, &url);
+        }
+    }
+
+    fn collect_definitions(&mut self, diagnostics: &mut Vec<Diagnostic>) {
+        let document = &self.document_data;
+        let type_data = &mut self.type_data;
+
+        if let Some(tree) = document.tree.as_ref() {
+            type_data.defines.clear();
+            type_data.aliases.clear();
+            type_data.labels.clear();
+
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::DEFINITIONS;
+
+            let define_idx = query.capture_index_for_name("define").unwrap();
+            let alias_idx = query.capture_index_for_name("alias").unwrap();
+            let label_idx = query.capture_index_for_name("label").unwrap();
+
+            let captures = cursor.captures(query, tree.root_node(), document.content.as_bytes());
+
+            for (capture, _) in captures {
+                let capture_idx = capture.captures[0].index;
+                if capture_idx == define_idx || capture_idx == alias_idx {
+                    if let Some(name_node) = capture.captures[0].node.child_by_field_name("operand")
+                    {
+                        let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
+                        // Reported by check_types
+                        if builtin_name_kind(name).is_some() {
+                            continue;
+                        }
+                        let previous_range = {
+                            if let Some(previous) = type_data.defines.get(name) {
+                                Some(previous.range.clone())
+                            } else {
+                                type_data
+                                    .aliases
+                                    .get(name)
+                                    .map(|previous| previous.range.clone())
+                            }
+                        };
+                        if let Some(previous_range) = previous_range {
+                            diagnostics.push(Diagnostic::new(
+                                Range::from(name_node.range()).into(),
+                                Some(DiagnosticSeverity::ERROR),
+                                None,
+                                None,
+                                "Duplicate definition".to_string(),
+                                Some(vec![DiagnosticRelatedInformation {
+                                    location: Location::new(
+                                        document.url.clone(),
+                                        previous_range.into(),
+                                    ),
+                                    message: "Previously defined here".to_string(),
+                                }]),
+                                None,
+                            ));
+                            continue;
+                        } else {
+                            let mut cursor = capture.captures[0].node.walk();
+                            let value_node = capture.captures[0]
+                                .node
+                                .children_by_field_name("operand", &mut cursor)
+                                .last();
+
+                            if let Some(value_node) = value_node {
+                                let value =
+                                    value_node.utf8_text(document.content.as_bytes()).unwrap();
+                                if capture.captures[0].index == define_idx {
+                                    if value_node.id() == name_node.id() {
+                                        continue;
+                                    }
+                                    let error = match value_node.child(0) {
+                                        Some(x) if x.kind() != "number" => Some(format!(
+                                            "Define value must be a number, found `{value}`"
+                                        )),
+                                        Some(x)
+                                            if x.child(0)
+                                                .is_some_and(|x| x.kind() == "hash_preproc") =>
+                                        {
+                                            None
+                                        }
+                                        _ => try_parse_number(value).err().map(|error| {
+                                            format!("Invalid number `{value}`: {error}")
+                                        }),
+                                    };
+                                    if let Some(message) = error {
+                                        diagnostics.push(Diagnostic {
+                                            range: Range::from(value_node.range()).into(),
+                                            severity: Some(DiagnosticSeverity::ERROR),
+                                            message,
+                                            ..Default::default()
+                                        });
+                                        continue;
+                                    }
+                                    type_data.defines.insert(
+                                        name.to_owned(),
+                                        DefinitionData::new(
+                                            name_node.range().into(),
+                                            value.to_string(),
+                                        ),
+                                    );
+                                } else if capture.captures[0].index == alias_idx {
+                                    let is_register = |x: Node| match x.kind() {
+                                        "register" | "device_spec" => true,
+                                        // Registers out of range are reported by check_types
+                                        "identifier" => parse_register(value).is_some(),
+                                        _ => false,
+                                    };
+                                    if value_node.child(0).is_some_and(|x| !is_register(x)) {
+                                        continue;
+                                    }
+                                    type_data.aliases.insert(
+                                        name.to_owned(),
+                                        DefinitionData::new(
+                                            name_node.range().into(),
+                                            value.to_owned().into(),
+                                        ),
+                                    );
+                                }
+                            }
+                        }
+                    }
+                } else if capture_idx == label_idx {
+                    let name_node = capture.captures[0].node;
+                    let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
+                    if let Some(previous) = type_data.get_range(name) {
+                        diagnostics.push(Diagnostic::new(
+                            Range::from(name_node.range()).into(),
+                            Some(DiagnosticSeverity::ERROR),
+                            None,
+                            None,
+                            "Duplicate definition".to_string(),
+                            Some(vec![DiagnosticRelatedInformation {
+                                location: Location::new(document.url.clone(), previous.into()),
+                                message: "Previously defined here".to_string(),
+                            }]),
+                            None,
+                        ));
+                        continue;
+                    }
+                    type_data.labels.insert(
+                        name.to_owned(),
+                        DefinitionData::new(
+                            name_node.range().into(),
+                            name_node.start_position().row as u32,
+                        ),
+                    );
+                }
+                //println!("{:#?}", capture);
+            }
+            // println!("{:#?}", type_data.defines);
+            // println!("{:#?}", type_data.aliases);
+            // println!("{:#?}", type_data.labels);
+        }
+    }
+
+    fn check_types(&self, config: &Configuration, diagnostics: &mut Vec<Diagnostic>) {
+        let file_data = self;
+        let document = &file_data.document_data;
+        let type_data = &file_data.type_data;
+
+        let Some(tree) = document.tree.as_ref() else {
+            return;
+        };
+
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::INSTRUCTIONS;
+
+        let captures = cursor.captures(query, tree.root_node(), document.content.as_bytes());
+
+        for (capture, _) in captures {
+            let capture = capture.captures[0].node;
+
+            if let Some(operation_node) = capture.child_by_field_name("operation") {
+                let operation = operation_node
+                    .utf8_text(document.content.as_bytes())
+                    .unwrap();
+                let Some(signature) = instructions::signature(&config.ruleset, operation)
+                    .filter(|_| instructions::in_ruleset(&config.ruleset, operation))
+                else {
+                    let severity = config.lint_severity(
+                        LINT_UNSUPPORTED_INSTRUCTION,
+                        DiagnosticSeverity::INFORMATION,
+                    );
+                    if !matches!(operation, "define" | "alias" | "label") {
+                        if let Some(severity) = severity {
+                            diagnostics.push(Diagnostic {
+                                range: Range::from(operation_node.range()).into(),
+                                severity: Some(severity),
+                                code: Some(NumberOrString::String(
+                                    LINT_UNSUPPORTED_INSTRUCTION.to_string(),
+                                )),
+                                message: "Unsupported instruction".to_string(),
+                                data: instructions::closest_instruction(operation, &config.ruleset)
+                                    .map(|x| Value::String(x.to_string())),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    continue;
+                };
+
+                // Definitions named like a built-in, which the game uses instead. Names the grammar
+                // recognizes as registers or devices are already a type mismatch.
+                if let (true, Some(severity)) = (
+                    matches!(operation, "alias" | "define"),
+                    config.lint_severity(LINT_BUILTIN_NAME, DiagnosticSeverity::WARNING),
+                ) {
+                    let name_node = capture
+                        .child_by_field_name("operand")
+                        .and_then(|x| x.named_child(0))
+                        .filter(|x| x.kind() == "identifier");
+                    if let Some(name_node) = name_node {
+                        let name = name_node.utf8_text(document.content.as_bytes()).unwrap();
+                        if let Some(kind) = builtin_name_kind(name) {
+                            diagnostics.push(Diagnostic {
+                                range: Range::from(name_node.range()).into(),
+                                severity: Some(severity),
+                                code: Some(NumberOrString::String(LINT_BUILTIN_NAME.to_string())),
+                                message: format!(
+                                    "`{name}` is a built-in {kind}, this {operation} is ignored"
+                                ),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+
+                let mut argument_count = 0;
+                let mut tree_cursor = capture.walk();
+                let operands = capture.children_by_field_name("operand", &mut tree_cursor);
+                let mut parameters = signature.0.iter();
+
+                let mut first_superfluous_arg = None;
+
+                for operand in operands {
+                    use instructions::DataType;
+                    argument_count += 1;
+                    // Define values are checked when collecting definitions
+                    if operation == "define" && argument_count == 2 {
+                        parameters.next();
+                        continue;
+                    }
+                    let Some(parameter) = parameters.next() else {
+                        if first_superfluous_arg.is_none() {
+                            first_superfluous_arg = Some(operand);
+                        }
+                        continue;
+                    };
+
+                    let mut types = Vec::new();
+                    let typ = match operand.named_child(0).unwrap().kind() {
+                        kind @ ("register" | "device_spec") => {
+                            let text = operand.utf8_text(document.content.as_bytes()).unwrap();
+                            let register = text.split(':').next().unwrap();
+                            if let Some(diagnostic) =
+                                nonexistent_register(register, operand.range(), config)
+                            {
+                                diagnostics.push(diagnostic);
+                                continue;
+                            }
+                            if kind == "register" {
+                                instructions::Union(&[DataType::Register])
+                            } else {
+                                instructions::Union(&[DataType::Device])
+                            }
+                        }
+                        "number" => {
+                            // Literals where only integers make sense
+                            let text = operand.utf8_text(document.content.as_bytes()).unwrap();
+                            let is_last = argument_count == signature.0.len();
+                            let is_relative_jump = is_last
+                                && (operation == "jr"
+                                    || instructions::RELATIVE_BRANCHES
+                                        .values()
+                                        .any(|x| *x == operation));
+                            let is_jump_target =
+                                is_last && instructions::BRANCH_INSTRUCTIONS.contains(operation);
+                            let last_line = config.max_lines.saturating_sub(1) as f64;
+                            let message = parse_number(text).and_then(|value| {
+                                let integer = value.fract() == 0.0;
+                                if is_jump_target {
+                                    (!integer || !(0.0..=last_line).contains(&value)).then(|| {
+                                        format!("Jump target must be a line from 0 to {last_line}")
+                                    })
+                                } else if is_relative_jump {
+                                    (!integer).then(|| "Jump offset must be an integer".to_string())
+                                } else if parameter.match_type(DataType::SlotIndex) {
+                                    (!integer || value < 0.0).then(|| {
+                                        "Slot index must be a non-negative integer".to_string()
+                                    })
+                                } else {
+                                    None
+                                }
+                            });
+                            if let Some(message) = message {
+                                diagnostics.push(Diagnostic {
+                                    range: Range::from(operand.range()).into(),
+                                    severity: Some(DiagnosticSeverity::ERROR),
+                                    message,
+                                    ..Default::default()
+                                });
+                            }
+                            instructions::Union(&[DataType::Number])
+                        }
+                        "logictype" => {
+                            let ident = operand
+                                .named_child(0)
+                                .unwrap()
+                                .utf8_text(document.content.as_bytes())
+                                .unwrap();
+
+                            if instructions::is_logic_type(ident) {
+                                types.push(DataType::LogicType);
+                            }
+                            if instructions::is_slot_logic_type(ident) {
+                                types.push(DataType::SlotLogicType);
+                            }
+                            if instructions::BATCH_MODES.contains(ident) {
+                                types.push(DataType::BatchMode);
+                            }
+                            if instructions::REAGENT_MODES.contains(ident) {
+                                types.push(DataType::ReagentMode);
+                            }
+
+                            if parameter.match_type(DataType::LogicType) {
+                                use instructions::LogicTypeAccess;
+
+                                let message =
+                                    match (operation, instructions::logic_type_access(ident)) {
+                                        ("s" | "sb" | "sbn", LogicTypeAccess::Read) => {
+                                            Some(format!("{ident} is read-only"))
+                                        }
+                                        ("l" | "lb" | "lbn", LogicTypeAccess::Write) => {
+                                            Some(format!("{ident} is write-only"))
+                                        }
+                                        _ => None,
+                                    };
+                                if let Some(message) = message {
+                                    diagnostics.push(Diagnostic::new(
+                                        Range::from(operand.range()).into(),
+                                        Some(DiagnosticSeverity::ERROR),
+                                        None,
+                                        None,
+                                        message,
+                                        None,
+                                        None,
+                                    ));
+                                }
+                            }
+
+                            instructions::Union(types.as_slice())
+                        }
+                        "identifier" => {
+                            let ident = operand
+                                .named_child(0)
+                                .unwrap()
+                                .utf8_text(document.content.as_bytes())
+                                .unwrap();
+                            if parameter.match_type(DataType::Name) {
+                                instructions::Union(&[DataType::Name])
+                            } else if type_data.defines.contains_key(ident)
+                                || type_data.labels.contains_key(ident)
+                                || instructions::ENUMS.contains(ident)
+                            {
+                                instructions::Union(&[DataType::Number])
+                            } else if let Some(type_data) = type_data.aliases.get(ident) {
+                                match type_data.value {
+                                    AliasValue::Device(_) => {
+                                        instructions::Union(&[DataType::Device])
+                                    }
+                                    AliasValue::Register(_) => {
+                                        instructions::Union(&[DataType::Register])
+                                    }
+                                }
+                            } else if instructions::is_logic_type(ident)
+                                || instructions::is_slot_logic_type(ident)
+                            {
+                                // Custom logic types are not known to the grammar
+                                match (
+                                    instructions::is_logic_type(ident),
+                                    instructions::is_slot_logic_type(ident),
+                                ) {
+                                    (true, true) => instructions::Union(&[
+                                        DataType::LogicType,
+                                        DataType::SlotLogicType,
+                                    ]),
+                                    (true, false) => instructions::Union(&[DataType::LogicType]),
+                                    _ => instructions::Union(&[DataType::SlotLogicType]),
+                                }
+                            } else if let Some((is_device, _)) = parse_register(ident) {
+                                if let Some(diagnostic) =
+                                    nonexistent_register(ident, operand.range(), config)
+                                {
+                                    diagnostics.push(diagnostic);
+                                    continue;
+                                }
+                                if is_device {
+                                    instructions::Union(&[DataType::Device])
+                                } else {
+                                    instructions::Union(&[DataType::Register])
+                                }
+                            } else if let Some(index) = ident
+                                .strip_prefix('d')
+                                .filter(|x| x.bytes().all(|x| x.is_ascii_digit()))
+                                .and_then(|x| x.parse::<usize>().ok())
+                            {
+                                diagnostics.push(Diagnostic::new(
+                                    Range::from(operand.range()).into(),
+                                    Some(DiagnosticSeverity::ERROR),
+                                    Some(NumberOrString::String(
+                                        ERROR_NONEXISTENT_REGISTER.to_string(),
+                                    )),
+                                    None,
+                                    format!(
+                                        "Device d{index} does not exist. Only d0 to d{MAX_DEVICE} and db are available"
+                                    ),
+                                    None,
+                                    None,
+                                ));
+                                continue;
+                            } else {
+                                let is_jump_target = instructions::BRANCH_INSTRUCTIONS
+                                    .contains(operation)
+                                    && argument_count == signature.0.len();
+
+                                if is_jump_target {
+                                    diagnostics.push(Diagnostic::new(
+                                        Range::from(operand.range()).into(),
+                                        Some(DiagnosticSeverity::ERROR),
+                                        None,
+                                        None,
+                                        "Unknown jump target".to_string(),
+                                        None,
+                                        None,
+                                    ));
+                                } else {
+                                    diagnostics.push(Diagnostic {
+                                        range: Range::from(operand.range()).into(),
+                                        severity: Some(DiagnosticSeverity::ERROR),
+                                        code: Some(NumberOrString::String(
+                                            ERROR_UNKNOWN_IDENTIFIER.to_string(),
+                                        )),
+                                        message: "Unknown identifier".to_string(),
+                                        data: Some(json!({
+                                            "name": ident,
+                                            "register": parameter.match_type(DataType::Register),
+                                            "device": parameter.match_type(DataType::Device),
+                                            "number": parameter.match_type(DataType::Number),
+                                        })),
+                                        ..Default::default()
+                                    });
+                                }
+                                continue;
+                            }
+                        }
+                        _ => {
+                            // WTF
+                            continue;
+                        }
+                    };
+
+                    if !parameter.match_union(&typ) {
+                        // Point at the definition an identifier got its type from
+                        let related_information = operand
+                            .named_child(0)
+                            .filter(|x| x.kind() == "identifier")
+                            .map(|x| x.utf8_text(document.content.as_bytes()).unwrap())
+                            .and_then(|name| {
+                                let range = type_data.get_range(name)?;
+                                let url = type_data.get_source(name).unwrap_or(&document.url);
+                                Some(vec![DiagnosticRelatedInformation {
+                                    location: Location::new(url.clone(), range.into()),
+                                    message: format!("'{name}' is defined here"),
+                                }])
+                            });
+                        diagnostics.push(Diagnostic::new(
+                            Range::from(operand.range()).into(),
+                            Some(DiagnosticSeverity::ERROR),
+                            None,
+                            None,
+                            format!("Type mismatch. Found {}, expected {}", typ, parameter),
+                            related_information,
+                            None,
+                        ));
+                    }
+                }
+
+                // No-op self-moves
+                {
+                    let content = document.content.as_bytes();
+                    let operands = capture
+                        .children_by_field_name("operand", &mut tree_cursor)
+                        .filter_map(|x| x.named_child(0))
+                        .collect::<Vec<_>>();
+                    let same_register = |x: Node, y: Node| {
+                        let x = type_data.register_name(x, content);
+                        x.is_some() && x == type_data.register_name(y, content)
+                    };
+                    let is_noop = match (operation, operands.as_slice()) {
+                        ("move", [target, source]) => same_register(*target, *source),
+                        ("add" | "sub", [target, source, value]) => {
+                            same_register(*target, *source)
+                                && value.kind() == "number"
+                                && value
+                                    .utf8_text(content)
+                                    .unwrap()
+                                    .parse::<f64>()
+                                    .is_ok_and(|x| x == 0.0)
+                        }
+                        _ => false,
+                    };
+                    let severity =
+                        config.lint_severity(LINT_SELF_MOVE, DiagnosticSeverity::WARNING);
+                    if let (true, Some(severity)) = (is_noop, severity) {
+                        diagnostics.push(Diagnostic {
+                            range: Range::from(capture.range()).into(),
+                            severity: Some(severity),
+                            code: Some(NumberOrString::String(LINT_SELF_MOVE.to_string())),
+                            message: "Instruction has no effect".to_string(),
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                // Division by zero and math functions outside their domain
+                {
+                    let content = document.content.as_bytes();
+                    let operands = capture
+                        .children_by_field_name("operand", &mut tree_cursor)
+                        .filter_map(|x| x.named_child(0))
+                        .collect::<Vec<_>>();
+                    let value = |x: &Node| type_data.number_value(*x, content);
+                    let problem = match (operation, operands.as_slice()) {
+                        ("div" | "mod", [_, _, divisor]) if value(divisor) == Some(0.0) => {
+                            Some((*divisor, "Division by zero"))
+                        }
+                        ("sqrt", [_, argument]) if value(argument).is_some_and(|x| x < 0.0) => {
+                            Some((*argument, "Square root of a negative number"))
+                        }
+                        ("log", [_, argument]) if value(argument).is_some_and(|x| x < 0.0) => {
+                            Some((*argument, "Logarithm of a negative number"))
+                        }
+                        _ => None,
+                    };
+                    let severity =
+                        config.lint_severity(LINT_INVALID_MATH, DiagnosticSeverity::WARNING);
+                    if let (Some((node, message)), Some(severity)) = (problem, severity) {
+                        diagnostics.push(Diagnostic {
+                            range: Range::from(node.range()).into(),
+                            severity: Some(severity),
+                            code: Some(NumberOrString::String(LINT_INVALID_MATH.to_string())),
+                            message: message.to_string(),
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                // Branches comparing only literal numbers, which always or never jump
+                if let Some(severity) =
+                    config.lint_severity(LINT_CONSTANT_BRANCH, DiagnosticSeverity::WARNING)
+                {
+                    let content = document.content.as_bytes();
+                    let operands = capture
+                        .children_by_field_name("operand", &mut tree_cursor)
+                        .filter_map(|x| x.named_child(0))
+                        .collect::<Vec<_>>();
+                    let values = operands
+                        .split_last()
+                        .map(|(_, compared)| compared)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|x| {
+                            let text = x.utf8_text(content).unwrap();
+                            (x.kind() == "number")
+                                .then(|| constant_value(text))
+                                .flatten()
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(taken) = values.and_then(|x| branch_taken(operation, &x)) {
+                        diagnostics.push(Diagnostic {
+                            range: Range::from(capture.range()).into(),
+                            severity: Some(severity),
+                            code: Some(NumberOrString::String(LINT_CONSTANT_BRANCH.to_string())),
+                            message: if taken {
+                                "Branch is always taken".to_string()
+                            } else {
+                                "Branch is never taken".to_string()
+                            },
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                // Batch instructions: prefab hash and batch mode given through a define
+                {
+                    use instructions::DataType;
+
+                    let content = document.content.as_bytes();
+                    let operands = capture
+                        .children_by_field_name("operand", &mut tree_cursor)
+                        .filter_map(|x| x.named_child(0))
+                        .collect::<Vec<_>>();
+
+                    let prefab_name = instructions::prefab_hash_parameter(operation)
+                        .and_then(|x| operands.get(x))
+                        .and_then(|x| x.named_child(0))
+                        .filter(|x| x.kind() == "hash_preproc")
+                        .and_then(|x| x.child_by_field_name("string"));
+                    let severity =
+                        config.lint_severity(LINT_UNKNOWN_PREFAB, DiagnosticSeverity::WARNING);
+                    if let (Some(name_node), Some(severity)) = (prefab_name, severity) {
+                        let name = name_node.utf8_text(content).unwrap();
+                        if !instructions::HASH_NAME_LOOKUP
+                            .contains_key(&instructions::hash(name).to_string())
+                        {
+                            diagnostics.push(Diagnostic {
+                                range: Range::from(name_node.range()).into(),
+                                severity: Some(severity),
+                                code: Some(NumberOrString::String(LINT_UNKNOWN_PREFAB.to_string())),
+                                message: "Unknown prefab name".to_string(),
+                                ..Default::default()
+                            });
+                        }
+                    }
+
+                    // Literal numbers are checked along with the batch mode lint
+                    let batch_mode = signature
+                        .0
+                        .iter()
+                        .position(|x| x.match_type(DataType::BatchMode))
+                        .and_then(|x| operands.get(x))
+                        .filter(|x| x.kind() == "identifier");
+                    let error = batch_mode.and_then(|node| {
+                        let value = type_data.number_value(*node, content)?;
+                        let error =
+                            mode_name(value, "batch mode", &instructions::BATCH_MODE_LOOKUP)
+                                .err()?;
+                        Some((node, error))
+                    });
+                    if let Some((node, message)) = error {
+                        diagnostics.push(Diagnostic {
+                            range: Range::from(node.range()).into(),
+                            severity: Some(DiagnosticSeverity::ERROR),
+                            message,
+                            ..Default::default()
+                        });
+                    }
+                }
+
+                if argument_count > signature.0.len() {
+                    let plural_str = if argument_count - signature.0.len() > 1 {
+                        "s"
+                    } else {
+                        ""
+                    };
+
+                    diagnostics.push(Diagnostic::new(
+                        tower_lsp::lsp_types::Range::new(
+                            Position::from(first_superfluous_arg.unwrap().start_position()).into(),
+                            Position::from(capture.end_position()).into(),
+                        ),
+                        Some(DiagnosticSeverity::ERROR),
+                        None,
+                        None,
+                        format!(
+                            "Superfluous argument{}. '{}' only requires {} arguments.",
+                            plural_str,
+                            operation,
+                            signature.0.len()
+                        ),
+                        None,
+                        None,
+                    ));
+                    continue;
+                }
+                if argument_count != signature.0.len() {
+                    diagnostics.push(Diagnostic::new(
+                        Range::from(capture.range()).into(),
+                        Some(DiagnosticSeverity::ERROR),
+                        None,
+                        None,
+                        "Invalid number of arguments".to_string(),
+                        None,
+                        None,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Counts the lines taking up space in the instruction budget.
+    ///
+    /// Whether `define`/`alias` lines and labels count depends on the configuration.
+    fn instruction_count(&self, config: &Configuration) -> usize {
+        let Some(tree) = self.document_data.tree.as_ref() else {
+            return 0;
+        };
+        let content = self.document_data.content.as_bytes();
+        let mut count = 0;
+
+        let mut cursor = QueryCursor::new();
+        let query = &*queries::LINES;
+        for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+            let Some(node) = capture.captures[0].node.named_child(0) else {
+                continue;
+            };
+            let counts = match node.kind() {
+                "label" => config.count_labels,
+                "instruction" => match node
+                    .child_by_field_name("operation")
+                    .map(|x| x.utf8_text(content).unwrap())
+                {
+                    Some("define" | "alias" | "label") => config.count_definitions,
+                    _ => true,
+                },
+                _ => false,
+            };
+            if counts {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Every label defined in the document with its range, and the region of lines belonging to it,
+    /// up to the next label or the end of the document. Sorted by line.
+    fn label_regions(&self) -> Vec<(&String, &Range, LspRange)> {
+        let mut ret = self
+            .type_data
+            .labels
+            .iter()
+            .filter(|x| x.1.source.is_none())
+            .map(|(name, x)| (name, &x.range, x.value))
+            .collect::<Vec<_>>();
+        ret.sort_by_key(|x| x.2);
+
+        let end = self.document_data.content.lines().count() as u32;
+        let starts = ret.iter().map(|x| x.2).collect::<Vec<_>>();
+        ret.into_iter()
+            .enumerate()
+            .map(|(i, (name, range, start))| {
+                let end = starts.get(i + 1).copied().unwrap_or(end);
+                let region = LspRange::new(LspPosition::new(start, 0), LspPosition::new(end, 0));
+                (name, range, region)
+            })
+            .collect()
+    }
+
+    /// Branches and jumps to labels of the document as the line of the jump, the label name and the
+    /// client range of the target operand.
+    fn label_jumps(&self) -> Vec<(u32, String, LspRange)> {
+        let document = &self.document_data;
+        let content = document.content.as_bytes();
+        let Some(tree) = document.tree.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut ret = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut tree_cursor = tree.walk();
+        let query = &*queries::INSTRUCTIONS;
+        for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+            let node = capture.captures[0].node;
+            let Some(operation) = node.child_by_field_name("operation") else {
+                continue;
+            };
+            if !instructions::BRANCH_INSTRUCTIONS.contains(operation.utf8_text(content).unwrap()) {
+                continue;
+            }
+            let Some(target) = node
+                .children_by_field_name("operand", &mut tree_cursor)
+                .last()
+                .and_then(|x| x.named_child(0))
+                .filter(|x| x.kind() == "identifier")
+            else {
+                continue;
+            };
+            let name = target.utf8_text(content).unwrap();
+            if self
+                .type_data
+                .labels
+                .get(name)
+                .is_some_and(|x| x.source.is_none())
+            {
+                ret.push((
+                    node.start_position().row as u32,
+                    name.to_string(),
+                    document.client_range(target),
+                ));
+            }
+        }
+        ret
+    }
+
+    /// A call hierarchy item for a label of the document, spanning its region.
+    fn call_hierarchy_item(&self, name: &str) -> Option<CallHierarchyItem> {
+        let document = &self.document_data;
+        let (name, range, region) = self.label_regions().into_iter().find(|x| x.0 == name)?;
+
+        Some(CallHierarchyItem {
+            name: name.clone(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri: document.url.clone(),
+            range: document.to_client_range(region),
+            selection_range: document.to_client_range(range.0),
+            data: None,
+        })
+    }
+
+    /// Statistics about the size of the program, for the `stats` command.
+    ///
+    /// The stack depth is estimated by following the program top to bottom, ignoring jumps.
+    fn stats(&self, config: &Configuration) -> Value {
+        let document = &self.document_data;
+        let content = document.content.as_bytes();
+
+        let mut text = document.content.as_str();
+        if document.appended_newline {
+            text = text.strip_suffix('\n').unwrap_or(text);
+        }
+
+        let mut registers = Vec::new();
+        let (mut pushes, mut pops, mut depth, mut max_depth) = (0, 0, 0i64, 0);
+        if let Some(tree) = document.tree.as_ref() {
+            let mut cursor = QueryCursor::new();
+            let mut tree_cursor = tree.walk();
+            let query = &*queries::INSTRUCTIONS;
+            for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+                let node = capture.captures[0].node;
+                for operand in node
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .filter_map(|x| x.named_child(0))
+                {
+                    if let Some(register) = self.type_data.register_name(operand, content) {
+                        if !registers.contains(&register) {
+                            registers.push(register);
+                        }
+                    }
+                }
+
+                match node
+                    .child_by_field_name("operation")
+                    .map(|x| x.utf8_text(content).unwrap())
+                {
+                    Some("push") => {
+                        pushes += 1;
+                        depth += 1;
+                        max_depth = max_depth.max(depth);
+                    }
+                    Some("pop") => {
+                        pops += 1;
+                        depth = (depth - 1).max(0);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        registers.sort_by_key(|x| parse_register(x).map(|x| x.1));
+
+        json!({
+            "lines": text.lines().count(),
+            "instructions": self.instruction_count(config),
+            "max_instructions": config.max_lines,
+            "longest_line": text.lines().map(|x| x.chars().count()).max().unwrap_or(0),
+            "max_columns": config.max_columns,
+            "registers": registers,
+            "stack": {
+                "pushes": pushes,
+                "pops": pops,
+                "max_depth": max_depth,
+            },
+        })
+    }
+
+    /// The defines, aliases and labels of the document itself, sorted by line. Define values are
+    /// resolved to numbers where possible.
+    fn symbols(&self) -> Value {
+        let type_data = &self.type_data;
+
+        let defines = type_data.defines.iter().map(|(name, data)| {
+            let value = constant_value(&data.value).map_or_else(|| json!(data.value), |x| json!(x));
+            (name, "define", value, &data.range, &data.source)
+        });
+        let aliases = type_data.aliases.iter().map(|(name, data)| {
+            let value = json!(data.value.to_string());
+            (name, "alias", value, &data.range, &data.source)
+        });
+        let labels = type_data
+            .labels
+            .iter()
+            .map(|(name, data)| (name, "label", json!(data.value), &data.range, &data.source));
+
+        let mut symbols = defines
+            .chain(aliases)
+            .chain(labels)
+            .filter(|x| x.4.is_none())
+            .map(|(name, kind, value, range, _)| (range.0.start.line, name, kind, value))
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|x| x.0);
+
+        symbols
+            .into_iter()
+            .map(|(line, name, kind, value)| {
+                json!({
+                    "name": name,
+                    "kind": kind,
+                    "value": value,
+                    "line": line,
+                })
+            })
+            .collect()
+    }
+
+    /// Collects definitions and computes all diagnostics of the document.
+    fn diagnostics(&mut self, config: &Configuration) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        // Collect definitions
+        self.update_definitions(&mut diagnostics);
+        diagnostics.retain_mut(|diagnostic| {
+            let Some(NumberOrString::String(code)) = &diagnostic.code else {
+                return true;
+            };
+            if code != LINT_INCLUDE_CONFLICT && code != LINT_SAME_VALUE_REDEFINITION {
+                return true;
+            }
+            diagnostic.severity = config.lint_severity(code, DiagnosticSeverity::WARNING);
+            diagnostic.severity.is_some()
+        });
+
+        let file_data = &*self;
+        let document = &file_data.document_data;
+        let Some(tree) = document.tree.as_ref() else {
+            return diagnostics;
+        };
+
+        // Syntax errors
+        {
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::SYNTAX_ERRORS;
+            let captures = cursor.captures(query, tree.root_node(), document.content.as_bytes());
+            for (capture, _) in captures {
+                let node = capture.captures[0].node;
+                // Unexpected tokens are wrapped in a second error node
+                if node.parent().is_some_and(|x| x.is_error()) {
+                    continue;
+                }
+                diagnostics.push(Diagnostic::new(
+                    Range::from(node.range()).into(),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    syntax_error_message(node, &document.content),
+                    None,
+                    None,
+                ));
+            }
+        }
+
+        // Find invalid instructions
+        {
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::INVALID_INSTRUCTIONS;
+            let captures = cursor.captures(query, tree.root_node(), document.content.as_bytes());
+            for (capture, _) in captures {
+                let node = capture.captures[0].node;
+                if instructions::is_custom_instruction(
+                    node.utf8_text(document.content.as_bytes()).unwrap(),
+                ) {
+                    continue;
+                }
+                diagnostics.push(Diagnostic::new(
+                    Range::from(capture.captures[0].node.range()).into(),
+                    Some(DiagnosticSeverity::ERROR),
+                    None,
+                    None,
+                    "Invalid instruction".to_string(),
+                    None,
+                    None,
+                ));
+            }
+        }
+
+        // Type check
+        self.check_types(config, &mut diagnostics);
+
+        // Overlength checks
+        {
+            let mut cursor = QueryCursor::new();
+
+            let query = &*queries::INSTRUCTIONS;
+            for (capture, _) in
+                cursor.captures(query, tree.root_node(), document.content.as_bytes())
+            {
+                let node = capture.captures[0].node;
+                if let Some(start) = document.column_overflow(node.end_position(), config) {
+                    diagnostics.push(Diagnostic {
+                        range: LspRange::new(start, Position::from(node.end_position()).into()),
+                        severity: Some(config.overcolumn_instruction_severity),
+                        message: format!("Instruction past column {}", config.max_columns),
+                        ..Default::default()
+                    });
+                }
+            }
+
+            if config.warn_overcolumn_comment {
+                let query = &*queries::COMMENTS;
+                for (capture, _) in
+                    cursor.captures(query, tree.root_node(), document.content.as_bytes())
+                {
+                    let node = capture.captures[0].node;
+                    if let Some(start) = document.column_overflow(node.end_position(), config) {
+                        diagnostics.push(Diagnostic {
+                            range: LspRange::new(start, Position::from(node.end_position()).into()),
+                            severity: Some(DiagnosticSeverity::WARNING),
+                            message: format!("Comment past column {}", config.max_columns),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+
+            cursor.set_point_range(
+                tree_sitter::Point::new(config.max_lines, 0)
+                    ..tree_sitter::Point::new(usize::MAX, usize::MAX),
+            );
+            let query = &*queries::INSTRUCTIONS;
+
+            for (capture, _) in
+                cursor.captures(query, tree.root_node(), document.content.as_bytes())
+            {
+                let node = capture.captures[0].node;
+                diagnostics.push(Diagnostic {
+                    range: Range::from(node.range()).into(),
+                    severity: Some(config.overline_instruction_severity),
+                    message: format!("Instruction past line {}", config.max_lines),
+                    ..Default::default()
+                });
+            }
+
+            if config.warn_overline_comment {
+                let query = &*queries::COMMENTS;
+                for (capture, _) in
+                    cursor.captures(query, tree.root_node(), document.content.as_bytes())
+                {
+                    let node = capture.captures[0].node;
+                    diagnostics.push(Diagnostic {
+                        range: Range::from(node.range()).into(),
+                        severity: Some(DiagnosticSeverity::WARNING),
+                        message: format!("Comment past line {}", config.max_lines),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        // Instruction count summary
+        if config.instruction_count {
+            diagnostics.push(Diagnostic {
+                range: LspRange::default(),
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                message: format!(
+                    "{}/{} instructions used",
+                    file_data.instruction_count(config),
+                    config.max_lines
+                ),
+                ..Default::default()
+            });
+        }
+
+        // Absolute jump to number lint
+        if let Some(severity) =
+            config.lint_severity(LINT_ABSOLUTE_JUMP, DiagnosticSeverity::WARNING)
+        {
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::NUMBER_OPERANDS;
+            let mut tree_cursor = tree.walk();
+            let captures = cursor.captures(query, tree.root_node(), document.content.as_bytes());
+            for (capture, _) in captures {
+                let capture = capture.captures[0].node;
+                let Some(operation_node) = capture.child_by_field_name("operation") else {
+                    continue;
+                };
+                let operation = operation_node
+                    .utf8_text(document.content.as_bytes())
+                    .unwrap();
+                if !instructions::BRANCH_INSTRUCTIONS.contains(operation) {
+                    continue;
+                }
+
+                tree_cursor.reset(capture);
+                let Some(last_operand) = capture
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .last()
+                else {
+                    continue;
+                };
+                let last_operand = last_operand.child(0).unwrap();
+
+                if last_operand.kind() == "number" {
+                    diagnostics.push(Diagnostic::new(
+                        Range::from(capture.range()).into(),
+                        Some(severity),
+                        Some(NumberOrString::String(LINT_ABSOLUTE_JUMP.to_string())),
+                        None,
+                        "Absolute jump to line number".to_string(),
+                        None,
+                        None,
+                    ));
+                }
+            }
+        }
+
+        // Number batch and reagent modes
+        for (node, code, replacement) in document.literal_modes() {
+            let replacement = match replacement {
+                Ok(replacement) => replacement,
+                Err(message) => {
+                    diagnostics.push(Diagnostic {
+                        range: Range::from(node.range()).into(),
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        message,
+                        ..Default::default()
+                    });
+                    continue;
+                }
+            };
+
+            let Some(severity) = config.lint_severity(code, DiagnosticSeverity::WARNING) else {
+                continue;
+            };
+            let kind = if code == LINT_NUMBER_BATCH_MODE {
+                "batch mode"
+            } else {
+                "reagent mode"
+            };
+            diagnostics.push(Diagnostic {
+                range: Range::from(node.range()).into(),
+                severity: Some(severity),
+                code: Some(NumberOrString::String(code.to_string())),
+                message: format!("Use of literal number for {kind}"),
+                data: Some(Value::String(replacement.to_string())),
+                ..Default::default()
+            });
+        }
+
+        // Float equality lint
+        if let (true, Some(severity)) = (
+            config.warn_float_equality,
+            config.lint_severity(LINT_FLOAT_EQUALITY, DiagnosticSeverity::HINT),
+        ) {
+            const APPROXIMATE_BRANCHES: phf::Map<&'static str, &'static str> = phf::phf_map! {
+                "beq" => "bap",
+                "beqal" => "bapal",
+                "breq" => "brap",
+                "beqz" => "bapz",
+                "beqzal" => "bapzal",
+                "breqz" => "brapz",
+                "bne" => "bna",
+                "bneal" => "bnaal",
+                "brne" => "brna",
+                "bnez" => "bnaz",
+                "bnezal" => "bnazal",
+                "brnez" => "brnaz",
+            };
+            const FLOAT_SOURCES: phf::Set<&'static str> = phf_set!(
+                "lr", "div", "sqrt", "exp", "log", "sin", "cos", "tan", "asin", "acos", "atan",
+                "atan2", "rand"
+            );
+            const LOADS: phf::Set<&'static str> = phf_set!("l", "lb", "lbn", "lbs", "lbns", "ls");
+
+            let type_data = &file_data.type_data;
+            let content = document.content.as_bytes();
+
+            // Registers currently believed to hold a floating point value
+            let mut float_registers = std::collections::HashSet::new();
+
+            let is_float = |node: Node, float_registers: &std::collections::HashSet<String>| {
+                let text = node.utf8_text(content).unwrap();
+                match node.kind() {
+                    "number" => {
+                        text.contains('.')
+                            || matches!(text, "pi" | "deg2rad" | "rad2deg" | "epsilon")
+                    }
+                    "identifier" if type_data.defines.contains_key(text) => {
+                        type_data.defines[text].value.contains('.')
+                    }
+                    _ => type_data
+                        .register_name(node, content)
+                        .is_some_and(|x| float_registers.contains(&x)),
+                }
+            };
+
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::INSTRUCTIONS;
+            let mut tree_cursor = tree.walk();
+
+            for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+                let node = capture.captures[0].node;
+                let Some(operation_node) = node.child_by_field_name("operation") else {
+                    continue;
+                };
+                let operation = operation_node.utf8_text(content).unwrap();
+
+                let operands = node
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .filter_map(|x| x.named_child(0))
+                    .collect::<Vec<_>>();
+
+                if let Some(replacement) = APPROXIMATE_BRANCHES.get(operation) {
+                    let compared = if operation.contains('z') { 1 } else { 2 };
+                    if operands
+                        .iter()
+                        .take(compared)
+                        .any(|x| is_float(*x, &float_registers))
+                    {
+                        diagnostics.push(Diagnostic {
+                            range: Range::from(node.range()).into(),
+                            severity: Some(severity),
+                            code: Some(NumberOrString::String(LINT_FLOAT_EQUALITY.to_string())),
+                            message: format!(
+                                "Exact comparison of floating point values. Consider using {replacement} instead"
+                            ),
+                            ..Default::default()
+                        });
+                    }
+                    continue;
+                }
+
+                let Some(signature) = instructions::signature(&config.ruleset, operation) else {
+                    continue;
+                };
+                if !signature.0.first().is_some_and(|x| {
+                    x.match_type(instructions::DataType::Register) && x.0.len() == 1
+                }) {
+                    continue;
+                }
+                let Some(target) = operands
+                    .first()
+                    .and_then(|x| type_data.register_name(*x, content))
+                else {
+                    continue;
+                };
+
+                // Loads only give fractions for logic types that can hold them, or when averaging
+                let loads_float = LOADS.contains(operation)
+                    && signature
+                        .0
+                        .iter()
+                        .zip(&operands)
+                        .any(|(parameter, operand)| {
+                            use instructions::DataType;
+
+                            let text = operand.utf8_text(content).unwrap();
+                            if parameter.match_type(DataType::LogicType) {
+                                instructions::FRACTIONAL_LOGIC_TYPES.contains(text)
+                            } else if parameter.match_type(DataType::SlotLogicType) {
+                                instructions::FRACTIONAL_SLOT_LOGIC_TYPES.contains(text)
+                            } else if parameter.match_type(DataType::BatchMode) {
+                                text == "Average"
+                                    || type_data.number_value(*operand, content) == Some(0.0)
+                            } else {
+                                false
+                            }
+                        });
+
+                let produces_float = FLOAT_SOURCES.contains(operation)
+                    || loads_float
+                    || (operation == "move"
+                        && operands
+                            .get(1)
+                            .is_some_and(|x| is_float(*x, &float_registers)));
+
+                if produces_float {
+                    float_registers.insert(target);
+                } else {
+                    float_registers.remove(&target);
+                }
+            }
+        }
+
+        // Unreachable code after unconditional jumps
+        if let Some(severity) =
+            config.lint_severity(LINT_UNREACHABLE_CODE, DiagnosticSeverity::HINT)
+        {
+            let content = document.content.as_bytes();
+
+            let mut unreachable = false;
+            let mut span: Option<(Node, Node)> = None;
+            let mut flush = |span: &mut Option<(Node, Node)>| {
+                if let Some((start, end)) = span.take() {
+                    diagnostics.push(Diagnostic {
+                        range: LspRange::new(
+                            Position::from(start.start_position()).into(),
+                            Position::from(end.end_position()).into(),
+                        ),
+                        severity: Some(severity),
+                        code: Some(NumberOrString::String(LINT_UNREACHABLE_CODE.to_string())),
+                        message: "Unreachable code".to_string(),
+                        tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                        ..Default::default()
+                    });
+                }
+            };
+
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::LINES;
+            for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+                let Some(node) = capture.captures[0].node.named_child(0) else {
+                    continue;
+                };
+
+                match node.kind() {
+                    "label" => {
+                        flush(&mut span);
+                        unreachable = false;
+                    }
+                    "instruction" => {
+                        let operation = node
+                            .child_by_field_name("operation")
+                            .map(|x| x.utf8_text(content).unwrap())
+                            .unwrap_or("");
+                        if operation == "define" {
+                            continue;
+                        }
+
+                        if unreachable {
+                            span = Some(match span {
+                                Some((start, _)) => (start, node),
+                                None => (node, node),
+                            });
+                        }
+                        if operation == "j" || operation == "jr" {
+                            unreachable = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            flush(&mut span);
+        }
+
+        // Aliases sharing a target
+        if let (true, Some(severity)) = (
+            config.warn_duplicate_alias_target,
+            config.lint_severity(LINT_DUPLICATE_ALIAS_TARGET, DiagnosticSeverity::WARNING),
+        ) {
+            let mut aliases = file_data
+                .type_data
+                .aliases
+                .iter()
+                .filter(|x| x.1.source.is_none())
+                .collect::<Vec<_>>();
+            aliases.sort_by_key(|x| x.1.range.0.start);
+
+            let mut targets: HashMap<String, (&String, &Range)> = HashMap::new();
+            for (name, definition) in aliases {
+                let target = match definition.value.to_string().as_str() {
+                    "sp" => "r16".to_string(),
+                    "ra" => "r17".to_string(),
+                    target => target.to_string(),
+                };
+                let Some((previous_name, previous_range)) = targets.get(&target) else {
+                    targets.insert(target, (name, &definition.range));
+                    continue;
+                };
+
+                diagnostics.push(Diagnostic {
+                    range: definition.range.0,
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String(
+                        LINT_DUPLICATE_ALIAS_TARGET.to_string(),
+                    )),
+                    message: format!("{target} is already aliased as '{previous_name}'"),
+                    related_information: Some(vec![DiagnosticRelatedInformation {
+                        location: Location::new(document.url.clone(), previous_range.0),
+                        message: format!("'{previous_name}' is defined here"),
+                    }]),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Labels with no instruction between them, which all jump to the same code
+        if let Some(severity) = config.lint_severity(LINT_REDUNDANT_LABEL, DiagnosticSeverity::HINT)
+        {
+            let mut cursor = QueryCursor::new();
+            let instruction_rows = cursor
+                .captures(
+                    &queries::INSTRUCTIONS,
+                    tree.root_node(),
+                    document.content.as_bytes(),
+                )
+                .map(|(capture, _)| capture.captures[0].node.start_position().row as u32)
+                .collect::<Vec<_>>();
+
+            let mut labels = file_data
+                .type_data
+                .labels
+                .iter()
+                .filter(|x| x.1.source.is_none())
+                .collect::<Vec<_>>();
+            labels.sort_by_key(|x| x.1.value);
+
+            let mut first: Option<(&String, &DefinitionData<u32>)> = None;
+            for (name, label) in labels {
+                let redundant_to = first.filter(|(_, first)| {
+                    !instruction_rows
+                        .iter()
+                        .any(|row| (first.value..label.value).contains(row))
+                });
+                let Some((first_name, first_label)) = redundant_to else {
+                    first = Some((name, label));
+                    continue;
+                };
+                diagnostics.push(Diagnostic {
+                    range: label.range.0,
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String(LINT_REDUNDANT_LABEL.to_string())),
+                    message: format!("Label '{name}' jumps to the same code as '{first_name}'"),
+                    related_information: Some(vec![DiagnosticRelatedInformation {
+                        location: Location::new(
+                            document.url.clone(),
+                            first_label.range.clone().into(),
+                        ),
+                        message: format!("'{first_name}' is defined here"),
+                    }]),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Unused defines, aliases and labels
+        //
+        // Files consisting only of definitions are likely meant to be included elsewhere, so they
+        // are skipped.
+        if let Some(severity) =
+            config.lint_severity(LINT_UNUSED_DEFINITION, DiagnosticSeverity::HINT)
+        {
+            let content = document.content.as_bytes();
+
+            let mut has_code = false;
+            let mut usages: HashMap<&str, usize> = HashMap::new();
+            let mut cursor = QueryCursor::new();
+            for (capture, _) in cursor.captures(&queries::OPERATIONS, tree.root_node(), content) {
+                let operation = capture.captures[0].node.utf8_text(content).unwrap();
+                has_code |= !matches!(operation, "define" | "alias" | "label");
+            }
+            for (name, _) in document.identifiers() {
+                *usages.entry(name).or_default() += 1;
+            }
+
+            let mut unused = file_data
+                .type_data
+                .symbols()
+                .filter(|(name, ..)| has_code && usages.get(name.as_str()).copied() <= Some(1))
+                .collect::<Vec<_>>();
+            unused.sort_by_key(|x| x.1 .0.start);
+
+            for (name, range, kind) in unused {
+                let kind = match kind {
+                    SymbolKind::NUMBER => "Define",
+                    SymbolKind::VARIABLE => "Alias",
+                    _ => "Label",
+                };
+                diagnostics.push(Diagnostic {
+                    range: range.0,
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String(LINT_UNUSED_DEFINITION.to_string())),
+                    message: format!("{kind} '{name}' is never used"),
+                    tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Defines used in unrelated roles, such as both a jump target and a prefab hash
+        if let Some(severity) =
+            config.lint_severity(LINT_MIXED_DEFINE_USE, DiagnosticSeverity::INFORMATION)
+        {
+            use instructions::DataType;
+
+            let content = document.content.as_bytes();
+            let mut roles: HashMap<&str, Vec<(&str, Node)>> = HashMap::new();
+            for (name, node) in document.identifiers() {
+                if file_data
+                    .type_data
+                    .defines
+                    .get(name)
+                    .is_none_or(|x| x.source.is_some())
+                {
+                    continue;
+                }
+                let Some(operand) = node.parent().filter(|x| x.kind() == "operand") else {
+                    continue;
+                };
+                let Some(instruction) = operand.parent() else {
+                    continue;
+                };
+                let Some(operation) = instruction
+                    .child_by_field_name("operation")
+                    .map(|x| x.utf8_text(content).unwrap())
+                else {
+                    continue;
+                };
+                if operation == "define" {
+                    continue;
+                }
+
+                let mut tree_cursor = instruction.walk();
+                let operands = instruction
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .collect::<Vec<_>>();
+                let index = operands
+                    .iter()
+                    .position(|x| x.id() == operand.id())
+                    .unwrap();
+                let parameter = instructions::signature(&config.ruleset, operation)
+                    .and_then(|x| x.0.get(index));
+
+                let is_branch = instructions::BRANCH_INSTRUCTIONS.contains(operation)
+                    || operation == "jr"
+                    || instructions::RELATIVE_BRANCHES
+                        .values()
+                        .any(|x| *x == operation);
+                let role = if is_branch && index + 1 == operands.len() {
+                    "jump target"
+                } else if instructions::prefab_hash_parameter(operation) == Some(index) {
+                    "prefab hash"
+                } else if parameter.is_some_and(|x| x.match_type(DataType::BatchMode)) {
+                    "batch mode"
+                } else if parameter.is_some_and(|x| x.match_type(DataType::ReagentMode)) {
+                    "reagent mode"
+                } else if parameter.is_some_and(|x| x.match_type(DataType::SlotIndex)) {
+                    "slot index"
+                } else {
+                    "number"
+                };
+                roles.entry(name).or_default().push((role, node));
+            }
+
+            let mut names = roles.keys().copied().collect::<Vec<_>>();
+            names.sort_by_key(|x| file_data.type_data.defines[*x].range.0.start);
+            for name in names {
+                let uses = &roles[name];
+                let mut distinct = Vec::new();
+                for (role, _) in uses {
+                    if !distinct.contains(role) {
+                        distinct.push(*role);
+                    }
+                }
+                // Plain numbers only clash with jump targets, which are line numbers
+                if !distinct.contains(&"jump target") {
+                    distinct.retain(|x| *x != "number");
+                }
+                if distinct.len() < 2 {
+                    continue;
+                }
+
+                let last = distinct.pop().unwrap();
+                diagnostics.push(Diagnostic {
+                    range: file_data.type_data.defines[name].range.0,
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String(LINT_MIXED_DEFINE_USE.to_string())),
+                    message: format!(
+                        "'{name}' is used as a {} and as a {last}",
+                        distinct.join(", a ")
+                    ),
+                    related_information: Some(
+                        uses.iter()
+                            .map(|(role, node)| DiagnosticRelatedInformation {
+                                location: Location::new(
+                                    document.url.clone(),
+                                    Range::from(node.range()).into(),
+                                ),
+                                message: format!("Used as a {role}"),
+                            })
+                            .collect(),
+                    ),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Defines and aliases used before their definition. Labels can be jumped to from anywhere.
+        if let Some(severity) =
+            config.lint_severity(LINT_USE_BEFORE_DEFINITION, DiagnosticSeverity::WARNING)
+        {
+            let type_data = &file_data.type_data;
+            for (name, node) in document.identifiers() {
+                let definition = type_data
+                    .defines
+                    .get(name)
+                    .map(|x| (&x.range, &x.source))
+                    .or_else(|| type_data.aliases.get(name).map(|x| (&x.range, &x.source)));
+                let Some((range, None)) = definition else {
+                    continue;
+                };
+                if range.0.start.line as usize <= node.start_position().row {
+                    continue;
+                }
+
+                diagnostics.push(Diagnostic {
+                    range: Range::from(node.range()).into(),
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String(
+                        LINT_USE_BEFORE_DEFINITION.to_string(),
+                    )),
+                    message: "Used before definition".to_string(),
+                    related_information: Some(vec![DiagnosticRelatedInformation {
+                        location: Location::new(document.url.clone(), range.0),
+                        message: format!("'{name}' is defined here"),
+                    }]),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Loops without yield
+        //
+        // Conservative: only an unconditional `j` back to a label is considered, and only if the
+        // loop body cannot yield, sleep, call or leave the loop through a branch, so bounded loops
+        // exiting through a branch are not reported.
+        if let (true, Some(severity)) = (
+            config.warn_missing_yield,
+            config.lint_severity(LINT_MISSING_YIELD, DiagnosticSeverity::HINT),
+        ) {
+            let content = document.content.as_bytes();
+            let labels = &file_data.type_data.labels;
+            let label_row = |node: Option<&Node>| {
+                let node = node.filter(|x| x.kind() == "identifier")?;
+                labels
+                    .get(node.utf8_text(content).unwrap())
+                    .filter(|x| x.source.is_none())
+                    .map(|x| x.range.0.start.line as usize)
+            };
+
+            let mut instructions = Vec::new();
+            let mut cursor = QueryCursor::new();
+            let mut tree_cursor = tree.walk();
+            for (capture, _) in cursor.captures(&queries::INSTRUCTIONS, tree.root_node(), content) {
+                let node = capture.captures[0].node;
+                let Some(operation_node) = node.child_by_field_name("operation") else {
+                    continue;
+                };
+                let operands = node
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .filter_map(|x| x.named_child(0))
+                    .collect::<Vec<_>>();
+                instructions.push((node, operation_node.utf8_text(content).unwrap(), operands));
+            }
+
+            for (index, (node, operation, operands)) in instructions.iter().enumerate() {
+                let row = node.start_position().row;
+                if *operation != "j" {
+                    continue;
+                }
+                let Some(start) = label_row(operands.first()).filter(|x| *x <= row) else {
+                    continue;
+                };
+
+                let escapes = instructions[..index]
+                    .iter()
+                    .filter(|x| x.0.start_position().row >= start)
+                    .any(|(_, operation, operands)| match *operation {
+                        "yield" | "sleep" | "hcf" | "jr" => true,
+                        _ if operation.starts_with("br") => true,
+                        _ if instructions::BRANCH_INSTRUCTIONS.contains(operation) => {
+                            operation.ends_with("al")
+                                || !label_row(operands.last())
+                                    .is_some_and(|x| (start..=row).contains(&x))
+                        }
+                        _ => false,
+                    });
+                if escapes {
+                    continue;
+                }
+
+                diagnostics.push(Diagnostic {
+                    range: Range::from(node.range()).into(),
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String(LINT_MISSING_YIELD.to_string())),
+                    message: "Loop without `yield` or `sleep`".to_string(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Stack balance
+        //
+        // Heuristic: before the first label the stack depth is known to start at 0, so popping at
+        // depth 0 is reported. Every label starts a new region, in which the depth is counted
+        // relative to the label, so popping arguments pushed by the caller is fine. A region
+        // returning with `j ra` while having pushed more than it popped is reported.
+        if let (true, Some(severity)) = (
+            config.warn_stack_balance,
+            config.lint_severity(LINT_STACK_BALANCE, DiagnosticSeverity::WARNING),
+        ) {
+            let type_data = &file_data.type_data;
+            let content = document.content.as_bytes();
+
+            let mut depth = Some(0i64);
+            let mut in_label_region = false;
+
+            let mut cursor = QueryCursor::new();
+            let query = &*queries::LINES;
+            let mut tree_cursor = tree.walk();
+
+            for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+                let line_node = capture.captures[0].node;
+                let Some(node) = line_node.named_child(0) else {
+                    continue;
+                };
+
+                if node.kind() == "label" {
+                    depth = Some(0);
+                    in_label_region = true;
+                    continue;
+                }
+                if node.kind() != "instruction" {
+                    continue;
+                }
+
+                let Some(operation_node) = node.child_by_field_name("operation") else {
+                    continue;
+                };
+                let operation = operation_node.utf8_text(content).unwrap();
+                let operands = node
+                    .children_by_field_name("operand", &mut tree_cursor)
+                    .filter_map(|x| x.named_child(0))
+                    .collect::<Vec<_>>();
+                let first_register = operands
+                    .first()
+                    .and_then(|x| type_data.register_name(*x, content));
+
+                match operation {
+                    "push" => depth = depth.map(|x| x + 1),
+                    "pop" | "peek" => {
+                        if !in_label_region && depth == Some(0) {
+                            diagnostics.push(Diagnostic {
+                                range: Range::from(node.range()).into(),
+                                severity: Some(severity),
+                                code: Some(NumberOrString::String(LINT_STACK_BALANCE.to_string())),
+                                message: format!("'{operation}' may run with an empty stack"),
+                                ..Default::default()
+                            });
+                        }
+                        if operation == "pop" {
+                            depth = depth.map(|x| x - 1);
+                        }
+                    }
+                    "j" if first_register.as_deref() == Some("r17") => {
+                        if let Some(depth) = depth.filter(|x| in_label_region && *x > 0) {
+                            diagnostics.push(Diagnostic {
+                                range: Range::from(node.range()).into(),
+                                severity: Some(severity),
+                                code: Some(NumberOrString::String(LINT_STACK_BALANCE.to_string())),
+                                message: format!(
+                                    "Returning with {depth} more value{} pushed than popped",
+                                    if depth == 1 { "" } else { "s" }
+                                ),
+                                ..Default::default()
+                            });
+                        }
+                        depth = None;
+                    }
+                    "j" | "jr" => depth = None,
+                    _ => {
+                        // Any other write to sp makes the depth unknown
+                        let writes_register = instructions::signature(&config.ruleset, operation)
+                            .and_then(|x| x.0.first())
+                            .is_some_and(|x| x.0 == [instructions::DataType::Register]);
+                        if writes_register && first_register.as_deref() == Some("r16") {
+                            depth = None;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Suppression comments
+        let suppressions = document.suppressions();
+        diagnostics.retain(
+            |diagnostic| match suppressions.get(&diagnostic.range.start.line) {
+                None => true,
+                Some(None) => false,
+                Some(Some(codes)) => !matches!(
+                    &diagnostic.code,
+                    Some(NumberOrString::String(code)) if codes.contains(&code.as_str())
+                ),
+            },
+        );
+
+        diagnostics
+    }
+}
+
+/// Edits turning an absolute jump to a line number into the equivalent relative jump, along with
+/// the name of the relative instruction.
+///
+/// Returns `None` for instructions without a relative counterpart, or without a literal line
+/// number as their target.
+fn relative_jump_edits(
+    instruction: Node,
+    document: &DocumentData,
+) -> Option<(&'static str, Vec<TextEdit>)> {
+    let content = document.content.as_bytes();
+    let operation = instruction.child_by_field_name("operation")?;
+    let replacement = instructions::RELATIVE_BRANCHES.get(operation.utf8_text(content).unwrap())?;
+
+    let mut cursor = instruction.walk();
+    let target = instruction
+        .children_by_field_name("operand", &mut cursor)
+        .last()?
+        .named_child(0)
+        .filter(|x| x.kind() == "number")?;
+    let line = parse_number(target.utf8_text(content).unwrap())?;
+    let offset = line - instruction.start_position().row as f64;
+
+    Some((
+        replacement,
+        vec![
+            TextEdit::new(document.client_range(operation), replacement.to_string()),
+            TextEdit::new(document.client_range(target), offset.to_string()),
+        ],
+    ))
+}
+
+/// Whether `name` can be used as a define, alias or label name.
+fn is_valid_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && parse_register(name).is_none()
+        && !matches!(name, "sp" | "ra" | "db")
+        && instructions::signature(instructions::DEFAULT_RULESET, name).is_none()
+}
+
+/// Collects the indices of all registers and devices referenced anywhere in the document.
+fn used_registers(
+    tree: &Tree,
+    content: &[u8],
+) -> (
+    std::collections::HashSet<usize>,
+    std::collections::HashSet<usize>,
+) {
+    let mut registers = std::collections::HashSet::new();
+    let mut devices = std::collections::HashSet::new();
+
+    let mut cursor = QueryCursor::new();
+    let query = &*queries::REGISTERS_AND_DEVICES;
+    let register_idx = query.capture_index_for_name("register").unwrap();
+
+    for (capture, _) in cursor.captures(query, tree.root_node(), content) {
+        let capture = capture.captures[0];
+        let text = capture.node.utf8_text(content).unwrap();
+        if capture.index == register_idx {
+            if let Ok(index) = text.trim_start_matches('r').parse() {
+                registers.insert(index);
+            }
+        } else if let Some(rest) = text.strip_prefix('d') {
+            if let Ok(index) = rest.parse() {
+                devices.insert(index);
+            } else if let Ok(index) = rest.trim_start_matches('r').parse() {
+                registers.insert(index);
+            }
+        }
+    }
+
+    (registers, devices)
+}
+
+/// Whether the client can count columns in bytes, which is preferred over UTF-16.
+fn supports_utf8(capabilities: &ClientCapabilities) -> bool {
+    capabilities
+        .general
+        .as_ref()
+        .and_then(|x| x.position_encodings.as_ref())
+        .is_some_and(|x| x.contains(&PositionEncodingKind::UTF8))
+}
+
+/// Contents of an included file, with the modification time it was read at.
+type IncludedFile = (SystemTime, Arc<str>);
+
+static INCLUDED_FILES: LazyLock<std::sync::Mutex<HashMap<PathBuf, IncludedFile>>> =
+    LazyLock::new(Default::default);
+
+/// Reads an included file, reusing the contents read before as long as it has not been modified.
+///
+/// Includes are resolved on every diagnostics run, so this keeps edits from rereading them.
+fn read_included_file(path: &std::path::Path) -> std::io::Result<Arc<str>> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    let mut cache = INCLUDED_FILES.lock().unwrap();
+    if let Some((read_at, contents)) = cache.get(path) {
+        if *read_at == modified {
+            return Ok(contents.clone());
+        }
+    }
+    let contents = Arc::<str>::from(std::fs::read_to_string(path)?);
+    cache.insert(path.to_path_buf(), (modified, contents.clone()));
+    Ok(contents)
+}
+
+/// Extracts the path from an include directive like `# include "file.ic10"`.
+fn parse_include(comment: &str) -> Option<&str> {
+    let rest = comment
+        .strip_prefix('#')?
+        .trim_start()
+        .strip_prefix("include")?;
+    let path = rest.trim().strip_prefix('"')?.strip_suffix('"')?;
+    (!path.is_empty()).then_some(path)
+}
+
+/// Parses a decimal, `$` hexadecimal or `%` binary number, or a named constant.
+fn parse_number(text: &str) -> Option<f64> {
+    try_parse_number(text).ok()
+}
+
+/// Numeric value of a define's value or a constant operand, including `HASH("...")` calls.
+fn constant_value(text: &str) -> Option<f64> {
+    text.strip_prefix("HASH(\"")
+        .and_then(|x| x.strip_suffix("\")"))
+        .map(|x| instructions::hash(x) as f64)
+        .or_else(|| parse_number(text))
+}
+
+/// Looks up the name of a batch or reagent mode given as a number.
+///
+/// Returns a message describing the problem if the number is not an integer, is out of range or
+/// is not one of `modes`.
+fn mode_name(
+    value: f64,
+    kind: &str,
+    modes: &phf::Map<u8, &'static str>,
+) -> std::result::Result<&'static str, String> {
+    if value.fract() != 0.0 {
+        return Err(format!("Use of non-integer {kind}"));
+    }
+    let max = modes.keys().max().copied().unwrap_or(0);
+    if !(0.0..=max as f64).contains(&value) {
+        return Err(format!("Use of out-of-range {kind}, expected 0 to {max}"));
+    }
+    modes
+        .get(&(value as u8))
+        .copied()
+        .ok_or_else(|| format!("Invalid {kind}"))
+}
+
+/// Like [`parse_number`], but describes why parsing failed.
+fn try_parse_number(text: &str) -> std::result::Result<f64, String> {
+    if let Some(value) = instructions::CONSTANTS.get(text) {
+        return Ok(*value);
+    }
+    if let Some(hex) = text.strip_prefix('$') {
+        i64::from_str_radix(hex, 16)
+            .map(|x| x as f64)
+            .map_err(|x| x.to_string())
+    } else if let Some(binary) = text.strip_prefix('%') {
+        i64::from_str_radix(binary, 2)
+            .map(|x| x as f64)
+            .map_err(|x| x.to_string())
+    } else {
+        text.parse()
+            .map_err(|x: std::num::ParseFloatError| x.to_string())
+    }
+}
+
+/// Separates groups of `size` digits with underscores, counting from the right.
+fn group_digits(digits: &str, size: usize) -> String {
+    let mut result = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(size) {
+            result.push('_');
+        }
+        result.push(digit);
+    }
+    result
+}
+
+/// Parses registers the grammar does not recognize, such as `r16` or `rr20`, as well as the
+/// corresponding indirect device references like `dr16`.
+///
+/// Returns whether it is a device reference and the index of the register.
+fn parse_register(text: &str) -> Option<(bool, usize)> {
+    let (is_device, text) = match text.strip_prefix('d') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let digits = text.trim_start_matches('r');
+    if digits.len() == text.len()
+        || digits.is_empty()
+        || !digits.bytes().all(|x| x.is_ascii_digit())
+    {
+        return None;
+    }
+    Some((is_device, digits.parse().ok()?))
+}
+
+/// Error for `text` naming a register past the configured number of registers.
+fn nonexistent_register(
+    text: &str,
+    range: tree_sitter::Range,
+    config: &Configuration,
+) -> Option<Diagnostic> {
+    let (_, index) = parse_register(text)?;
+    if index < config.max_registers {
+        return None;
+    }
+    Some(Diagnostic::new(
+        Range::from(range).into(),
+        Some(DiagnosticSeverity::ERROR),
+        Some(NumberOrString::String(
+            ERROR_NONEXISTENT_REGISTER.to_string(),
+        )),
+        None,
+        format!(
+            "Register r{index} does not exist. Only r0 to r{} are available",
+            config.max_registers.saturating_sub(1)
+        ),
+        None,
+        None,
+    ))
+}
+
+/// What `name` refers to without any definitions, if it is not free to be defined.
+fn builtin_name_kind(name: &str) -> Option<&'static str> {
+    match parse_register(name) {
+        Some((true, _)) => Some("device"),
+        Some((false, _)) => Some("register"),
+        None if matches!(name, "sp" | "ra") => Some("register"),
+        None if instructions::ENUMS.contains(name) => Some("enum member"),
+        None if instructions::CONSTANTS.contains_key(name) => Some("constant"),
+        None => None,
+    }
+}
+
+/// Reads the project configuration file in `root`, if there is one, in the same form as the
+/// settings sent by the client.
+fn read_project_config(root: &std::path::Path) -> Option<std::result::Result<Value, String>> {
+    let text = std::fs::read_to_string(root.join(PROJECT_CONFIG_FILE)).ok()?;
+    let value = toml::from_str::<toml::Value>(&text)
+        .map_err(|err| err.to_string())
+        .and_then(|x| serde_json::to_value(x).map_err(|err| err.to_string()));
+    Some(value)
+}
+
+/// Reads the project configuration file that applies to the file at `path` outside of the language
+/// server. It is looked for in the directory of the file and its parents, then in the current
+/// directory.
+fn find_project_config(path: &std::path::Path) -> Option<std::result::Result<Value, String>> {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    path.parent()
+        .into_iter()
+        .flat_map(std::path::Path::ancestors)
+        .find_map(read_project_config)
+        .or_else(|| read_project_config(&std::env::current_dir().ok()?))
+}
+
+fn parse_severity(value: &str) -> Option<DiagnosticSeverity> {
+    match value.to_lowercase().as_str() {
+        "error" => Some(DiagnosticSeverity::ERROR),
+        "warning" => Some(DiagnosticSeverity::WARNING),
+        "information" | "info" => Some(DiagnosticSeverity::INFORMATION),
+        "hint" => Some(DiagnosticSeverity::HINT),
+        _ => None,
+    }
+}
+
+/// The part of `text`, which starts at column `start`, that lies before column `cursor`.
+///
+/// Returns `None` if the cursor is not on a character boundary.
+fn text_before(text: &str, start: usize, cursor: usize) -> Option<&str> {
+    text.get(..cursor.saturating_sub(start).min(text.len()))
+}
+
+/// Index and node of the operand the cursor at byte column `position` is in.
+///
+/// A cursor directly after an operand still belongs to it, as that is where it is being typed.
+/// Whitespace after that belongs to the following operand.
+fn get_current_parameter(instruction_node: Node, position: usize) -> (usize, Option<Node>) {
+    let mut ret: usize = 0;
+    let mut cursor = instruction_node.walk();
+    for operand in instruction_node.children_by_field_name("operand", &mut cursor) {
+        if operand.end_position().column >= position {
+            break;
+        }
+        ret += 1;
+    }
+
+    let operand = instruction_node
+        .children_by_field_name("operand", &mut cursor)
+        .nth(ret);
+
+    cursor.reset(instruction_node);
+    (ret, operand)
+}
+
+/// Whether a branch instruction comparing `values` jumps, or `None` if it is not a comparison.
+fn branch_taken(operation: &str, values: &[f64]) -> Option<bool> {
+    // Relative and linking branches compare the same way as the plain ones
+    let operation = operation.strip_prefix("br").unwrap_or(operation);
+    let operation = operation.strip_prefix('b').unwrap_or(operation);
+    let operation = operation.strip_suffix("al").unwrap_or(operation);
+
+    let approximately = |a: f64, b: f64, epsilon: f64| {
+        (a - b).abs() <= (epsilon * a.abs().max(b.abs())).max(f64::EPSILON * 8.0)
+    };
+    let taken = match (operation, values) {
+        ("ltz", [a]) => *a < 0.0,
+        ("gez", [a]) => *a >= 0.0,
+        ("lez", [a]) => *a <= 0.0,
+        ("gtz", [a]) => *a > 0.0,
+        ("eqz", [a]) => *a == 0.0,
+        ("nez", [a]) => *a != 0.0,
+        ("nan", [a]) => a.is_nan(),
+        ("apz", [a, epsilon]) => approximately(*a, 0.0, *epsilon),
+        ("naz", [a, epsilon]) => !approximately(*a, 0.0, *epsilon),
+        ("lt", [a, b]) => a < b,
+        ("gt", [a, b]) => a > b,
+        ("le", [a, b]) => a <= b,
+        ("ge", [a, b]) => a >= b,
+        ("eq", [a, b]) => a == b,
+        ("ne", [a, b]) => a != b,
+        ("ap", [a, b, epsilon]) => approximately(*a, *b, *epsilon),
+        ("na", [a, b, epsilon]) => !approximately(*a, *b, *epsilon),
+        _ => return None,
+    };
+    Some(taken)
+}
+
+/// Describes what went wrong at an `ERROR` node, based on its text and the nodes around it.
+fn syntax_error_message(node: Node, content: &str) -> String {
+    let text = node.utf8_text(content.as_bytes()).unwrap().trim();
+    let next = node
+        .next_sibling()
+        .map(|x| x.utf8_text(content.as_bytes()).unwrap());
+    // The operand directly before the error, if it is inside or after an instruction
+    let previous = match node.prev_named_sibling() {
+        Some(x) if x.kind() == "instruction" => x.named_child(x.named_child_count() - 1),
+        x => x,
+    };
+    let after_instruction = previous.is_some_and(|x| x.kind() == "operand");
+    let starts_line = node
+        .prev_sibling()
+        .is_none_or(|x| matches!(x.kind(), "line" | "newline"));
+
+    if node.named_child(0).is_some_and(|x| x.kind() == "label") {
+        return "Expected end of line after label".to_string();
+    }
+
+    match text {
+        "," if after_instruction => "Operands are separated by spaces, not commas".to_string(),
+        ":" if previous
+            .and_then(|x| x.named_child(0))
+            .is_some_and(|x| x.kind() == "device_spec") =>
+        {
+            "Expected network index after ':'".to_string()
+        }
+        "\"" => "Strings are only allowed in HASH(\"...\")".to_string(),
+        "$" if next.is_some() => "Expected hexadecimal digits after '$'".to_string(),
+        "%" if next.is_some() => "Expected binary digits after '%'".to_string(),
+        _ if starts_line => format!("Expected instruction or label, found '{text}'"),
+        _ if !text.is_empty() => format!("Unexpected '{text}'"),
+        _ => "Syntax error".to_string(),
+    }
+}
+
+trait NodeEx: Sized {
+    fn find_parent(&self, kind: &str) -> Option<Self>;
+    fn query<'a>(&'a self, query: &Query, content: impl AsRef<[u8]>) -> Option<Node<'a>>;
+}
+
+impl<'a> NodeEx for Node<'a> {
+    fn find_parent(&self, kind: &str) -> Option<Self> {
+        let mut cur = *self;
+        while cur.kind() != kind {
+            cur = cur.parent()?;
+        }
+        Some(cur)
+    }
+
+    fn query(&self, query: &Query, content: impl AsRef<[u8]>) -> Option<Node<'a>> {
+        let mut cursor = QueryCursor::new();
+
+        let mut captures = cursor.captures(query, *self, content.as_ref());
+        captures
+            .next()
+            .map(|x| x.0.captures)
+            .and_then(|x| x.first())
+            .map(|x| x.node)
+    }
+}
+
+/// Prints the diagnostics of a file to stderr and returns the exit code for `--check`.
+fn check(path: &std::path::Path, config: &Configuration) -> i32 {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            return 1;
+        }
+    };
+    let url = std::fs::canonicalize(path)
+        .ok()
+        .and_then(|path| Url::from_file_path(path).ok())
+        .unwrap_or_else(|| Url::parse("file:///").unwrap());
+
+    let mut file_data = FileData::new(url, text);
+    let mut diagnostics = file_data.diagnostics(config);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+
+    let mut errors = false;
+    for diagnostic in diagnostics {
+        let severity = match diagnostic.severity {
+            Some(DiagnosticSeverity::ERROR) | None => {
+                errors = true;
+                "error"
+            }
+            Some(DiagnosticSeverity::WARNING) => "warning",
+            Some(DiagnosticSeverity::INFORMATION) => "info",
+            _ => "hint",
+        };
+        eprintln!(
+            "{}:{}:{}: {severity}: {}",
+            path.display(),
+            diagnostic.range.start.line + 1,
+            diagnostic.range.start.character + 1,
+            diagnostic.message
+        );
+    }
+
+    i32::from(errors)
+}
+
+/// Formats a file for `--format`, printing the result or writing it back. Returns the exit code.
+fn format(path: &std::path::Path, config: &Configuration, write: bool) -> i32 {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            return 1;
+        }
+    };
+
+    let file_data = FileData::new(Url::parse("file:///").unwrap(), text.clone());
+    let document = &file_data.document_data;
+    let Some(ref tree) = document.tree else {
+        eprintln!("{}: could not parse file", path.display());
+        return 1;
+    };
+    let mut formatted =
+        formatting::format_text(tree, document.content.as_bytes(), config.align_operands);
+    if document.appended_newline {
+        formatted.pop();
+    }
+
+    if !write {
+        print!("{formatted}");
+    } else if formatted != text {
+        if let Err(e) = std::fs::write(path, formatted) {
+            eprintln!("{}: {e}", path.display());
+            return 1;
+        }
+    }
+    0
+}
+
+#[tokio::main]
+async fn main() {
+    use clap::Parser as _;
+    let cli = cli::Cli::parse();
+
+    queries::init();
+
+    let mut parser = Parser::new();
+    parser
+        .set_language(tree_sitter_ic10::language())
+        .expect("Failed to set language");
+
+    let mut config = Configuration::default();
+    // Outside of the language server, the project configuration is read here. Flags take precedence.
+    if let Some(path) = cli.check.as_ref().or(cli.format.as_ref()) {
+        match find_project_config(path) {
+            Some(Ok(value)) => {
+                for message in config.update(&value) {
+                    eprintln!("{PROJECT_CONFIG_FILE}: {message}");
+                }
+            }
+            Some(Err(err)) => {
+                eprintln!("{PROJECT_CONFIG_FILE}: {err}");
+                std::process::exit(1);
+            }
+            None => {}
+        }
+    }
+    if let Some(ruleset) = cli.ruleset {
+        if !instructions::RULESETS.contains_key(&ruleset) {
+            let mut available = instructions::RULESETS.keys().collect::<Vec<_>>();
+            available.sort();
+            eprintln!(
+                "Unknown ruleset '{ruleset}'. Available rulesets: {}",
+                available
+                    .into_iter()
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            std::process::exit(1);
+        }
+        config.ruleset = ruleset;
+    }
+
+    if let Some(path) = cli.data_dir {
+        if let Err(err) = instructions::load_data_dir(&path) {
+            eprintln!("Failed to load custom data: {err}");
+            std::process::exit(1);
+        }
+        config.data_dir = Some(path);
+    }
+
+    if let Some(path) = cli.check {
+        std::process::exit(check(&path, &config));
+    }
+
+    if let Some(path) = cli.format {
+        std::process::exit(format(&path, &config, cli.write));
+    }
+
+    let (service, socket) = LspService::new(|client| Backend {
+        client,
+        files: Arc::new(RwLock::new(HashMap::new())),
+        config: Arc::new(RwLock::new(config)),
+        client_capabilities: Arc::new(RwLock::new(ClientCapabilities::default())),
+        pending_diagnostics: Arc::new(Mutex::new(HashMap::new())),
+    });
+
+    if let Some(path) = cli.socket {
+        // Unix domain socket
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            // A socket left behind by a server that did not shut down cleanly refuses connections.
+            // A socket that is still being listened on, or anything other than a socket, is not
+            // ours to remove.
+            if std::fs::symlink_metadata(&path).is_ok_and(|x| x.file_type().is_socket()) {
+                match std::os::unix::net::UnixStream::connect(&path) {
+                    Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    _ => {
+                        eprintln!("Cannot listen on {}: already in use", path.display());
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            let stream = {
+                let listener = match tokio::net::UnixListener::bind(&path) {
+                    Ok(listener) => listener,
+                    Err(err) => {
+                        eprintln!("Cannot listen on {}: {err}", path.display());
+                        std::process::exit(1);
+                    }
+                };
+                let (stream, _) = listener.accept().await.unwrap();
+                stream
+            };
+
+            let (input, output) = tokio::io::split(stream);
+            Server::new(input, output, socket).serve(service).await;
+            let _ = std::fs::remove_file(&path);
+        }
+        #[cfg(not(unix))]
+        {
+            eprintln!(
+                "Cannot listen on {}: Unix domain sockets are not supported on this platform",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+    } else if cli.stdio || !cli.listen && cli.host.is_none() {
+        // stdin/stdout
+        Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
+            .serve(service)
+            .await;
+    } else if cli.listen {
+        // listen
+
+        let host = cli
+            .host
+            .map(Cow::Owned)
+            .unwrap_or(Cow::Borrowed("127.0.0.1"))
+            .parse::<Ipv4Addr>()
+            .expect("Could not parse IP address");
+
+        let port = cli.port.unwrap_or(9257);
+
+        let stream = {
+            let listener = TcpListener::bind((host, port)).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            stream
+        };
+
+        let (input, output) = tokio::io::split(stream);
+        Server::new(input, output, socket).serve(service).await;
+    } else {
+        let host = cli.host.expect("No host given");
+        let port = cli.port.expect("No port given");
+
+        let stream = TcpStream::connect((host, port))
+            .await
+            .expect("Could not open TCP stream");
+
+        let (input, output) = tokio::io::split(stream);
+        Server::new(input, output, socket).serve(service).await;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Position(tower_lsp::lsp_types::Position);
+
+#[derive(Clone, Debug)]
+struct Range(tower_lsp::lsp_types::Range);
+
+impl Range {
+    pub fn contains(&self, position: Position) -> bool {
+        let start = (self.0.start.line, self.0.start.character);
+        let end = (self.0.end.line, self.0.end.character);
+        let position = (position.0.line, position.0.character);
+
+        start <= position && position <= end
+    }
+}
+
+impl From<tree_sitter::Point> for Position {
+    fn from(value: tree_sitter::Point) -> Self {
+        Position(tower_lsp::lsp_types::Position::new(
+            value.row as u32,
+            value.column as u32,
+        ))
+    }
+}
+
+impl From<tower_lsp::lsp_types::Position> for Position {
+    fn from(value: tower_lsp::lsp_types::Position) -> Self {
+        Position(value)
+    }
+}
+
+impl From<Position> for tower_lsp::lsp_types::Position {
+    fn from(value: Position) -> Self {
+        value.0
+    }
+}
+
+impl From<Position> for tree_sitter::Point {
+    fn from(value: Position) -> Self {
+        tree_sitter::Point {
+            row: value.0.line as usize,
+            column: value.0.character as usize,
+        }
+    }
+}
+
+impl From<tree_sitter::Range> for Range {
+    fn from(value: tree_sitter::Range) -> Self {
+        Range(tower_lsp::lsp_types::Range::new(
+            Position::from(value.start_point).into(),
+            Position::from(value.end_point).into(),
+        ))
+    }
+}
+
+impl From<tower_lsp::lsp_types::Range> for Range {
+    fn from(value: tower_lsp::lsp_types::Range) -> Self {
+        Range(value)
+    }
+}
+
+impl From<Range> for tower_lsp::lsp_types::Range {
+    fn from(value: Range) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn open(source: &str) -> LspService<Backend> {
+        let (service, _) = LspService::new(|client| Backend {
+            client,
+            files: Arc::new(RwLock::new(HashMap::new())),
+            config: Arc::new(RwLock::new(Configuration::default())),
+            client_capabilities: Arc::new(RwLock::new(ClientCapabilities::default())),
+            pending_diagnostics: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let backend = service.inner();
+        let uri = Url::parse("file:///test.ic10").unwrap();
+
+        backend
+            .update_content(uri.clone(), source.to_string())
+            .await;
+        if let Some(file_data) = backend.files.write().await.get_mut(&uri) {
+            file_data.update_definitions(&mut Vec::new());
+        }
+
+        service
+    }
+
+    fn position(line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                uri: Url::parse("file:///test.ic10").unwrap(),
+            },
+            position: LspPosition::new(line, character),
+        }
+    }
+
+    fn lints(source: &str, config: &Configuration, code: &str) -> Vec<Diagnostic> {
+        FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string())
+            .diagnostics(config)
+            .into_iter()
+            .filter(|x| x.code == Some(NumberOrString::String(code.to_string())))
+            .collect()
+    }
+
+    async fn hover_at(source: &str, line: u32, character: u32) -> Option<Hover> {
+        open(source)
+            .await
+            .inner()
+            .hover(HoverParams {
+                text_document_position_params: position(line, character),
+                work_done_progress_params: Default::default(),
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn goto_definition_jump_targets() {
+        let source = "define TARGET 5\nalias dest r0\nj TARGET\nj dest\n";
+        let service = open(source).await;
+        let goto = |line, character| {
+            service.inner().goto_definition(GotoDefinitionParams {
+                text_document_position_params: position(line, character),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+        };
+        let target_line = |response: Option<GotoDefinitionResponse>| match response {
+            Some(GotoDefinitionResponse::Scalar(location)) => Some(location.range.start.line),
+            _ => None,
+        };
+
+        assert_eq!(target_line(goto(2, 3).await.unwrap()), Some(0));
+        assert_eq!(target_line(goto(2, 8).await.unwrap()), Some(0));
+        assert_eq!(target_line(goto(3, 3).await.unwrap()), Some(1));
+        assert_eq!(target_line(goto(3, 6).await.unwrap()), Some(1));
+        assert_eq!(target_line(goto(2, 0).await.unwrap()), None);
+    }
+
+    #[test]
+    fn range_contains() {
+        let position = |line, character| Position(LspPosition::new(line, character));
+        let range = Range(LspRange::new(
+            LspPosition::new(2, 4),
+            LspPosition::new(2, 8),
+        ));
+
+        assert!(range.contains(position(2, 4)));
+        assert!(range.contains(position(2, 6)));
+        assert!(range.contains(position(2, 8)));
+        assert!(!range.contains(position(2, 3)));
+        assert!(!range.contains(position(2, 9)));
+        assert!(!range.contains(position(1, 6)));
+        assert!(!range.contains(position(3, 6)));
+
+        let range = Range(LspRange::new(
+            LspPosition::new(1, 4),
+            LspPosition::new(3, 2),
+        ));
+        assert!(range.contains(position(1, 10)));
+        assert!(range.contains(position(2, 0)));
+        assert!(range.contains(position(3, 1)));
+        assert!(!range.contains(position(1, 3)));
+        assert!(!range.contains(position(3, 3)));
+    }
+
+    #[tokio::test]
+    async fn hover_alias_usage() {
+        let source = "alias pump d0\nalias counter r3\nyield\ns pump On 1\nadd counter counter 1\n";
+
+        let Some(hover) = hover_at(source, 3, 3).await else {
+            panic!("No hover on alias usage");
+        };
+        let HoverContents::Array(contents) = hover.contents else {
+            panic!("Unexpected hover contents");
+        };
+        assert_eq!(
+            contents[0],
+            MarkedString::LanguageString(LanguageString {
+                language: "ic10".to_string(),
+                value: "alias pump d0".to_string(),
+            })
+        );
+        assert_eq!(contents[1], MarkedString::String("Device `d0`".to_string()));
+        assert_eq!(
+            hover.range,
+            Some(LspRange::new(
+                LspPosition::new(3, 2),
+                LspPosition::new(3, 6)
+            ))
+        );
+
+        let Some(hover) = hover_at(source, 4, 13).await else {
+            panic!("No hover on alias usage");
+        };
+        let HoverContents::Array(contents) = hover.contents else {
+            panic!("Unexpected hover contents");
+        };
+        assert_eq!(
+            contents[1],
+            MarkedString::String("Register `r3`".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn hover_number() {
+        let hash = instructions::hash("ItemPumpkin");
+        let source =
+            format!("move r0 256\nmove r0 -1.5\nmove r0 $FF\nmove r0 {hash}\nmove r0 -12\n");
+        let hover = |line| {
+            let source = source.clone();
+            async move {
+                let Some(hover) = hover_at(&source, line, 9).await else {
+                    panic!("No hover on number");
+                };
+                let HoverContents::Array(contents) = hover.contents else {
+                    panic!("Unexpected hover contents");
+                };
+                contents
+                    .into_iter()
+                    .map(|x| match x {
+                        MarkedString::String(x) => x,
+                        MarkedString::LanguageString(x) => x.value,
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            hover(0).await,
+            vec![
+                "Decimal: `256`",
+                "Hexadecimal: `0x100`",
+                "Binary: `0b1_0000_0000`"
+            ]
+        );
+        assert_eq!(hover(1).await, vec!["Decimal: `-1.5`"]);
+        assert_eq!(
+            hover(2).await,
+            vec![
+                "Decimal: `255`",
+                "Hexadecimal: `0xFF`",
+                "Binary: `0b1111_1111`"
+            ]
+        );
+        assert_eq!(hover(3).await.last().unwrap(), "Hash of `Pumpkin`");
+        assert_eq!(
+            hover(4).await,
+            vec!["Decimal: `-12`", "Hexadecimal: `-0xC`", "Binary: `-0b1100`"]
+        );
+
+        let source = "lb r0 0 Temperature 3\nlr r0 d0 1 0\n";
+        let mode_hover = |line, character| async move {
+            let Some(Hover {
+                contents: HoverContents::Array(contents),
+                ..
+            }) = hover_at(source, line, character).await
+            else {
+                panic!("No hover on mode");
+            };
+            contents[..2]
+                .iter()
+                .map(|x| match x {
+                    MarkedString::String(x) => x.clone(),
+                    MarkedString::LanguageString(x) => x.value.clone(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            mode_hover(0, 20).await,
+            vec!["Batch mode `Maximum`", "Highest of all read values"]
+        );
+        assert_eq!(
+            mode_hover(1, 9).await,
+            vec![
+                "Reagent mode `Required`",
+                "Reagents still needed by the device's current recipe"
+            ]
+        );
+        assert_eq!(mode_hover(0, 6).await[0], "Decimal: `0`");
+    }
+
+    #[tokio::test]
+    async fn hover_hash_string() {
+        let source = "sb HASH(\"ItemPumpkin\") On 1\nsb HASH(\"Pumpkin\") On 1\n";
+        let hover = |line| async move {
+            let Some(hover) = hover_at(source, line, 10).await else {
+                panic!("No hover on HASH string");
+            };
+            let HoverContents::Array(contents) = hover.contents else {
+                panic!("Unexpected hover contents");
+            };
+            contents
+                .into_iter()
+                .map(|x| match x {
+                    MarkedString::String(x) => x,
+                    MarkedString::LanguageString(x) => x.value,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let hash = instructions::hash("ItemPumpkin");
+        assert_eq!(
+            hover(0).await,
+            vec![
+                format!("HASH(\"ItemPumpkin\") = {hash}"),
+                "Hash of `Pumpkin`".to_string()
+            ]
+        );
+
+        let contents = hover(1).await;
+        assert_eq!(
+            contents[0],
+            format!("HASH(\"Pumpkin\") = {}", instructions::hash("Pumpkin"))
+        );
+        assert_eq!(
+            contents.last().unwrap(),
+            &format!("`Pumpkin` is the name of the prefab with hash `{hash}`")
+        );
+    }
+
+    #[tokio::test]
+    async fn hover_label_past_line_255() {
+        let source = format!("{}target:\nj target\n", "yield\n".repeat(300));
+
+        let Some(hover) = hover_at(&source, 301, 3).await else {
+            panic!("No hover on label usage");
+        };
+        assert_eq!(
+            hover.contents,
+            HoverContents::Scalar(MarkedString::String("Label on line 301".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn workspace_symbols() {
+        let service = open("define MaxPressure 100\nalias pump d0\nloop:\nj loop\n").await;
+        let backend = service.inner();
+        let other = Url::parse("file:///other.ic10").unwrap();
+        backend
+            .update_content(other.clone(), "alias pressureSensor d1\n".to_string())
+            .await;
+        backend
+            .files
+            .write()
+            .await
+            .values_mut()
+            .for_each(|x| x.update_definitions(&mut Vec::new()));
+
+        let symbols = |query: &str| {
+            backend.symbol(WorkspaceSymbolParams {
+                query: query.to_string(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+        };
+
+        let found = symbols("PRESSURE").await.unwrap().unwrap();
+        assert_eq!(
+            found
+                .iter()
+                .map(|x| (x.name.as_str(), x.kind, x.location.uri.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("MaxPressure", SymbolKind::NUMBER, "file:///test.ic10"),
+                ("pressureSensor", SymbolKind::VARIABLE, "file:///other.ic10"),
+            ]
+        );
+
+        let found = symbols("lo").await.unwrap().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, SymbolKind::FUNCTION);
+        assert_eq!(
+            found[0].location.range,
+            LspRange::new(LspPosition::new(2, 0), LspPosition::new(2, 4))
+        );
+
+        assert_eq!(symbols("").await.unwrap().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("ic10lsp-includes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("lib.ic10"),
+            "# include \"nested.ic10\"\ndefine MaxPressure 100\nalias pump d0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("nested.ic10"),
+            "# include \"lib.ic10\"\nloop:\nyield\n",
+        )
+        .unwrap();
+
+        let source =
+            "# include \"lib.ic10\"\n# include \"missing.ic10\"\n# include \"main.ic10\"\n\
+                      s pump Setting MaxPressure\nj loop\n";
+        let mut file_data = FileData::new(
+            Url::from_file_path(dir.join("main.ic10")).unwrap(),
+            source.to_string(),
+        );
+        let diagnostics = file_data.diagnostics(&Configuration::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let type_data = &file_data.type_data;
+        assert!(type_data.defines.contains_key("MaxPressure"));
+        assert!(type_data.aliases.contains_key("pump"));
+        assert_eq!(
+            type_data.get_source("loop"),
+            Some(&Url::from_file_path(dir.join("nested.ic10")).unwrap())
+        );
+        assert!(type_data.symbols().next().is_none());
+
+        let messages = diagnostics
+            .iter()
+            .map(|x| (x.range.start.line, x.message.as_str()))
+            .collect::<Vec<_>>();
+        assert!(messages.iter().any(|x| x.0 == 1
+            && x.1
+                .starts_with("Could not read included file \"missing.ic10\"")));
+        assert!(messages.contains(&(2, "Circular include of \"main.ic10\"")));
+        assert!(!messages.iter().any(|x| x.1 == "Unknown identifier"));
+        assert!(diagnostics
+            .iter()
+            .filter(|x| x.range.start.line < 3 && x.severity == Some(DiagnosticSeverity::ERROR))
+            .all(|x| x.code == Some(NumberOrString::String(ERROR_INCLUDE.to_string()))));
+    }
+
+    #[test]
+    fn include_conflicts() {
+        let dir = std::env::temp_dir().join(format!("ic10lsp-conflicts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.ic10"),
+            "define Gain 2\ndefine Offset 5\nalias pump d0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.ic10"),
+            "define Offset 6\ndefine Scale $10\nalias pump d0\n",
+        )
+        .unwrap();
+
+        let source = "# include \"a.ic10\"\n# include \"b.ic10\"\ndefine Gain 3\ndefine Scale 16\n\
+                      s pump Setting Gain\ns pump Setting Offset\ns pump Setting Scale\n";
+        let mut file_data = FileData::new(
+            Url::from_file_path(dir.join("main.ic10")).unwrap(),
+            source.to_string(),
+        );
+        let lints = |config: &Configuration, file_data: &mut FileData| {
+            file_data
+                .diagnostics(config)
+                .into_iter()
+                .filter(|x| {
+                    x.code == Some(NumberOrString::String(LINT_INCLUDE_CONFLICT.to_string()))
+                        || x.code
+                            == Some(NumberOrString::String(
+                                LINT_SAME_VALUE_REDEFINITION.to_string(),
+                            ))
+                })
+                .map(|x| (x.range.start.line, x.severity, x.message))
+                .collect::<Vec<_>>()
+        };
+        let default_lints = lints(&Configuration::default(), &mut file_data);
+        let hint_config = Configuration {
+            lints: HashMap::from([(
+                LINT_SAME_VALUE_REDEFINITION.to_string(),
+                Some(DiagnosticSeverity::HINT),
+            )]),
+            ..Default::default()
+        };
+        let hint_lints = lints(&hint_config, &mut file_data);
+        // Locations in the included file are not converted with the lines of this one
+        let mut file_data = FileData::new(
+            Url::from_file_path(dir.join("main.ic10")).unwrap(),
+            "# äää\n# include \"a.ic10\"\ndefine Gain 3\n".to_string(),
+        );
+        file_data.document_data.utf16 = true;
+        let mut diagnostics = file_data.diagnostics(&Configuration::default());
+        file_data
+            .document_data
+            .convert_diagnostics_for_client(&mut diagnostics);
+        let related = diagnostics
+            .iter()
+            .find(|x| x.message.starts_with("'Gain'"))
+            .and_then(|x| x.related_information.as_ref())
+            .unwrap();
+        assert_eq!(
+            related[0].location.range,
+            LspRange::new(LspPosition::new(0, 7), LspPosition::new(0, 11))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let warning = Some(DiagnosticSeverity::WARNING);
+        assert_eq!(
+            default_lints,
+            vec![
+                (
+                    2,
+                    warning,
+                    "'Gain' is defined differently in an included file".to_string()
+                ),
+                (
+                    1,
+                    warning,
+                    "'Offset' is defined differently in an included file".to_string()
+                ),
+                (
+                    3,
+                    warning,
+                    "'Scale' is defined again with the same value in an included file".to_string()
+                ),
+                (
+                    1,
+                    warning,
+                    "'pump' is defined again with the same value in an included file".to_string()
+                ),
+            ]
+        );
+        let severities = hint_lints.iter().map(|x| x.1).collect::<Vec<_>>();
+        let hint = Some(DiagnosticSeverity::HINT);
+        assert_eq!(severities, vec![warning, warning, hint, hint]);
+    }
+
+    #[tokio::test]
+    async fn closed_documents_are_forgotten() {
+        let service = open("alias pump d0\ns pump On 1\n").await;
+        let backend = service.inner();
+        let hover = || {
+            backend.hover(HoverParams {
+                text_document_position_params: position(1, 3),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        assert!(hover().await.unwrap().is_some());
+
+        backend
+            .did_close(DidCloseTextDocumentParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+            })
+            .await;
+        assert!(backend.files.read().await.is_empty());
+        assert_eq!(
+            hover().await.unwrap_err().code,
+            tower_lsp::jsonrpc::ErrorCode::InvalidRequest
+        );
+    }
+
+    #[tokio::test]
+    async fn save_updates_content() {
+        let service = open("move r0 1\n").await;
+        let backend = service.inner();
+        let uri = Url::parse("file:///test.ic10").unwrap();
+
+        backend
+            .did_save(DidSaveTextDocumentParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier { uri: uri.clone() },
+                text: Some("alias pump d0\ns pump On 1\n".to_string()),
+            })
+            .await;
+
+        let files = backend.files.read().await;
+        let file_data = &files[&uri];
+        assert_eq!(
+            file_data.document_data.content,
+            "alias pump d0\ns pump On 1\n"
+        );
+        assert!(file_data.type_data.aliases.contains_key("pump"));
+    }
+
+    #[tokio::test]
+    async fn signature_help_overloads() {
+        let source = "alias sensor d0\nalias pump sensor\nalias counter r0\nmove counter 1\n";
+        let service = open(source).await;
+        let backend = service.inner();
+        let help = |line, character| async move {
+            let help = backend
+                .signature_help(SignatureHelpParams {
+                    context: None,
+                    text_document_position_params: position(line, character),
+                    work_done_progress_params: Default::default(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            let labels = help.signatures.into_iter().map(|x| x.label);
+            (labels.collect::<Vec<_>>(), help.active_signature)
+        };
+
+        let alias_forms = vec!["alias name r?".to_string(), "alias name d?".to_string()];
+        assert_eq!(help(0, 15).await, (alias_forms.clone(), Some(1)));
+        assert_eq!(help(1, 17).await, (alias_forms.clone(), Some(1)));
+        assert_eq!(help(2, 16).await, (alias_forms, Some(0)));
+        assert_eq!(help(3, 14).await.0, vec!["move r? (r?|num)".to_string()]);
+
+        let active_parameter = |character| async move {
+            let help = backend
+                .signature_help(SignatureHelpParams {
+                    context: None,
+                    text_document_position_params: position(3, character),
+                    work_done_progress_params: Default::default(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            help.signatures[0].active_parameter
+        };
+        for (character, index) in [(4, 0), (5, 0), (12, 0), (13, 1), (14, 1)] {
+            assert_eq!(
+                active_parameter(character).await,
+                Some(index),
+                "character {character}"
+            );
+        }
+    }
+
+    #[test]
+    fn current_parameter() {
+        let source = "  add r0  r1 r2\n";
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_ic10::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+        let instruction = root.query(&queries::INSTRUCTIONS, source).unwrap();
+
+        let expected = [
+            (5, 0),
+            (6, 0),
+            (8, 0),
+            (9, 1),
+            (10, 1),
+            (12, 1),
+            (13, 2),
+            (15, 2),
+            (16, 3),
+        ];
+        for (column, index) in expected {
+            let (current, operand) = get_current_parameter(instruction, column);
+            assert_eq!(current, index, "column {column}");
+            assert_eq!(
+                operand.map(|x| x.utf8_text(source.as_bytes()).unwrap()),
+                ["r0", "r1", "r2"].get(index).copied(),
+                "column {column}"
+            );
+        }
+    }
+
+    #[test]
+    fn device_out_of_range() {
+        let source = "l r0 d6 Temperature\nl r0 d5 Temperature\nl r0 db Temperature\ns d12 On 1\n";
+        let diagnostics = lints(
+            source,
+            &Configuration::default(),
+            ERROR_NONEXISTENT_REGISTER,
+        )
+        .into_iter()
+        .map(|x| (x.range.start.line, x.message))
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    0,
+                    "Device d6 does not exist. Only d0 to d5 and db are available".to_string()
+                ),
+                (
+                    3,
+                    "Device d12 does not exist. Only d0 to d5 and db are available".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_registers() {
+        let source = "alias extra r20\nmove extra 1\nmove r17 r18\nl r12 dr12 On\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let mut errors = |config: &Configuration| {
+            file_data
+                .diagnostics(config)
+                .into_iter()
+                .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+                .map(|x| (x.range.start.line, x.message))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            errors(&Configuration::default()),
+            vec![
+                (
+                    0,
+                    "Register r20 does not exist. Only r0 to r17 are available".to_string()
+                ),
+                (
+                    2,
+                    "Register r18 does not exist. Only r0 to r17 are available".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            errors(&Configuration {
+                max_registers: 24,
+                ..Default::default()
+            }),
+            vec![]
+        );
+        // Registers the grammar knows are limited as well
+        assert_eq!(
+            errors(&Configuration {
+                max_registers: 8,
+                ..Default::default()
+            })
+            .into_iter()
+            .filter(|x| x.0 == 3)
+            .collect::<Vec<_>>(),
+            vec![
+                (
+                    3,
+                    "Register r12 does not exist. Only r0 to r7 are available".to_string()
+                ),
+                (
+                    3,
+                    "Register r12 does not exist. Only r0 to r7 are available".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_instruction_operands() {
+        let source = "define Bogus 7\ndefine Total 1\n\
+            lb r0 HASH(\"ItemPumpkin\") Temperature Average\n\
+            lb r0 HASH(\"NotAPrefab\") Temperature Total\n\
+            lbn r0 HASH(\"ItemPumpkin\") HASH(\"My Pumpkin\") Temperature Bogus\n\
+            sb HASH(\"NotAPrefab\") On 1\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let diagnostics = file_data
+            .diagnostics(&Configuration {
+                max_columns: 80,
+                ..Default::default()
+            })
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (3, "Unknown prefab name".to_string()),
+                (
+                    4,
+                    "Use of out-of-range batch mode, expected 0 to 3".to_string()
+                ),
+                (5, "Unknown prefab name".to_string()),
+            ]
+        );
+
+        let unknown_prefabs = lints(
+            source,
+            &Configuration {
+                max_columns: 80,
+                ..Default::default()
+            },
+            LINT_UNKNOWN_PREFAB,
+        );
+        assert_eq!(unknown_prefabs.len(), 2);
+
+        let diagnostics = file_data.diagnostics(&Configuration {
+            max_columns: 80,
+            lints: HashMap::from([(LINT_UNKNOWN_PREFAB.to_string(), None)]),
+            ..Default::default()
+        });
+        assert!(!diagnostics
+            .iter()
+            .any(|x| x.message == "Unknown prefab name"));
+    }
+
+    #[tokio::test]
+    async fn unused_definition_lint() {
+        let source = "define Unused 1\ndefine Limit 5\nalias pump d0\nalias spare r1\nstart:\nidle:\ns pump On Limit\nj start\n";
+        let diagnostics = lints(source, &Configuration::default(), LINT_UNUSED_DEFINITION);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|x| (x.range.start.line, x.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, "Define 'Unused' is never used"),
+                (3, "Alias 'spare' is never used"),
+                (5, "Label 'idle' is never used"),
+            ]
+        );
+
+        let service = open(source).await;
+        let actions = service
+            .inner()
+            .code_action(CodeActionParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                range: diagnostics[1].range,
+                context: tower_lsp::lsp_types::CodeActionContext {
+                    diagnostics: vec![diagnostics[1].clone()],
+                    ..Default::default()
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let Some(CodeActionOrCommand::CodeAction(action)) = actions.first() else {
+            panic!("No code action for unused definition");
+        };
+        assert_eq!(action.is_preferred, Some(false));
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+            [&Url::parse("file:///test.ic10").unwrap()];
+        assert_eq!(
+            edits,
+            &vec![TextEdit::new(
+                LspRange::new(LspPosition::new(3, 0), LspPosition::new(4, 0)),
+                String::new()
+            )]
+        );
+
+        // Files with only definitions are meant to be included
+        let mut file_data = FileData::new(
+            Url::parse("file:///test.ic10").unwrap(),
+            "define Unused 1\nalias pump d0\n".to_string(),
+        );
+        assert!(file_data
+            .diagnostics(&Configuration::default())
+            .iter()
+            .all(|x| x.code.is_none()));
+    }
+
+    #[tokio::test]
+    async fn reagent_mode_quick_fix() {
+        let source = "lr r0 d0 1 HASH(\"Iron\")\n";
+        let diagnostic =
+            lints(source, &Configuration::default(), LINT_NUMBER_REAGENT_MODE).remove(0);
+
+        let service = open(source).await;
+        let actions = service
+            .inner()
+            .code_action(CodeActionParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                range: diagnostic.range,
+                context: tower_lsp::lsp_types::CodeActionContext {
+                    diagnostics: vec![diagnostic],
+                    ..Default::default()
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let Some(CodeActionOrCommand::CodeAction(action)) = actions.first() else {
+            panic!("No code action for literal reagent mode");
+        };
+        assert_eq!(action.title, "Replace with Required");
+        assert_eq!(action.is_preferred, Some(true));
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+            [&Url::parse("file:///test.ic10").unwrap()];
+        assert_eq!(
+            edits,
+            &vec![TextEdit::new(
+                LspRange::new(LspPosition::new(0, 9), LspPosition::new(0, 10)),
+                "Required".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn convert_all_absolute_jumps() {
+        let source = "yield\nj 0\nbeqz r0 3\nbrnez r0 -1\njal 0\n";
+        let service = open(source).await;
+        let actions = service
+            .inner()
+            .code_action(CodeActionParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                range: LspRange::default(),
+                context: Default::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let Some(CodeActionOrCommand::CodeAction(action)) = actions
+            .iter()
+            .find(|x| matches!(x, CodeActionOrCommand::CodeAction(x) if x.kind == Some(CodeActionKind::SOURCE)))
+        else {
+            panic!("No source action to convert absolute jumps");
+        };
+        let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()
+            [&Url::parse("file:///test.ic10").unwrap()];
+        let edit = |line, start, end, text: &str| {
+            TextEdit::new(
+                LspRange::new(LspPosition::new(line, start), LspPosition::new(line, end)),
+                text.to_string(),
+            )
+        };
+
+        assert_eq!(
+            edits,
+            &vec![
+                edit(1, 0, 1, "jr"),
+                edit(1, 2, 3, "-1"),
+                edit(2, 0, 4, "breqz"),
+                edit(2, 8, 9, "1"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn call_hierarchy() {
+        let source = "jal init\nstart:\njal read\nbeqz r0 start\nj start\ninit:\nj ra\nread:\njal init\nj ra\n";
+        let service = open(source).await;
+        let backend = service.inner();
+        let uri = Url::parse("file:///test.ic10").unwrap();
+
+        let items = backend
+            .prepare_call_hierarchy(CallHierarchyPrepareParams {
+                text_document_position_params: position(2, 5),
+                work_done_progress_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        let read = items[0].clone();
+        assert_eq!(read.name, "read");
+        assert_eq!(
+            read.range,
+            LspRange::new(LspPosition::new(7, 0), LspPosition::new(10, 0))
+        );
+        assert_eq!(read.uri, uri);
+
+        let incoming = |name: &str| {
+            let item = CallHierarchyItem {
+                name: name.to_string(),
+                ..read.clone()
+            };
+            async move {
+                backend
+                    .incoming_calls(CallHierarchyIncomingCallsParams {
+                        item,
+                        work_done_progress_params: Default::default(),
+                        partial_result_params: Default::default(),
+                    })
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .into_iter()
+                    .map(|x| (x.from.name, x.from_ranges.len()))
+                    .collect::<Vec<_>>()
+            }
+        };
+        // The call before the first label has no caller
+        assert_eq!(incoming("init").await, vec![("read".to_string(), 1)]);
+        assert_eq!(incoming("start").await, vec![("start".to_string(), 2)]);
+
+        let outgoing = backend
+            .outgoing_calls(CallHierarchyOutgoingCallsParams {
+                item: CallHierarchyItem {
+                    name: "start".to_string(),
+                    ..read.clone()
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.to.name, x.from_ranges))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outgoing,
+            vec![
+                (
+                    "read".to_string(),
+                    vec![LspRange::new(
+                        LspPosition::new(2, 4),
+                        LspPosition::new(2, 8)
+                    )]
+                ),
+                (
+                    "start".to_string(),
+                    vec![
+                        LspRange::new(LspPosition::new(3, 8), LspPosition::new(3, 13)),
+                        LspRange::new(LspPosition::new(4, 2), LspPosition::new(4, 7)),
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn selection_range() {
+        let service = open("yield\ns d0 On 1 # on\n").await;
+        let ranges = service
+            .inner()
+            .selection_range(SelectionRangeParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                positions: vec![LspPosition::new(1, 6)],
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ranges.len(), 1);
+
+        let mut chain = Vec::new();
+        let mut current = Some(&ranges[0]);
+        while let Some(selection_range) = current {
+            let range = selection_range.range;
+            chain.push((
+                range.start.line,
+                range.start.character,
+                range.end.line,
+                range.end.character,
+            ));
+            current = selection_range.parent.as_deref();
+        }
+        assert_eq!(
+            chain,
+            vec![
+                // On
+                (1, 5, 1, 7),
+                // s d0 On 1
+                (1, 0, 1, 9),
+                // The line, including comment and newline
+                (1, 0, 2, 0),
+                // The whole file
+                (0, 0, 2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_instruction_severity() {
+        let mut file_data = FileData::new(
+            Url::parse("file:///test.ic10").unwrap(),
+            "frob r0\n".to_string(),
+        );
+        let mut severity = |settings: Value| {
+            let mut config = Configuration::default();
+            config.update(&settings);
+            file_data
+                .diagnostics(&config)
+                .into_iter()
+                .find(|x| x.message == "Unsupported instruction")
+                .map(|x| x.severity)
+        };
+
+        assert_eq!(
+            severity(json!({})),
+            Some(Some(DiagnosticSeverity::INFORMATION))
+        );
+        assert_eq!(
+            severity(json!({"lints": {"L004": "error"}})),
+            Some(Some(DiagnosticSeverity::ERROR))
+        );
+        assert_eq!(severity(json!({"lints": {"L004": "off"}})), None);
+    }
+
+    #[test]
+    fn overflow_severity() {
+        let source = "move r0 1\nmove r1 2\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let config = Configuration {
+            max_lines: 1,
+            max_columns: 7,
+            overline_instruction_severity: DiagnosticSeverity::WARNING,
+            overcolumn_instruction_severity: DiagnosticSeverity::HINT,
+            ..Default::default()
+        };
+        let diagnostics = file_data
+            .diagnostics(&config)
+            .into_iter()
+            .filter(|x| x.message.starts_with("Instruction past"))
+            .map(|x| (x.range.start.line, x.severity, x.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    0,
+                    Some(DiagnosticSeverity::HINT),
+                    "Instruction past column 7".to_string()
+                ),
+                (
+                    1,
+                    Some(DiagnosticSeverity::HINT),
+                    "Instruction past column 7".to_string()
+                ),
+                (
+                    1,
+                    Some(DiagnosticSeverity::WARNING),
+                    "Instruction past line 1".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn column_limit_counts_characters() {
+        let source = "s d0 On 1 # ééééé\n\ts d0 On 1\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let mut overflows = |config: &Configuration| {
+            file_data
+                .diagnostics(config)
+                .into_iter()
+                .filter(|x| x.message.contains("past column"))
+                .map(|x| (x.range.start.line, x.range.start.character, x.message))
+                .collect::<Vec<_>>()
+        };
+
+        // The comment is 17 characters but 22 bytes long
+        let config = Configuration {
+            max_columns: 17,
+            warn_overcolumn_comment: true,
+            tab_width: 1,
+            ..Default::default()
+        };
+        assert_eq!(overflows(&config), vec![]);
+
+        // The start of the range is a byte offset, on a character boundary
+        let config = Configuration {
+            max_columns: 15,
+            ..config
+        };
+        assert_eq!(
+            overflows(&config),
+            vec![(0, 18, "Comment past column 15".to_string())]
+        );
+
+        // A tab extends to the next tab stop
+        let config = Configuration {
+            max_columns: 10,
+            ..config
+        };
+        assert_eq!(
+            overflows(&config),
+            vec![(0, 10, "Comment past column 10".to_string())]
+        );
+        let config = Configuration {
+            tab_width: 4,
+            ..config
+        };
+        assert_eq!(
+            overflows(&config),
+            vec![
+                (1, 7, "Instruction past column 10".to_string()),
+                (0, 10, "Comment past column 10".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_define_values() {
+        let source = "define A foo\ndefine B r0\ndefine C $FFFFFFFFFFFFFFFFFFFF\ndefine D HASH(\"x\")\ndefine E pi\ndefine F $1F\nmove r0 A\nmove r0 D\nmove r0 E\nmove r0 F\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let errors = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+            .map(|x| (x.range.start.line, x.range.start.character, x.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    0,
+                    9,
+                    "Define value must be a number, found `foo`".to_string()
+                ),
+                (1, 9, "Define value must be a number, found `r0`".to_string()),
+                (
+                    2,
+                    9,
+                    "Invalid number `$FFFFFFFFFFFFFFFFFFFF`: number too large to fit in target type"
+                        .to_string()
+                ),
+                (6, 8, "Unknown identifier".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn literal_modes() {
+        let source = "lb r0 0 Temperature 1.0\nlb r0 0 Temperature 1.5\nlb r0 0 Temperature 4\n\
+            lb r0 0 Temperature -1\nlb r0 0 Temperature $2\nlr r0 d0 2.0 0\nlr r0 d0 3 0\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let diagnostics = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message, x.data))
+            .collect::<Vec<_>>();
+
+        let lint = |line, message: &str, mode: &str| {
+            (
+                line,
+                message.to_string(),
+                Some(Value::String(mode.to_string())),
+            )
+        };
+        let error = |line, message: &str| (line, message.to_string(), None);
+        assert_eq!(
+            diagnostics,
+            vec![
+                lint(0, "Use of literal number for batch mode", "Sum"),
+                error(1, "Use of non-integer batch mode"),
+                error(2, "Use of out-of-range batch mode, expected 0 to 3"),
+                error(3, "Use of out-of-range batch mode, expected 0 to 3"),
+                lint(4, "Use of literal number for batch mode", "Minimum"),
+                lint(5, "Use of literal number for reagent mode", "Recipe"),
+                error(6, "Use of out-of-range reagent mode, expected 0 to 2"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn enum_members() {
+        let source =
+            "move r0 Color.Blue\ns d0 Color Color.Red\nadd r1 r0 Color.Blue\nmove r0 Color.Bluee\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let errors = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![(3, "Unknown identifier".to_string())]);
+
+        let service = open(source).await;
+        let backend = service.inner();
+        let goto = backend
+            .goto_definition(GotoDefinitionParams {
+                text_document_position_params: position(0, 10),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(goto, None);
+        assert!(hover_at(source, 0, 10).await.is_some());
+
+        let Some(DocumentSymbolResponse::Flat(symbols)) = backend
+            .document_symbol(DocumentSymbolParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+        else {
+            panic!("No document symbols");
+        };
+        assert_eq!(
+            symbols
+                .iter()
+                .map(|x| (
+                    x.name.as_str(),
+                    x.kind,
+                    x.location.range.start.line,
+                    x.container_name.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Color.Blue", SymbolKind::ENUM_MEMBER, 0, Some("Color")),
+                ("Color.Red", SymbolKind::ENUM_MEMBER, 1, Some("Color")),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_enum_members_are_numbers() {
+        for name in &instructions::ENUMS {
+            let mut file_data = FileData::new(
+                Url::parse("file:///test.ic10").unwrap(),
+                format!("move r0 {name}\n"),
+            );
+            let errors = file_data
+                .diagnostics(&Configuration::default())
+                .into_iter()
+                .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+                .map(|x| x.message)
+                .collect::<Vec<_>>();
+            assert_eq!(errors, Vec::<String>::new(), "{name}");
+        }
+    }
+
+    #[test]
+    fn use_before_definition_lint() {
+        let source = "move r0 Limit\nj start\ns pump On 1\nstart:\ndefine Limit 5\nalias pump d0\ns pump Setting Limit\n";
+        let diagnostics = lints(
+            source,
+            &Configuration::default(),
+            LINT_USE_BEFORE_DEFINITION,
+        )
+        .into_iter()
+        .map(|x| {
+            (
+                x.range.start.line,
+                x.range.start.character,
+                x.related_information.unwrap()[0].location.range.start.line,
+            )
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(diagnostics, vec![(0, 8, 4), (2, 2, 5)]);
+    }
+
+    #[test]
+    fn float_equality_lint() {
+        let source = "define Half 0.5\nstart:\n\
+                      l r0 d0 On\nbeqz r0 start\n\
+                      l r1 d0 Temperature\nbeq r1 300 start\n\
+                      lb r2 0 On Average\nbnez r2 start\n\
+                      lb r2 0 On Maximum\nbnez r2 start\n\
+                      ls r3 d0 0 Occupied\nbeqz r3 start\n\
+                      move r4 Half\nbeq r4 r0 start\n\
+                      move r4 1\nbeq r4 r0 start\n\
+                      div r5 r0 2\nbne r5 1 start\n";
+        let config = Configuration {
+            warn_float_equality: true,
+            ..Default::default()
+        };
+        let diagnostics = lints(source, &config, LINT_FLOAT_EQUALITY)
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    5,
+                    "Exact comparison of floating point values. Consider using bap instead"
+                        .to_string()
+                ),
+                (
+                    7,
+                    "Exact comparison of floating point values. Consider using bnaz instead"
+                        .to_string()
+                ),
+                (
+                    13,
+                    "Exact comparison of floating point values. Consider using bap instead"
+                        .to_string()
+                ),
+                (
+                    17,
+                    "Exact comparison of floating point values. Consider using bna instead"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_yield_lint() {
+        let source = "start:\nl r0 d0 On\nbeqz r0 skip\ns d1 On 1\nskip:\nj start\n\
+                      loop:\nyield\nj loop\n\
+                      count:\nadd r1 r1 1\nblt r1 10 done\nj count\ndone:\n";
+        let config = Configuration {
+            warn_missing_yield: true,
+            ..Default::default()
+        };
+        let diagnostics = lints(source, &config, LINT_MISSING_YIELD)
+            .into_iter()
+            .map(|x| x.range.start.line)
+            .collect::<Vec<_>>();
+
+        assert_eq!(diagnostics, vec![5]);
+    }
+
+    #[test]
+    fn unreachable_code_lint() {
+        let source = "start:\nyield\nj start\nmove r0 1\n# comment\nadd r0 r0 1\n\
+                      define Limit 5\nnext:\njr 2\nmove r1 1\nj next\n";
+        let unreachable = |config: &Configuration| {
+            lints(source, config, LINT_UNREACHABLE_CODE)
+                .into_iter()
+                .map(|x| (x.range.start.line, x.range.end.line, x.severity))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            unreachable(&Configuration::default()),
+            vec![
+                (3, 5, Some(DiagnosticSeverity::HINT)),
+                (9, 10, Some(DiagnosticSeverity::HINT)),
+            ]
+        );
+        assert_eq!(
+            unreachable(&Configuration {
+                lints: HashMap::from([(LINT_UNREACHABLE_CODE.to_string(), None)]),
+                ..Default::default()
+            }),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn stack_balance_lint() {
+        let source = "pop r0\npush 1\npush 2\npop r0\n\
+                      helper:\npop r1\npush r1\npush 2\nj ra\n\
+                      balanced:\npush 1\npop r0\nj ra\n";
+        let unbalanced = |config: &Configuration| {
+            lints(source, config, LINT_STACK_BALANCE)
+                .into_iter()
+                .map(|x| (x.range.start.line, x.message))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(unbalanced(&Configuration::default()), vec![]);
+        assert_eq!(
+            unbalanced(&Configuration {
+                warn_stack_balance: true,
+                ..Default::default()
+            }),
+            vec![
+                (0, "'pop' may run with an empty stack".to_string()),
+                (
+                    8,
+                    "Returning with 1 more value pushed than popped".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            unbalanced(&Configuration {
+                warn_stack_balance: true,
+                lints: HashMap::from([(LINT_STACK_BALANCE.to_string(), None)]),
+                ..Default::default()
+            }),
+            vec![]
+        );
     }
 
-    async fn run_diagnostics(&self, uri: &Url) {
-        let mut diagnostics = Vec::new();
+    #[test]
+    fn builtin_name_lint() {
+        let source =
+            "alias r16 d1\ndefine Color.Blue 5\nalias dr16 r1\ndefine Speed 5\nmove r0 Color.Blue\n";
+        let diagnostics = lints(source, &Configuration::default(), LINT_BUILTIN_NAME)
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    0,
+                    "`r16` is a built-in register, this alias is ignored".to_string()
+                ),
+                (
+                    1,
+                    "`Color.Blue` is a built-in enum member, this define is ignored".to_string()
+                ),
+                (
+                    2,
+                    "`dr16` is a built-in device, this alias is ignored".to_string()
+                ),
+            ]
+        );
 
-        // Collect definitions
-        self.update_definitions(uri, &mut diagnostics).await;
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        file_data.update_definitions(&mut Vec::new());
+        assert!(!file_data.type_data.aliases.contains_key("r16"));
+        assert!(file_data.type_data.defines.contains_key("Speed"));
+    }
 
-        let config = self.config.read().await;
-        let files = self.files.read().await;
-        let Some(file_data) = files.get(uri) else {return;};
+    #[test]
+    fn mixed_define_use_lint() {
+        let source = "define Loop 2\ndefine Choice 1\ndefine Count 3\nyield\nlb r0 Choice On Choice\nadd r1 Loop 1\nj Loop\nadd r1 Count 1\nls r0 d0 Count Occupied\n";
+        let diagnostics = lints(source, &Configuration::default(), LINT_MIXED_DEFINE_USE)
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    0,
+                    "'Loop' is used as a number and as a jump target".to_string()
+                ),
+                (
+                    1,
+                    "'Choice' is used as a prefab hash and as a batch mode".to_string()
+                ),
+            ]
+        );
+    }
 
-        let document = &file_data.document_data;
-        let Some(tree) = document.tree.as_ref() else {
-            return;
+    #[test]
+    fn integer_operands() {
+        let source = "j 1.5\nj 200\nj 3\njr -2.5\njr -2\nls r0 d0 1.5 Occupied\n\
+                      ls r0 d0 -1 Occupied\nls r0 d0 2 Occupied\nbeqz r0 128\nbeqz r0 127\n\
+                      move r0 1.5\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let errors = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+
+        let jump_target = "Jump target must be a line from 0 to 127".to_string();
+        let slot_index = "Slot index must be a non-negative integer".to_string();
+        assert_eq!(
+            errors,
+            vec![
+                (0, jump_target.clone()),
+                (1, jump_target.clone()),
+                (3, "Jump offset must be an integer".to_string()),
+                (5, slot_index.clone()),
+                (6, slot_index),
+                (8, jump_target),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_jump_target() {
+        let errors = |source: &str| {
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string())
+                .diagnostics(&Configuration::default())
+                .into_iter()
+                .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+                .map(|x| (x.range.start.line, x.range.start.character, x.message))
+                .collect::<Vec<_>>()
         };
 
-        // Syntax errors
-        {
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(tree_sitter_ic10::language(), "(ERROR)@error").unwrap();
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
-            for (capture, _) in captures {
-                diagnostics.push(Diagnostic::new(
-                    Range::from(capture.captures[0].node.range()).into(),
-                    Some(DiagnosticSeverity::ERROR),
-                    None,
-                    None,
-                    "Syntax error".to_string(),
-                    None,
-                    None,
-                ));
-            }
-        }
+        assert_eq!(
+            errors("loop:\nyield\nj loopp\n"),
+            vec![(2, 2, "Unknown jump target".to_string())]
+        );
+        assert_eq!(errors("define T 5\nj T\n"), vec![]);
+    }
 
-        // Find invalid instructions
-        {
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction (invalid_instruction)@error)",
-            )
-            .unwrap();
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
-            for (capture, _) in captures {
-                diagnostics.push(Diagnostic::new(
-                    Range::from(capture.captures[0].node.range()).into(),
-                    Some(DiagnosticSeverity::ERROR),
-                    None,
-                    None,
-                    "Invalid instruction".to_string(),
-                    None,
-                    None,
-                ));
-            }
+    #[test]
+    fn syntax_error_messages() {
+        let source = "add r0, r1 r2\nadd r0 r1 @\ns d0 On \"x\nmove r0 d0:\nmove r0 $zz\n:\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let diagnostics = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .map(|x| (x.range.start.line, x.message))
+            .collect::<Vec<_>>();
+
+        for expected in [
+            (0, "Operands are separated by spaces, not commas"),
+            (1, "Unexpected '@'"),
+            (2, "Strings are only allowed in HASH(\"...\")"),
+            (3, "Expected network index after ':'"),
+            (4, "Expected hexadecimal digits after '$'"),
+            (5, "Expected instruction or label, found ':'"),
+        ] {
+            assert!(
+                diagnostics
+                    .iter()
+                    .filter(|x| x.0 == expected.0)
+                    .any(|x| x.1 == expected.1),
+                "{expected:?} not in {diagnostics:?}"
+            );
         }
+        assert!(!diagnostics.iter().any(|x| x.1 == "Syntax error"));
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|x| x.0 == 0 && x.1.starts_with("Operands"))
+                .count(),
+            1
+        );
+    }
 
-        // Type check
-        self.check_types(uri, &mut diagnostics).await;
+    #[test]
+    fn type_mismatch_related_information() {
+        let source =
+            "alias sensor d0\ndefine Limit 3\nadd r0 sensor 1\ns Limit On 1\nadd r0 d0 1\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let mismatches = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .filter(|x| x.message.starts_with("Type mismatch"))
+            .map(|x| {
+                let related = x
+                    .related_information
+                    .map(|x| (x[0].location.range.start.line, x[0].message.clone()));
+                (x.range.start.line, related)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            mismatches,
+            vec![
+                (2, Some((0, "'sensor' is defined here".to_string()))),
+                (3, Some((1, "'Limit' is defined here".to_string()))),
+                (4, None),
+            ]
+        );
+    }
 
-        // Overlength checks
-        {
-            let mut cursor = QueryCursor::new();
+    #[test]
+    fn redundant_label_lint() {
+        let source = "start:\nloop:\n# Main loop\n\nmain:\nyield\nother:\nj loop\nj main\nj start\nj other\n";
+        let redundant = lints(source, &Configuration::default(), LINT_REDUNDANT_LABEL)
+            .into_iter()
+            .map(|x| {
+                let related = x.related_information.unwrap()[0].location.range.start.line;
+                (x.range.start.line, x.message, related)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            redundant,
+            vec![
+                (
+                    1,
+                    "Label 'loop' jumps to the same code as 'start'".to_string(),
+                    0
+                ),
+                (
+                    4,
+                    "Label 'main' jumps to the same code as 'start'".to_string(),
+                    0
+                ),
+            ]
+        );
+    }
 
-            let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
-            for (capture, _) in
-                cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-            {
-                let node = capture.captures[0].node;
-                if node.end_position().column > config.max_columns {
-                    diagnostics.push(Diagnostic {
-                        range: LspRange::new(
-                            LspPosition::new(
-                                node.end_position().row as u32,
-                                config.max_columns as u32,
-                            ),
-                            Position::from(node.end_position()).into(),
-                        ),
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        message: format!("Instruction past column {}", config.max_columns),
-                        ..Default::default()
-                    });
-                }
-            }
+    #[test]
+    fn invalid_math_lint() {
+        let source = "define ZERO 0\ndefine ONE 1\ndiv r0 r1 0\nmod r0 r1 ZERO\ndiv r0 r1 ONE\n\
+                      div r0 r1 0.5\nsqrt r0 -1\nlog r0 $0\nlog r0 2\n";
+        let diagnostics = lints(source, &Configuration::default(), LINT_INVALID_MATH);
+
+        let lints = diagnostics
+            .iter()
+            .map(|x| (x.range.start.line, x.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            vec![
+                (2, "Division by zero"),
+                (3, "Division by zero"),
+                (6, "Square root of a negative number"),
+            ]
+        );
+    }
 
-            if config.warn_overcolumn_comment {
-                let query = Query::new(tree_sitter_ic10::language(), "(comment)@x").unwrap();
-                for (capture, _) in
-                    cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-                {
-                    let node = capture.captures[0].node;
-                    if node.end_position().column > config.max_columns {
-                        diagnostics.push(Diagnostic {
-                            range: LspRange::new(
-                                LspPosition::new(
-                                    node.end_position().row as u32,
-                                    config.max_columns as u32,
-                                ),
-                                Position::from(node.end_position()).into(),
-                            ),
-                            severity: Some(DiagnosticSeverity::WARNING),
-                            message: format!("Comment past column {}", config.max_columns),
-                            ..Default::default()
-                        });
-                    }
-                }
-            }
+    #[test]
+    fn constant_branch_lint() {
+        let source = "define ONE 1\nstart:\nyield\nbeqz 0 start\nbltz 5 start\nbrgt 2 1 -2\n\
+                      bneal 1 1 start\nbapz 0.001 0.01 start\nbeqz r0 start\nblt r0 1 start\n\
+                      bgtz ONE start\nbdse d0 start\nbnan nan start\nj start\n";
+        let diagnostics = lints(source, &Configuration::default(), LINT_CONSTANT_BRANCH);
+
+        let lints = diagnostics
+            .iter()
+            .map(|x| (x.range.start.line, x.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            vec![
+                (3, "Branch is always taken"),
+                (4, "Branch is never taken"),
+                (5, "Branch is always taken"),
+                (6, "Branch is never taken"),
+                (7, "Branch is never taken"),
+                (12, "Branch is always taken"),
+            ]
+        );
+    }
 
-            cursor.set_point_range(
-                tree_sitter::Point::new(config.max_lines, 0)
-                    ..tree_sitter::Point::new(usize::MAX, usize::MAX),
-            );
-            let query = Query::new(tree_sitter_ic10::language(), "(instruction)@x").unwrap();
+    #[test]
+    fn suppression_comments() {
+        let source = "div r0 r1 0 # ic10lsp:ignore L007\ndiv r0 r1 0 # ic10lsp:ignore L001, L005\n\
+                      move r0 r0 # ic10lsp:ignore-all\nmove r1 r1\nsqrt r0 foo # ic10lsp:ignore L007\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let diagnostics = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .filter(|x| x.code.is_some())
+            .map(|x| (x.range.start.line, x.code))
+            .collect::<Vec<_>>();
+
+        let code = |x: &str| Some(NumberOrString::String(x.to_string()));
+        assert_eq!(
+            diagnostics,
+            vec![
+                (1, code(LINT_INVALID_MATH)),
+                (3, code(LINT_SELF_MOVE)),
+                (4, code(ERROR_UNKNOWN_IDENTIFIER)),
+            ]
+        );
+    }
 
-            for (capture, _) in
-                cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-            {
-                let node = capture.captures[0].node;
-                diagnostics.push(Diagnostic {
-                    range: Range::from(node.range()).into(),
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    message: format!("Instruction past line {}", config.max_lines),
-                    ..Default::default()
-                });
-            }
+    #[test]
+    fn duplicate_alias_target_lint() {
+        let source = "alias a r0\nalias b d0\nalias c r0\nalias d sp\nalias e r16\nalias f d1\n";
+        assert!(lints(
+            source,
+            &Configuration::default(),
+            LINT_DUPLICATE_ALIAS_TARGET
+        )
+        .is_empty());
 
-            if config.warn_overline_comment {
-                let query = Query::new(tree_sitter_ic10::language(), "(comment)@x").unwrap();
-                for (capture, _) in
-                    cursor.captures(&query, tree.root_node(), document.content.as_bytes())
-                {
-                    let node = capture.captures[0].node;
-                    diagnostics.push(Diagnostic {
-                        range: Range::from(node.range()).into(),
-                        severity: Some(DiagnosticSeverity::WARNING),
-                        message: format!("Comment past line {}", config.max_lines),
-                        ..Default::default()
-                    });
-                }
-            }
-        }
+        let config = Configuration {
+            warn_duplicate_alias_target: true,
+            ..Default::default()
+        };
+        let lints = lints(source, &config, LINT_DUPLICATE_ALIAS_TARGET);
+        assert_eq!(lints.len(), 2);
+        assert_eq!(lints[0].range.start.line, 2);
+        assert_eq!(lints[0].message, "r0 is already aliased as 'a'");
+        assert_eq!(
+            lints[0].related_information.as_ref().unwrap()[0]
+                .location
+                .range
+                .start
+                .line,
+            0
+        );
+        assert_eq!(lints[1].range.start.line, 4);
+        assert_eq!(lints[1].message, "r16 is already aliased as 'd'");
+    }
 
-        // Absolute jump to number lint
-        {
-            const BRANCH_INSTRUCTIONS: phf::Set<&'static str> = phf_set!(
-                "bdns", "bdnsal", "bdse", "bdseal", "bap", "bapz", "bapzal", "beq", "beqal",
-                "beqz", "beqzal", "bge", "bgeal", "bgez", "bgezal", "bgt", "bgtal", "bgtz",
-                "bgtzal", "ble", "bleal", "blez", "blezal", "blt", "bltal", "bltz", "bltzal",
-                "bna", "bnaz", "bnazal", "bne", "bneal", "bnez", "bnezal", "j", "jal"
-            );
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction operand: (operand (number))) @x",
-            )
+    #[test]
+    fn instruction_count() {
+        let source =
+            "# Pump control\ndefine Max 10\nalias pump d0\n\nstart:\ns pump On 1\nyield\nj start\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let mut summary = |config: &Configuration| {
+            let diagnostics = file_data.diagnostics(config);
+            let summary = diagnostics
+                .iter()
+                .filter(|x| x.message.ends_with("instructions used"))
+                .collect::<Vec<_>>();
+            assert!(summary.len() <= 1);
+            summary
+                .first()
+                .map(|x| (x.range.start.line, x.message.clone()))
+        };
+
+        let config = Configuration {
+            instruction_count: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            summary(&config),
+            Some((0, "6/128 instructions used".to_string()))
+        );
+
+        let config = Configuration {
+            instruction_count: true,
+            count_definitions: false,
+            count_labels: false,
+            max_lines: 64,
+            ..Default::default()
+        };
+        assert_eq!(
+            summary(&config),
+            Some((0, "3/64 instructions used".to_string()))
+        );
+
+        // Only shown when enabled
+        assert_eq!(summary(&Configuration::default()), None);
+    }
+
+    #[tokio::test]
+    async fn prepare_rename() {
+        let source = "define max 10\nalias pump d0\nstart:\ns pump On max\nj start\n";
+        let service = open(source).await;
+        let backend = service.inner();
+
+        let range = |line, start, end| {
+            Some(PrepareRenameResponse::Range(LspRange::new(
+                LspPosition::new(line, start),
+                LspPosition::new(line, end),
+            )))
+        };
+        let prepare = |line, character| backend.prepare_rename(position(line, character));
+
+        assert_eq!(prepare(3, 3).await.unwrap(), range(3, 2, 6));
+        assert_eq!(prepare(3, 12).await.unwrap(), range(3, 10, 13));
+        assert_eq!(prepare(4, 3).await.unwrap(), range(4, 2, 7));
+        assert_eq!(prepare(0, 8).await.unwrap(), range(0, 7, 10));
+
+        // Instruction keyword, logic type and register
+        assert_eq!(prepare(3, 0).await.unwrap(), None);
+        assert_eq!(prepare(3, 8).await.unwrap(), None);
+        assert_eq!(prepare(1, 11).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn utf16_positions() {
+        // Clients without UTF-8 support count columns in UTF-16 code units
+        let source =
+            "# Ünïcödé 😀\nalias pump d0 # 😀\nsb HASH(\"Ünicode😀\") On pump\ns pump On x\n";
+        let service = open(source).await;
+        let backend = service.inner();
+
+        let Some(hover) = hover_at(source, 2, 25).await else {
+            panic!("No hover on alias usage");
+        };
+        assert_eq!(
+            hover.range,
+            Some(LspRange::new(
+                LspPosition::new(2, 24),
+                LspPosition::new(2, 28)
+            ))
+        );
+
+        let files = backend.files.read().await;
+        let file_data = files
+            .get(&Url::parse("file:///test.ic10").unwrap())
             .unwrap();
-            let mut tree_cursor = tree.walk();
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
-            for (capture, _) in captures {
-                let capture = capture.captures[0].node;
-                let Some(operation_node) = capture.child_by_field_name("operation") else {
-                    continue;
-                };
-                let operation = operation_node
-                    .utf8_text(document.content.as_bytes())
-                    .unwrap();
-                if !BRANCH_INSTRUCTIONS.contains(operation) {
-                    continue;
-                }
+        let document = &file_data.document_data;
+        assert!(document.utf16);
+        assert_eq!(
+            document.client_range(
+                document
+                    .tree
+                    .as_ref()
+                    .unwrap()
+                    .root_node()
+                    .named_descendant_for_point_range(
+                        tree_sitter::Point::new(0, 0),
+                        tree_sitter::Point::new(0, 1)
+                    )
+                    .unwrap()
+            ),
+            LspRange::new(LspPosition::new(0, 0), LspPosition::new(0, 12))
+        );
+        assert_eq!(
+            document.to_byte_position(LspPosition::new(1, 18)),
+            LspPosition::new(1, 20)
+        );
+    }
 
-                tree_cursor.reset(capture);
-                let Some(last_operand) = capture.children_by_field_name("operand", &mut tree_cursor).into_iter().last() else {
-                    continue;
-                };
-                let last_operand = last_operand.child(0).unwrap();
+    #[tokio::test]
+    async fn completion_after_multibyte_characters() {
+        let service = open("sb HASH(\"é\") O\nmove r0 1 # é\n").await;
+        let backend = service.inner();
+        let complete = |line, character| {
+            backend.completion(CompletionParams {
+                text_document_position: position(line, character),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            })
+        };
 
-                if last_operand.kind() == "number" {
-                    diagnostics.push(Diagnostic::new(
-                        Range::from(capture.range()).into(),
-                        Some(DiagnosticSeverity::WARNING),
-                        Some(NumberOrString::String(LINT_ABSOLUTE_JUMP.to_string())),
-                        None,
-                        "Absolute jump to line number".to_string(),
-                        None,
-                        None,
-                    ));
-                }
-            }
+        let Some(CompletionResponse::Array(items)) = complete(0, 14).await.unwrap() else {
+            panic!("No completions after accented character");
+        };
+        assert!(items.iter().any(|x| x.label == "On"));
+        assert!(items.iter().all(|x| x.label.starts_with('O')));
+
+        // A UTF-8 position inside a character must not panic
+        backend
+            .files
+            .write()
+            .await
+            .values_mut()
+            .for_each(|x| x.document_data.utf16 = false);
+        assert_eq!(complete(1, 13).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn hash_completion_substrings() {
+        let service = open("sb HASH(\"Pump\") On 1\n").await;
+        let Some(CompletionResponse::Array(items)) = service
+            .inner()
+            .completion(CompletionParams {
+                text_document_position: position(0, 12),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            })
+            .await
+            .unwrap()
+        else {
+            panic!("No completions inside HASH");
+        };
+        let labels = items
+            .iter()
+            .map(|x| x.label.as_str())
+            .filter(|x| x.to_lowercase().contains("pump"))
+            .collect::<Vec<_>>();
+
+        let split = labels.iter().position(|x| !x.starts_with("Pump")).unwrap();
+        assert!(labels[..split].contains(&"Pumpkin"));
+        assert!(labels[split..].contains(&"Cooked Pumpkin"));
+        assert!(labels[split..].contains(&"Volume Pump"));
+        assert!(labels[split..].iter().all(|x| !x.starts_with("Pump")));
+
+        let sort_text = |label| {
+            items
+                .iter()
+                .find(|x| x.label == label)
+                .and_then(|x| x.sort_text.clone())
+                .unwrap()
+        };
+        assert!(sort_text("Pumpkin Soup") < sort_text("Cooked Pumpkin"));
+    }
+
+    #[tokio::test]
+    async fn slot_index_and_reagent_mode_completion() {
+        let service = open("ls r0 d0 \nlr r0 d0 \n").await;
+        let backend = service.inner();
+        let labels = |line, character| async move {
+            let Some(CompletionResponse::Array(items)) = backend
+                .completion(CompletionParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: None,
+                })
+                .await
+                .unwrap()
+            else {
+                panic!("No completions");
+            };
+            items.into_iter().map(|x| x.label).collect::<Vec<_>>()
+        };
+
+        let slots = labels(0, 9).await;
+        assert!(slots.iter().any(|x| x == "0"));
+        assert!(slots.iter().any(|x| x == "9"));
+        assert!(slots.iter().any(|x| x == "r0"));
+
+        let modes = labels(1, 9).await;
+        for mode in ["Contents", "Required", "Recipe"] {
+            assert!(modes.iter().any(|x| x == mode));
         }
+        assert!(!modes.iter().any(|x| x == "0"));
+    }
 
-        // Number batch mode
-        {
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction (operation)@op (operand (number)@n) .)",
-            )
-            .unwrap();
+    #[tokio::test]
+    async fn definition_completion() {
+        let service =
+            open("alias pump d0\ndefine Limit 3\nstart:\nalias x \ndefine y \nlabel z \n").await;
+        let backend = service.inner();
+        let labels = |line, character| async move {
+            let Some(CompletionResponse::Array(items)) = backend
+                .completion(CompletionParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: None,
+                })
+                .await
+                .unwrap()
+            else {
+                panic!("No completions");
+            };
+            items.into_iter().map(|x| x.label).collect::<Vec<_>>()
+        };
+        let has_definitions = |labels: &[String]| {
+            labels
+                .iter()
+                .any(|x| ["pump", "Limit", "start"].contains(&&**x))
+        };
 
-            let matches = cursor.matches(&query, tree.root_node(), document.content.as_bytes());
+        let alias = labels(3, 8).await;
+        assert!(alias.iter().any(|x| x == "r0"));
+        assert!(alias.iter().any(|x| x == "d0"));
+        assert!(!has_definitions(&alias));
 
-            for query_match in matches {
-                {
-                    let operation_node = query_match.captures[0].node;
-                    let operation_text = operation_node
-                        .utf8_text(document.content.as_bytes())
-                        .unwrap();
-                    if !operation_text.starts_with("lb") {
-                        continue;
-                    }
-                }
-                let node = query_match.captures[1].node;
+        let define = labels(4, 9).await;
+        assert!(define.iter().any(|x| x == "pi"));
+        assert!(!define.iter().any(|x| x == "r0"));
+        assert!(!has_definitions(&define));
 
-                let Ok(value) = node
-                    .utf8_text(document.content.as_bytes())
-                    .unwrap()
-                    .parse::<u8>() else {
-                    diagnostics.push(Diagnostic {
-                        range: Range::from(node.range()).into(),
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        message: "Use of non-integer batch mode".to_string(),
-                        ..Default::default()
-                    });
-                    continue;
-                };
+        let label = labels(5, 8).await;
+        assert!(label.iter().any(|x| x == "d0"));
+        assert!(!has_definitions(&label));
 
-                let Some(replacement) = instructions::BATCH_MODE_LOOKUP.get(&value) else {
-                    diagnostics.push(Diagnostic {
-                        range: Range::from(node.range()).into(),
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        message: "Invalid batch mode".to_string(),
-                        ..Default::default()
-                    });
-                    continue;
-                };
+        assert_eq!(labels(3, 6).await, Vec::<String>::new());
+    }
 
-                diagnostics.push(Diagnostic {
-                    range: Range::from(node.range()).into(),
-                    severity: Some(DiagnosticSeverity::WARNING),
-                    code: Some(NumberOrString::String(LINT_NUMBER_BATCH_MODE.to_string())),
-                    message: "Use of literal number for batch mode".to_string(),
-                    data: Some(Value::String(replacement.to_string())),
-                    ..Default::default()
-                });
+    #[tokio::test]
+    async fn completion_resolve() {
+        let service = open("l r0 d0 Temperatur\nlb r0 0 On Average\ns").await;
+        let backend = service.inner();
+        let resolved = |line, character, label: &'static str| async move {
+            let Some(CompletionResponse::Array(items)) = backend
+                .completion(CompletionParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: None,
+                })
+                .await
+                .unwrap()
+            else {
+                panic!("No completions");
+            };
+            let item = items.into_iter().find(|x| x.label == label).unwrap();
+            assert!(item.documentation.is_none());
+            match backend
+                .completion_resolve(item)
+                .await
+                .unwrap()
+                .documentation
+            {
+                Some(Documentation::String(docs)) => docs,
+                docs => panic!("Unexpected documentation {docs:?}"),
             }
-        }
+        };
+
+        assert_eq!(
+            resolved(0, 18, "Temperature").await,
+            instructions::logic_type_docs("Temperature").unwrap()
+        );
+        assert_eq!(
+            resolved(1, 18, "Average").await,
+            instructions::BATCH_MODE_DOCS["Average"]
+        );
+        assert_eq!(
+            resolved(2, 1, "sleep").await,
+            instructions::instruction_docs("sleep").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn register_completion() {
+        let service = open("move \nl r0 \n").await;
+        let backend = service.inner();
+        let completions = |line, character| async move {
+            let Some(CompletionResponse::Array(mut items)) = backend
+                .completion(CompletionParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: None,
+                })
+                .await
+                .unwrap()
+            else {
+                panic!("No completions");
+            };
+            items.sort_by(|x, y| x.sort_text.cmp(&y.sort_text));
+            items.into_iter().map(|x| x.label).collect::<Vec<_>>()
+        };
 
-        // Number reagent mode
-        {
-            let mut cursor = QueryCursor::new();
-            let query = Query::new(
-                tree_sitter_ic10::language(),
-                "(instruction (operation \"lr\") . (operand) . (operand) . (operand (number)@n))",
-            )
-            .unwrap();
+        let registers = completions(0, 5).await;
+        let expected = (0..18)
+            .map(|i| format!("r{i}"))
+            .chain(["sp".to_string(), "ra".to_string()])
+            .collect::<Vec<_>>();
+        assert_eq!(registers[..20], expected);
+        assert!(completions(1, 5).await.contains(&"dr17".to_string()));
+
+        backend
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: json!({"max_registers": 16}),
+            })
+            .await;
+        let registers = completions(0, 5).await;
+        assert!(registers.contains(&"r15".to_string()));
+        assert!(!registers.contains(&"r16".to_string()));
+        assert!(registers.contains(&"sp".to_string()));
+        assert!(!completions(1, 5).await.contains(&"dr16".to_string()));
+    }
 
-            let captures = cursor.captures(&query, tree.root_node(), document.content.as_bytes());
+    #[tokio::test]
+    async fn logic_type_access_completion() {
+        let service = open("s d0 \nl r0 d0 \nsb 0 \n").await;
+        let backend = service.inner();
+        let labels = |line, character| async move {
+            let Some(CompletionResponse::Array(items)) = backend
+                .completion(CompletionParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: None,
+                })
+                .await
+                .unwrap()
+            else {
+                panic!("No completions");
+            };
+            items.into_iter().map(|x| x.label).collect::<Vec<_>>()
+        };
 
-            for (capture, _) in captures {
-                let node = capture.captures[0].node;
+        let store = labels(0, 5).await;
+        assert!(store.iter().any(|x| x == "On"));
+        assert!(store.iter().any(|x| x == "ClearMemory"));
+        assert!(!store.iter().any(|x| x == "Temperature"));
 
-                let Ok(value) = node
-                    .utf8_text(document.content.as_bytes())
-                    .unwrap()
-                    .parse::<u8>() else {
-                    diagnostics.push(Diagnostic {
-                        range: Range::from(node.range()).into(),
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        message: "Use of non-integer reagent mode".to_string(),
-                        ..Default::default()
-                    });
-                    continue;
-                };
+        let load = labels(1, 8).await;
+        assert!(load.iter().any(|x| x == "On"));
+        assert!(load.iter().any(|x| x == "Temperature"));
+        assert!(!load.iter().any(|x| x == "ClearMemory"));
 
-                let Some(replacement) = instructions::REAGENT_MODE_LOOKUP.get(&value) else {
-                    diagnostics.push(Diagnostic {
-                        range: Range::from(node.range()).into(),
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        message: "Invalid reagent mode".to_string(),
-                        ..Default::default()
-                    });
-                    continue;
-                };
+        let batch_store = labels(2, 5).await;
+        assert!(batch_store.iter().any(|x| x == "On"));
+        assert!(!batch_store.iter().any(|x| x == "Temperature"));
+    }
 
-                diagnostics.push(Diagnostic {
-                    range: Range::from(node.range()).into(),
-                    severity: Some(DiagnosticSeverity::WARNING),
-                    code: Some(NumberOrString::String(LINT_NUMBER_REAGENT_MODE.to_string())),
-                    message: "Use of literal number for reagent mode".to_string(),
-                    data: Some(Value::String(replacement.to_string())),
-                    ..Default::default()
-                });
-            }
-        }
+    #[tokio::test]
+    async fn prefab_completion_outside_string() {
+        let service = open("lb r0 Pumpk\n").await;
+        let Some(CompletionResponse::Array(items)) = service
+            .inner()
+            .completion(CompletionParams {
+                text_document_position: position(0, 11),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            })
+            .await
+            .unwrap()
+        else {
+            panic!("No completions for prefab hash");
+        };
 
-        self.client
-            .publish_diagnostics(uri.to_owned(), diagnostics, None)
-            .await;
+        let item = items.iter().find(|x| x.label == "Pumpkin").unwrap();
+        let Some(CompletionTextEdit::Edit(edit)) = &item.text_edit else {
+            panic!("No text edit");
+        };
+        // The game hashes the prefab name, not the display name that is matched
+        assert_eq!(edit.new_text, instructions::hash("ItemPumpkin").to_string());
+        assert_eq!(
+            edit.range,
+            LspRange::new(LspPosition::new(0, 6), LspPosition::new(0, 11))
+        );
     }
-}
 
-fn get_current_parameter(instruction_node: Node, position: usize) -> (usize, Option<Node>) {
-    let mut ret: usize = 0;
-    let mut cursor = instruction_node.walk();
-    for operand in instruction_node.children_by_field_name("operand", &mut cursor) {
-        if operand.end_position().column > position {
-            break;
+    #[tokio::test]
+    async fn custom_data_dir() {
+        let dir = std::env::temp_dir().join(format!("ic10lsp-data-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("instructions_sig.txt"), "frob r? (r?|num)\n").unwrap();
+        std::fs::write(
+            dir.join("instructions_desc.txt"),
+            "# Custom docs\nfrob Frobnicates the value\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("logictypes.txt"),
+            "FrobLevel Level of frobnication\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("devicelogictypes.txt"),
+            "Frobnicator FrobLevel On\n",
+        )
+        .unwrap();
+        // Custom data is global, so other tests should not keep seeing it
+        struct UnloadDataDir;
+        impl Drop for UnloadDataDir {
+            fn drop(&mut self) {
+                instructions::unload_data_dir();
+            }
         }
-        ret += 1;
+        let _unload = UnloadDataDir;
+        let result = instructions::load_data_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+
+        assert_eq!(
+            instructions::signature(instructions::DEFAULT_RULESET, "frob")
+                .unwrap()
+                .to_string(),
+            " r? (r?|num)"
+        );
+        assert_eq!(
+            instructions::instruction_docs("frob"),
+            Some("Frobnicates the value")
+        );
+        assert!(instructions::is_logic_type("FrobLevel"));
+        assert!(instructions::signature(instructions::DEFAULT_RULESET, "add").is_some());
+        assert_eq!(
+            instructions::signatures(instructions::DEFAULT_RULESET, "frob").len(),
+            1
+        );
+
+        let source = "frob r0 1\nl r1 d0 FrobLevel\n";
+        let mut file_data =
+            FileData::new(Url::parse("file:///test.ic10").unwrap(), source.to_string());
+        let diagnostics = file_data
+            .diagnostics(&Configuration::default())
+            .into_iter()
+            .filter(|x| x.severity == Some(DiagnosticSeverity::ERROR))
+            .map(|x| x.message)
+            .collect::<Vec<_>>();
+        assert_eq!(diagnostics, Vec::<String>::new());
+
+        // Logic types are narrowed down to those of a known prefab
+        let service =
+            open("define Target HASH(\"Frobnicator\")\nlb r0 Target \nlb r0 HASH(\"Other\") \n")
+                .await;
+        let backend = service.inner();
+        let complete = |line, character| async move {
+            let Some(CompletionResponse::Array(items)) = backend
+                .completion(CompletionParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: None,
+                })
+                .await
+                .unwrap()
+            else {
+                panic!("No completions");
+            };
+            items.into_iter().map(|x| x.label).collect::<Vec<_>>()
+        };
+
+        let labels = complete(1, 13).await;
+        assert!(labels.contains(&"FrobLevel".to_string()));
+        assert!(labels.contains(&"On".to_string()));
+        assert!(!labels.contains(&"Temperature".to_string()));
+        assert!(complete(2, 22).await.contains(&"Temperature".to_string()));
     }
 
-    let operand = instruction_node
-        .children_by_field_name("operand", &mut cursor)
-        .nth(ret);
+    #[tokio::test]
+    async fn references() {
+        let source = "define Limit 5\nalias pump d0\nstart:\ns pump Setting Limit\nj start\nj 2\n\
+                      beqz r0 start\n";
+        let service = open(source).await;
+        let backend = service.inner();
+        let references = |line, character, include_declaration| async move {
+            let mut lines = backend
+                .references(ReferenceParams {
+                    text_document_position: position(line, character),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: tower_lsp::lsp_types::ReferenceContext {
+                        include_declaration,
+                    },
+                })
+                .await
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|x| x.range.start.line)
+                .collect::<Vec<_>>();
+            lines.sort();
+            lines
+        };
 
-    cursor.reset(instruction_node);
-    (ret, operand)
-}
+        assert_eq!(references(3, 16, true).await, vec![0, 3]);
+        assert_eq!(references(3, 16, false).await, vec![3]);
+        assert_eq!(references(3, 3, true).await, vec![1, 3]);
+        assert_eq!(references(3, 3, false).await, vec![3]);
+        // The numeric jump to the label's line is not a reference to it
+        assert_eq!(references(4, 3, true).await, vec![2, 4, 6]);
+        assert_eq!(references(4, 3, false).await, vec![4, 6]);
+    }
 
-trait NodeEx: Sized {
-    fn find_parent(&self, kind: &str) -> Option<Self>;
-    fn query<'a>(&'a self, query: &str, content: impl AsRef<[u8]>) -> Option<Node<'a>>;
-}
+    #[tokio::test]
+    async fn folding_ranges() {
+        let folding_ranges = |source: &'static str| async move {
+            open(source)
+                .await
+                .inner()
+                .folding_range(FoldingRangeParams {
+                    text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                        uri: Url::parse("file:///test.ic10").unwrap(),
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|x| (x.start_line, x.end_line, x.kind))
+                .collect::<Vec<_>>()
+        };
 
-impl<'a> NodeEx for Node<'a> {
-    fn find_parent(&self, kind: &str) -> Option<Self> {
-        let mut cur = self.clone();
-        while cur.kind() != kind {
-            cur = cur.parent()?;
-        }
-        Some(cur)
+        // The last label block runs to the end of the file, single comment lines are not folded
+        let source = "# Setup\n# values\nmove r0 1\nstart:\nyield\n# single\nj start\nend:\n\
+                      move r1 2\nj end\n";
+        assert_eq!(
+            folding_ranges(source).await,
+            vec![
+                (3, 6, Some(FoldingRangeKind::Region)),
+                (7, 9, Some(FoldingRangeKind::Region)),
+                (0, 1, Some(FoldingRangeKind::Comment)),
+            ]
+        );
+        assert_eq!(folding_ranges("move r0 1\nyield\n").await, vec![]);
     }
 
-    fn query(&self, query: &str, content: impl AsRef<[u8]>) -> Option<Node<'a>> {
-        let mut cursor = QueryCursor::new();
-        let query = Query::new(tree_sitter_ic10::language(), query).unwrap();
+    #[tokio::test]
+    async fn bundled_device_logic_types() {
+        let service = open("lb r0 HASH(\"StructureLogicDial\") \n").await;
+        let Some(CompletionResponse::Array(items)) = service
+            .inner()
+            .completion(CompletionParams {
+                text_document_position: position(0, 33),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            })
+            .await
+            .unwrap()
+        else {
+            panic!("No completions");
+        };
+        let mut labels = items.into_iter().map(|x| x.label).collect::<Vec<_>>();
+        labels.sort();
 
-        let mut captures = cursor.captures(&query, self.clone(), content.as_ref());
-        captures
-            .next()
-            .map(|x| x.0.captures)
-            .and_then(|x| x.get(0))
-            .map(|x| x.node)
+        assert_eq!(labels, vec!["Mode", "PrefabHash", "Setting"]);
     }
-}
 
-#[tokio::main]
-async fn main() {
-    use clap::Parser as _;
-    let cli = cli::Cli::parse();
+    #[tokio::test]
+    async fn project_config_file() {
+        let root = std::env::temp_dir().join(format!("ic10lsp-config-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join(PROJECT_CONFIG_FILE),
+            "max_lines = 64\nruleset = \"legacy\"\n\n[lints]\nL001 = \"off\"\n",
+        )
+        .unwrap();
 
-    let mut parser = Parser::new();
-    parser
-        .set_language(tree_sitter_ic10::language())
-        .expect("Failed to set language");
+        let service = open("").await;
+        let backend = service.inner();
+        #[allow(deprecated)]
+        backend
+            .initialize(InitializeParams {
+                root_uri: Some(Url::from_directory_path(&root).unwrap()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        files: Arc::new(RwLock::new(HashMap::new())),
-        config: Arc::new(RwLock::new(Configuration::default())),
-    });
+        {
+            let config = backend.config.read().await;
+            assert_eq!(config.max_lines, 64);
+            assert_eq!(config.max_columns, 52);
+            assert_eq!(config.ruleset, "legacy");
+            assert_eq!(
+                config.lint_severity(LINT_ABSOLUTE_JUMP, DiagnosticSeverity::WARNING),
+                None
+            );
+        }
 
-    if !cli.listen && cli.host.is_none() {
-        // stdin/stdout
-        Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
-            .serve(service)
+        // Settings from the client take precedence
+        backend
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: json!({"max_lines": 100}),
+            })
             .await;
-    } else if cli.listen {
-        // listen
+        let config = backend.config.read().await;
+        assert_eq!(config.max_lines, 100);
+        assert_eq!(config.ruleset, "legacy");
+    }
 
-        let host = cli
-            .host
-            .map(Cow::Owned)
-            .unwrap_or(Cow::Borrowed("127.0.0.1"))
-            .parse::<Ipv4Addr>()
-            .expect("Could not parse IP address");
+    #[tokio::test]
+    async fn trigger_characters() {
+        let service = open("").await;
+        let result = service
+            .inner()
+            .initialize(InitializeParams::default())
+            .await
+            .unwrap();
+        let completion = result.capabilities.completion_provider.unwrap();
+        assert_eq!(completion.trigger_characters, Some(vec![" ".to_string()]));
+
+        let service = open("").await;
+        let result = service
+            .inner()
+            .initialize(InitializeParams {
+                initialization_options: Some(json!({"completion": {"trigger_characters": []}})),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let completion = result.capabilities.completion_provider.unwrap();
+        let signature_help = result.capabilities.signature_help_provider.unwrap();
+        assert_eq!(completion.trigger_characters, Some(vec![]));
+        assert_eq!(signature_help.trigger_characters, Some(vec![]));
+    }
 
-        let port = cli.port.unwrap_or(9257);
+    #[tokio::test]
+    async fn hash_inlay_hints_in_range() {
+        let service = open("move r0 HASH(\"ItemPumpkin\")\nmove r1 HASH(\"ItemPumpkin\")\n").await;
+        let hints = |range: LspRange| {
+            let service = &service;
+            async move {
+                service
+                    .inner()
+                    .inlay_hint(InlayHintParams {
+                        work_done_progress_params: Default::default(),
+                        text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                            uri: Url::parse("file:///test.ic10").unwrap(),
+                        },
+                        range,
+                    })
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .into_iter()
+                    .map(|x| x.position.line)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let range =
+            |start, end| LspRange::new(LspPosition::new(start, 0), LspPosition::new(end, 0));
 
-        let stream = {
-            let listener = TcpListener::bind((host, port)).await.unwrap();
-            let (stream, _) = listener.accept().await.unwrap();
-            stream
+        assert_eq!(hints(range(0, 2)).await, vec![0, 1]);
+        assert_eq!(hints(range(1, 2)).await, vec![1]);
+        assert!(hints(range(0, 0)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn label_inlay_hints() {
+        let service = open("j end\nstart:\nyield\nbeqz r0 start\nend:\n").await;
+        let hints = service
+            .inner()
+            .inlay_hint(InlayHintParams {
+                work_done_progress_params: Default::default(),
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                range: LspRange::new(LspPosition::new(0, 0), LspPosition::new(5, 0)),
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|x| match x.label {
+                InlayHintLabel::String(label) => (x.position, label),
+                InlayHintLabel::LabelParts(_) => panic!("Unexpected label parts"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            hints,
+            vec![
+                (LspPosition::new(0, 5), "→ line 5".to_string()),
+                (LspPosition::new(3, 13), "→ line 2".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn will_save_edits() {
+        let service = open("lb r0 HASH(\"Foo\") On 1  \nlr r0 d0 2 Iron\t\nlb r0 0 On 1.5\n").await;
+        let backend = service.inner();
+        let will_save = || {
+            backend.will_save_wait_until(WillSaveTextDocumentParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                reason: tower_lsp::lsp_types::TextDocumentSaveReason::MANUAL,
+            })
         };
 
-        let (input, output) = tokio::io::split(stream);
-        Server::new(input, output, socket).serve(service).await;
-    } else {
-        let host = cli.host.expect("No host given");
-        let port = cli.port.expect("No port given");
+        // Nothing is changed unless enabled
+        assert_eq!(will_save().await.unwrap(), None);
 
-        let stream = TcpStream::connect((host, port))
+        backend
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: json!({
+                    "save_actions": {"trim_trailing_whitespace": true, "named_modes": true}
+                }),
+            })
+            .await;
+        let edits = will_save()
             .await
-            .expect("Could not open TCP stream");
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|x| {
+                (
+                    x.range.start.line,
+                    x.range.start.character,
+                    x.range.end.character,
+                    x.new_text,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edits,
+            vec![
+                (0, 22, 24, "".to_string()),
+                (1, 15, 16, "".to_string()),
+                (0, 21, 22, "Sum".to_string()),
+                (1, 9, 10, "Recipe".to_string()),
+            ]
+        );
+    }
 
-        let (input, output) = tokio::io::split(stream);
-        Server::new(input, output, socket).serve(service).await;
+    #[tokio::test]
+    async fn will_save_keeps_crlf() {
+        let service = open("move r0 1 \r\nyield\r\nj 0\t\r\n").await;
+        let backend = service.inner();
+        backend
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: json!({"save_actions": {"trim_trailing_whitespace": true}}),
+            })
+            .await;
+        let edits = backend
+            .will_save_wait_until(WillSaveTextDocumentParams {
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+                reason: tower_lsp::lsp_types::TextDocumentSaveReason::MANUAL,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                TextEdit::new(
+                    LspRange::new(LspPosition::new(0, 9), LspPosition::new(0, 10)),
+                    String::new()
+                ),
+                TextEdit::new(
+                    LspRange::new(LspPosition::new(2, 3), LspPosition::new(2, 4)),
+                    String::new()
+                ),
+            ]
+        );
     }
-}
 
-#[derive(Clone, Copy)]
-struct Position(tower_lsp::lsp_types::Position);
+    #[tokio::test]
+    async fn minify() {
+        let minify = |source: &'static str| async move {
+            open(source)
+                .await
+                .inner()
+                .execute_command(ExecuteCommandParams {
+                    command: "minify".to_string(),
+                    arguments: vec![Value::String("file:///test.ic10".to_string())],
+                    work_done_progress_params: Default::default(),
+                })
+                .await
+                .unwrap()
+        };
 
-#[derive(Clone, Debug)]
-struct Range(tower_lsp::lsp_types::Range);
+        assert_eq!(
+            minify("# Pump control\nalias pump d0\n\nstart:  # loop\n  s  pump On 1 # on\nyield\nj start\n\n").await,
+            Some(Value::String(
+                "alias pump d0\nstart:\ns pump On 1\nyield\nj start".to_string()
+            ))
+        );
 
-impl Range {
-    pub fn contains(&self, position: Position) -> bool {
-        let (start_line, start_char) = (self.0.start.line, self.0.start.character);
-        let (end_line, end_char) = (self.0.end.line, self.0.end.character);
-        let (line, character) = (position.0.line, position.0.character);
+        // Line numbers matter to relative jumps, so lines stay where they are
+        assert_eq!(
+            minify("# Wait\nyield\nbrnez r0 -1\n# Done\n").await,
+            Some(Value::String("\nyield\nbrnez r0 -1".to_string()))
+        );
+        assert_eq!(
+            minify("define Start 1\n# Loop\nyield\nj Start\n").await,
+            Some(Value::String(
+                "define Start 1\n\nyield\nj Start".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn canonicalize() {
+        let canonicalize = |source: &'static str| async move {
+            open(source)
+                .await
+                .inner()
+                .execute_command(ExecuteCommandParams {
+                    command: "canonicalize".to_string(),
+                    arguments: vec![Value::String("file:///test.ic10".to_string())],
+                    work_done_progress_params: Default::default(),
+                })
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(
+            canonicalize("start:\nYIELD\ndefine B 2\ndefine A 1\nj start\n\n").await,
+            Some(json!("define A 1\ndefine B 2\nstart:\nyield\nj start\n"))
+        );
 
-        (line > start_line && line < end_line)
-            || (line == start_line && character >= start_char)
-            || (line == end_line && character <= end_char)
+        // Moving defines would change where these jumps go
+        for source in [
+            "yield\ndefine A 1\nj 0\n",
+            "define Start 0\nyield\ndefine A 1\nj Start\n",
+            "yield\ndefine A 1\njr -2\n",
+        ] {
+            assert_eq!(canonicalize(source).await, Some(json!(source)));
+        }
     }
-}
 
-impl From<tree_sitter::Point> for Position {
-    fn from(value: tree_sitter::Point) -> Self {
-        Position(tower_lsp::lsp_types::Position::new(
-            value.row as u32,
-            value.column as u32,
-        ))
+    #[tokio::test]
+    async fn organize() {
+        let organize = |source: &'static str| async move {
+            open(source)
+                .await
+                .inner()
+                .execute_command(ExecuteCommandParams {
+                    command: "organize".to_string(),
+                    arguments: vec![Value::String("file:///test.ic10".to_string())],
+                    work_done_progress_params: Default::default(),
+                })
+                .await
+        };
+
+        let source =
+            "alias pump d0\nstart:\ns pump On 1\n\n\n# Wait for it\nloop:\nalias sensor d1\n\
+                      yield\nj loop\nend:\nother:\ndefine Limit 3\n\n";
+        let Some(Value::Array(edits)) = organize(source).await.unwrap() else {
+            panic!("No edits");
+        };
+        let edits = edits
+            .into_iter()
+            .map(|x| serde_json::from_value::<TextEdit>(x).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edits,
+            vec![TextEdit::new(
+                LspRange::new(LspPosition::new(0, 0), LspPosition::new(14, 0)),
+                "alias pump d0\nalias sensor d1\ndefine Limit 3\n\nstart:\ns pump On 1\n\n\
+                 # Wait for it\nloop:\nyield\nj loop\n\nend:\nother:\n"
+                    .to_string()
+            )]
+        );
+
+        let organized = "alias pump d0\n\nstart:\nyield\nj start\n";
+        assert_eq!(organize(organized).await.unwrap(), Some(json!([])));
+        assert!(organize("yield\nj 0\n").await.is_err());
     }
-}
 
-impl From<tower_lsp::lsp_types::Position> for Position {
-    fn from(value: tower_lsp::lsp_types::Position) -> Self {
-        Position(value)
+    #[tokio::test]
+    async fn stats() {
+        let source = "# Counter\nalias counter r3\nstart:\npush counter\npush ra\npop r0\nadd counter counter 1\nj start";
+        let stats = open(source)
+            .await
+            .inner()
+            .execute_command(ExecuteCommandParams {
+                command: "stats".to_string(),
+                arguments: vec![Value::String("file:///test.ic10".to_string())],
+                work_done_progress_params: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stats,
+            Some(json!({
+                "lines": 8,
+                "instructions": 7,
+                "max_instructions": 128,
+                "longest_line": 21,
+                "max_columns": 52,
+                "registers": ["r0", "r3", "r17"],
+                "stack": {
+                    "pushes": 2,
+                    "pops": 1,
+                    "max_depth": 2,
+                },
+            }))
+        );
     }
-}
 
-impl From<Position> for tower_lsp::lsp_types::Position {
-    fn from(value: Position) -> Self {
-        value.0
+    #[tokio::test]
+    async fn symbols() {
+        let source = "alias pump d0\ndefine Target 1.5\ndefine Kind HASH(\"ItemPumpkin\")\nstart:\ns pump Setting Target\nj start\n";
+        let symbols = open(source)
+            .await
+            .inner()
+            .execute_command(ExecuteCommandParams {
+                command: "symbols".to_string(),
+                arguments: vec![Value::String("file:///test.ic10".to_string())],
+                work_done_progress_params: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            symbols,
+            Some(json!([
+                {"name": "pump", "kind": "alias", "value": "d0", "line": 0},
+                {"name": "Target", "kind": "define", "value": 1.5, "line": 1},
+                {"name": "Kind", "kind": "define", "value": 1277828144.0, "line": 2},
+                {"name": "start", "kind": "label", "value": 3, "line": 3},
+            ]))
+        );
     }
-}
 
-impl From<Position> for tree_sitter::Point {
-    fn from(value: Position) -> Self {
-        tree_sitter::Point {
-            row: value.0.line as usize,
-            column: value.0.character as usize,
+    #[tokio::test]
+    async fn incremental_changes() {
+        let service = open("alias pump d0\ns pump On 1").await;
+        let backend = service.inner();
+        let uri = Url::parse("file:///test.ic10").unwrap();
+
+        let range = |start_line, start_char, end_line, end_char| {
+            LspRange::new(
+                LspPosition::new(start_line, start_char),
+                LspPosition::new(end_line, end_char),
+            )
+        };
+        let changes = [
+            (range(1, 11, 1, 11), "\n", "alias pump d0\ns pump On 1\n"),
+            (range(0, 11, 0, 13), "db", "alias pump db\ns pump On 1\n"),
+            (
+                range(2, 0, 2, 0),
+                "yield",
+                "alias pump db\ns pump On 1\nyield\n",
+            ),
+            (
+                range(1, 0, 2, 5),
+                "l r0 pump Öffnen",
+                "alias pump db\nl r0 pump Öffnen\n",
+            ),
+            (range(1, 10, 1, 17), "On", "alias pump db\nl r0 pump On\n"),
+            (range(1, 12, 2, 0), "", "alias pump db\nl r0 pump On\n"),
+        ];
+
+        for (range, text, expected) in changes {
+            let mut files = backend.files.write().await;
+            let document = &mut files.get_mut(&uri).unwrap().document_data;
+            document.apply_change(range, text);
+            assert_eq!(document.content, expected);
+
+            let fresh = document.parser.parse(&document.content, None).unwrap();
+            assert_eq!(
+                document.tree.as_ref().unwrap().root_node().to_sexp(),
+                fresh.root_node().to_sexp()
+            );
         }
     }
-}
 
-impl From<tree_sitter::Range> for Range {
-    fn from(value: tree_sitter::Range) -> Self {
-        Range(tower_lsp::lsp_types::Range::new(
-            Position::from(value.start_point).into(),
-            Position::from(value.end_point).into(),
-        ))
+    #[tokio::test]
+    async fn semantic_tokens_hash_and_constants() {
+        let service = open("sb HASH(\"Foo\") On pi\nmove r0 Color.Blue\n").await;
+        let Some(SemanticTokensResult::Tokens(tokens)) = service
+            .inner()
+            .semantic_tokens_full(SemanticTokensParams {
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                text_document: tower_lsp::lsp_types::TextDocumentIdentifier {
+                    uri: Url::parse("file:///test.ic10").unwrap(),
+                },
+            })
+            .await
+            .unwrap()
+        else {
+            panic!("No semantic tokens");
+        };
+
+        let token_type = |x: SemanticTokenType| {
+            SEMANTIC_SYMBOL_LEGEND.iter().position(|y| *y == x).unwrap() as u32
+        };
+        let tokens = tokens
+            .data
+            .iter()
+            .map(|x| (x.delta_line, x.delta_start, x.length, x.token_type))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                (0, 0, 2, token_type(SemanticTokenType::KEYWORD)),
+                (0, 3, 4, token_type(SemanticTokenType::FUNCTION)),
+                (0, 6, 3, token_type(SemanticTokenType::STRING)),
+                (0, 6, 2, token_type(SemanticTokenType::STRING)),
+                (0, 3, 2, token_type(SemanticTokenType::ENUM_MEMBER)),
+                (1, 0, 4, token_type(SemanticTokenType::KEYWORD)),
+                (0, 5, 2, token_type(SemanticTokenType::MACRO)),
+                (0, 3, 10, token_type(SemanticTokenType::ENUM_MEMBER)),
+            ]
+        );
     }
-}
 
-impl From<tower_lsp::lsp_types::Range> for Range {
-    fn from(value: tower_lsp::lsp_types::Range) -> Self {
-        Range(value)
+    #[tokio::test]
+    async fn semantic_tokens_document_order() {
+        let source = "define Limit 5 # max\nstart: add r0 Limit $FF # sum\n\
+                      s d0 Setting HASH(\"Foo\") # x\nbeqz r0 start # loop\n";
+        let service = open(source).await;
+        let backend = service.inner();
+        let files = backend.files.read().await;
+        let document = &files
+            .get(&Url::parse("file:///test.ic10").unwrap())
+            .unwrap()
+            .document_data;
+
+        let mut line = 0;
+        let mut column = 0;
+        let tokens = backend
+            .semantic_tokens(document, None, Configuration::default().max_registers)
+            .unwrap()
+            .into_iter()
+            .map(|x| {
+                if x.delta_line > 0 {
+                    column = 0;
+                }
+                line += x.delta_line;
+                column += x.delta_start;
+                let text = &source.lines().nth(line as usize).unwrap()
+                    [column as usize..(column + x.length) as usize];
+                (line, text)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                (0, "define"),
+                (0, "Limit"),
+                (0, "5"),
+                (0, "# max"),
+                (1, "start"),
+                (1, "add"),
+                (1, "r0"),
+                (1, "Limit"),
+                (1, "$FF"),
+                (1, "# sum"),
+                (2, "s"),
+                (2, "d0"),
+                (2, "Setting"),
+                (2, "HASH"),
+                (2, "Foo"),
+                (2, "# x"),
+                (3, "beqz"),
+                (3, "r0"),
+                (3, "start"),
+                (3, "# loop"),
+            ]
+        );
     }
-}
 
-impl From<Range> for tower_lsp::lsp_types::Range {
-    fn from(value: Range) -> Self {
-        value.0
+    #[tokio::test]
+    async fn semantic_tokens_special_registers() {
+        let source = "push ra\nmove sp r16\nadd r0 r17 r20\n";
+        let service = open(source).await;
+        let backend = service.inner();
+        let files = backend.files.read().await;
+        let document = &files
+            .get(&Url::parse("file:///test.ic10").unwrap())
+            .unwrap()
+            .document_data;
+
+        let modifier = 1
+            << SEMANTIC_MODIFIER_LEGEND
+                .iter()
+                .position(|x| *x == SemanticTokenModifier::STATIC)
+                .unwrap();
+        let registers = |max_registers| {
+            backend
+                .semantic_tokens(document, None, max_registers)
+                .unwrap()
+                .into_iter()
+                .filter(|x| {
+                    SEMANTIC_SYMBOL_LEGEND[x.token_type as usize] == SemanticTokenType::MACRO
+                })
+                .map(|x| x.token_modifiers_bitset == modifier)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(registers(18), [true, true, true, false, true]);
+        // r20 only exists with more registers, but is not special
+        assert_eq!(registers(24), [true, true, true, false, true, false]);
+    }
+
+    #[tokio::test]
+    async fn semantic_tokens_range() {
+        let source = "move r0 1\nmove r1 2\nmove r2 3\n";
+        let service = open(source).await;
+        let backend = service.inner();
+        let files = backend.files.read().await;
+        let document = &files
+            .get(&Url::parse("file:///test.ic10").unwrap())
+            .unwrap()
+            .document_data;
+
+        let max_registers = Configuration::default().max_registers;
+        let full = backend
+            .semantic_tokens(document, None, max_registers)
+            .unwrap();
+        let range = backend
+            .semantic_tokens(
+                document,
+                Some(LspRange::new(
+                    LspPosition::new(1, 0),
+                    LspPosition::new(2, 0),
+                )),
+                max_registers,
+            )
+            .unwrap();
+
+        // Still relative to the start of the document
+        assert_eq!(range.len(), 3);
+        assert_eq!(range[0].delta_line, 1);
+        assert_eq!(range[0].delta_start, 0);
+        assert_eq!(range[1..], full[4..6]);
     }
 }
@@ -0,0 +1,112 @@
+//! Tree-sitter queries used by the language server, compiled once and shared between requests.
+
+use std::sync::LazyLock;
+
+use tree_sitter::Query;
+
+fn compile(source: &str) -> Query {
+    Query::new(tree_sitter_ic10::language(), source).unwrap()
+}
+
+pub(crate) static LINES: LazyLock<Query> = LazyLock::new(|| compile("(line)@x"));
+pub(crate) static INSTRUCTIONS: LazyLock<Query> = LazyLock::new(|| compile("(instruction)@x"));
+pub(crate) static OPERATIONS: LazyLock<Query> =
+    LazyLock::new(|| compile("(instruction (operation)@x)"));
+pub(crate) static IDENTIFIERS: LazyLock<Query> = LazyLock::new(|| compile("(identifier)@x"));
+pub(crate) static NUMBERS: LazyLock<Query> = LazyLock::new(|| compile("(number)@x"));
+pub(crate) static COMMENTS: LazyLock<Query> = LazyLock::new(|| compile("(comment)@x"));
+pub(crate) static NEWLINES: LazyLock<Query> = LazyLock::new(|| compile("(newline)@x"));
+pub(crate) static PREPROC_STRINGS: LazyLock<Query> =
+    LazyLock::new(|| compile("(preproc_string)@x"));
+pub(crate) static REGISTERS_AND_DEVICES: LazyLock<Query> =
+    LazyLock::new(|| compile("(register)@register (device)@device"));
+pub(crate) static HASH_STRINGS: LazyLock<Query> =
+    LazyLock::new(|| compile("(hash_preproc string: (preproc_string)@x)"));
+
+pub(crate) static SYNTAX_ERRORS: LazyLock<Query> = LazyLock::new(|| compile("(ERROR)@error"));
+pub(crate) static INVALID_INSTRUCTIONS: LazyLock<Query> =
+    LazyLock::new(|| compile("(instruction (invalid_instruction)@error)"));
+pub(crate) static NUMBER_OPERANDS: LazyLock<Query> =
+    LazyLock::new(|| compile("(instruction operand: (operand (number))) @x"));
+pub(crate) static LAST_NUMBER_OPERANDS: LazyLock<Query> =
+    LazyLock::new(|| compile("(instruction (operation)@op (operand (number)@n) .)"));
+pub(crate) static REAGENT_MODE_NUMBERS: LazyLock<Query> = LazyLock::new(|| {
+    compile("(instruction (operation \"lr\") . (operand) . (operand) . (operand (number)@n))")
+});
+
+pub(crate) static SEMANTIC_TOKENS: LazyLock<Query> = LazyLock::new(|| {
+    compile(
+        "(comment) @comment
+         (instruction (operation)@keyword)
+         (logictype)@string
+         (device)@preproc
+         (register)@macro
+         (number)@float
+         (preproc_string)@string
+         (identifier)@variable",
+    )
+});
+
+pub(crate) static SYMBOLS: LazyLock<Query> = LazyLock::new(|| {
+    compile(
+        "(instruction (operation \"define\") . (operand)@name)@define
+         (instruction (operation \"alias\") . (operand)@name)@alias
+         (instruction (operation \"label\") . (operand)@name)@alias
+         (label (identifier)@name)@label",
+    )
+});
+
+pub(crate) static DEFINITIONS: LazyLock<Query> = LazyLock::new(|| {
+    compile(
+        "(instruction (operation \"define\"))@define
+         (instruction (operation \"alias\"))@alias
+         (instruction (operation \"label\"))@alias
+         (label (identifier)@label)",
+    )
+});
+
+/// Compiles all queries, so the first request does not have to.
+pub(crate) fn init() {
+    for query in [
+        &LINES,
+        &INSTRUCTIONS,
+        &OPERATIONS,
+        &IDENTIFIERS,
+        &NUMBERS,
+        &COMMENTS,
+        &NEWLINES,
+        &PREPROC_STRINGS,
+        &REGISTERS_AND_DEVICES,
+        &HASH_STRINGS,
+        &SYNTAX_ERRORS,
+        &INVALID_INSTRUCTIONS,
+        &NUMBER_OPERANDS,
+        &LAST_NUMBER_OPERANDS,
+        &REAGENT_MODE_NUMBERS,
+        &SEMANTIC_TOKENS,
+        &SYMBOLS,
+        &DEFINITIONS,
+    ] {
+        LazyLock::force(query);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queries_compile() {
+        init();
+    }
+
+    #[test]
+    fn queries_are_compiled_once() {
+        let first: &'static Query = &LAST_NUMBER_OPERANDS;
+        let second = std::thread::spawn(|| &*LAST_NUMBER_OPERANDS as *const Query as usize)
+            .join()
+            .unwrap();
+        assert!(std::ptr::eq(first, &*LAST_NUMBER_OPERANDS));
+        assert_eq!(first as *const Query as usize, second);
+    }
+}